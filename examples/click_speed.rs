@@ -0,0 +1,41 @@
+//! Times generating clicks with the f32 fast path against the f64 reference.
+//!
+//! `cargo run --release --example click_speed`
+
+use std::time::{Duration, Instant};
+
+use metronome::click::{generate_click, generate_click_f64, ClickShape, Waveform};
+
+const SAMPLE_RATE: usize = 48000;
+/// Roughly what regenerating the sounds of a pattern takes, repeated to get measurable times
+const CLICKS: usize = 1000;
+
+fn time(generate: impl Fn(f32, ClickShape) -> Vec<f32>, shape: ClickShape) -> Duration {
+    let started = Instant::now();
+    for i in 0..CLICKS {
+        // Keeps the samples, and so the work, from being optimized away
+        std::hint::black_box(generate(440.0 + i as f32, shape));
+    }
+    started.elapsed()
+}
+
+fn main() {
+    for waveform in Waveform::ALL {
+        let shape = ClickShape {
+            waveform,
+            ..ClickShape::default()
+        };
+        let fast = time(|freq, s| generate_click(SAMPLE_RATE, freq, 1.0, s), shape);
+        let reference = time(
+            |freq, s| generate_click_f64(SAMPLE_RATE, freq, 1.0, s),
+            shape,
+        );
+        println!(
+            "{:>12}: f32 {:>8.2?}, f64 {:>8.2?} ({:.1}x)",
+            waveform.name(),
+            fast / CLICKS as u32,
+            reference / CLICKS as u32,
+            reference.as_secs_f64() / fast.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! Renders 8 bars of a 4/4 click at 120 BPM to a WAV file, without any audio device.
+//!
+//! `cargo run --example render_to_wav [output.wav]`
+
+use std::{path::PathBuf, sync::Arc};
+
+use metronome::click::{generate_click, ClickShape};
+use metronome::player::{render_offline, Playback};
+use metronome::wav::write_wav;
+
+const SAMPLE_RATE: usize = 44100;
+const BPM: f64 = 120.0;
+const BARS: usize = 8;
+
+fn main() -> anyhow::Result<()> {
+    let path = PathBuf::from(
+        std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "click.wav".to_string()),
+    );
+
+    let click = |freq| {
+        Arc::new(generate_click(
+            SAMPLE_RATE,
+            freq,
+            1.0,
+            ClickShape::default(),
+        ))
+    };
+    let beat = SAMPLE_RATE as f64 * 60.0 / BPM;
+    let bar = beat * 4.0;
+
+    // An accented downbeat and three plain beats, each looping with the bar
+    let playbacks = (0..4)
+        .map(|i| {
+            let freq = if i == 0 { 880.0 } else { 440.0 };
+            Playback::new(click(freq))
+                .offset((i as f64 * beat).round() as usize)
+                .repeat_exact(bar, Some(BARS - 1))
+        })
+        .collect();
+
+    let output = render_offline(playbacks, SAMPLE_RATE, 1, (bar * BARS as f64) as usize);
+    write_wav(&path, &output, SAMPLE_RATE, 1)?;
+    println!("Wrote {} bars to {}", BARS, path.display());
+    Ok(())
+}
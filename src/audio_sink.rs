@@ -0,0 +1,351 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Fills an interleaved buffer with the next frames.
+/// The duration is how long until the first frame of the buffer is heard.
+pub type RenderCallback = Box<dyn FnMut(&mut [f32], Duration) + Send>;
+
+/// An audio output the `Player` renders into
+pub trait AudioSink {
+    fn sample_rate(&self) -> usize;
+
+    fn num_channels(&self) -> usize;
+
+    /// The fixed buffer size in frames, or None if the backend picks it
+    fn buffer_size(&self) -> Option<u32> {
+        None
+    }
+
+    /// Starts requesting audio from `render`, usually from a separate audio thread
+    fn play(&mut self, render: RenderCallback) -> anyhow::Result<()>;
+
+    /// False once the output failed, e.g. because the device was unplugged
+    fn is_connected(&self) -> bool {
+        true
+    }
+
+    /// Tries to restart a failed output, continuing with the same render callback
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// An output device via cpal
+pub struct CpalSink {
+    device: cpal::Device,
+    /// The device asked for, reconnected to if it comes back
+    device_name: String,
+    config: cpal::SupportedStreamConfig,
+    buffer_size: cpal::BufferSize,
+    stream: Option<cpal::Stream>,
+    /// Shared so a failed attempt with a fixed buffer size can be retried with the default,
+    /// and a new stream can pick up where a failed one stopped
+    render: Option<Arc<Mutex<RenderCallback>>>,
+    /// Set by the stream's error callback
+    failed: Arc<AtomicBool>,
+}
+
+impl CpalSink {
+    /// Names of the output devices available to `open`
+    pub fn output_devices() -> anyhow::Result<Vec<String>> {
+        Ok(cpal::default_host()
+            .output_devices()?
+            .filter_map(|d| d.name().ok())
+            .collect())
+    }
+
+    /// Opens the output device called `device_name` or the default one, optionally requesting
+    /// a fixed buffer size in frames and a channel count other than the device's default.
+    /// Unsupported requests fall back to the defaults.
+    pub fn open(
+        device_name: Option<&str>,
+        buffer_size: Option<u32>,
+        channels: Option<u16>,
+    ) -> anyhow::Result<CpalSink> {
+        let host = cpal::default_host();
+        let named = device_name.and_then(|name| {
+            let found = host
+                .output_devices()
+                .ok()?
+                .find(|d| d.name().is_ok_and(|n| n == name));
+            if found.is_none() {
+                eprintln!("No output device called '{}', using the default", name);
+            }
+            found
+        });
+        let device = named
+            .or_else(|| host.default_output_device())
+            .ok_or(anyhow::anyhow!("No output device available"))?;
+        let device_name = device.name()?;
+        println!("Using output device: {}", device_name);
+
+        let config = output_config(&device, channels)?;
+        println!("Using output config: {:?}", config);
+
+        Ok(CpalSink {
+            device,
+            device_name,
+            config,
+            buffer_size: match buffer_size {
+                Some(size) => cpal::BufferSize::Fixed(size),
+                None => cpal::BufferSize::Default,
+            },
+            stream: None,
+            render: None,
+            failed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Opens a stream on `device` in this sink's sample format, rendering with `self.render`
+    fn build(
+        &self,
+        device: &cpal::Device,
+        stream_config: &cpal::StreamConfig,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError> {
+        let Some(render) = self.render.clone() else {
+            return Err(cpal::BuildStreamError::InvalidArgument);
+        };
+        let failed = self.failed.clone();
+        match self.config.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(device, stream_config, render, failed),
+            cpal::SampleFormat::F64 => build_stream::<f64>(device, stream_config, render, failed),
+            cpal::SampleFormat::I8 => build_stream::<i8>(device, stream_config, render, failed),
+            cpal::SampleFormat::I16 => build_stream::<i16>(device, stream_config, render, failed),
+            cpal::SampleFormat::I32 => build_stream::<i32>(device, stream_config, render, failed),
+            cpal::SampleFormat::I64 => build_stream::<i64>(device, stream_config, render, failed),
+            cpal::SampleFormat::U8 => build_stream::<u8>(device, stream_config, render, failed),
+            cpal::SampleFormat::U16 => build_stream::<u16>(device, stream_config, render, failed),
+            cpal::SampleFormat::U32 => build_stream::<u32>(device, stream_config, render, failed),
+            cpal::SampleFormat::U64 => build_stream::<u64>(device, stream_config, render, failed),
+            _ => Err(cpal::BuildStreamError::StreamConfigNotSupported),
+        }
+    }
+}
+
+impl CpalSink {
+    /// The format of the samples sent to the device, the mix is converted from f32 to it
+    pub fn sample_format(&self) -> cpal::SampleFormat {
+        self.config.sample_format()
+    }
+}
+
+impl AudioSink for CpalSink {
+    fn sample_rate(&self) -> usize {
+        self.config.sample_rate().0 as usize
+    }
+
+    fn num_channels(&self) -> usize {
+        self.config.channels() as usize
+    }
+
+    fn buffer_size(&self) -> Option<u32> {
+        match self.buffer_size {
+            cpal::BufferSize::Fixed(size) => Some(size),
+            cpal::BufferSize::Default => None,
+        }
+    }
+
+    fn play(&mut self, render: RenderCallback) -> anyhow::Result<()> {
+        self.render = Some(Arc::new(Mutex::new(render)));
+        let build = |stream_config: &cpal::StreamConfig| self.build(&self.device, stream_config);
+
+        let mut stream_config = self.config.config();
+        let mut built = None;
+        if let cpal::BufferSize::Fixed(size) = self.buffer_size {
+            let in_range = match self.config.buffer_size() {
+                cpal::SupportedBufferSize::Range { min, max } => (*min..=*max).contains(&size),
+                cpal::SupportedBufferSize::Unknown => true,
+            };
+
+            stream_config.buffer_size = cpal::BufferSize::Fixed(size);
+            if !in_range {
+                eprintln!(
+                    "Buffer size {} is outside the supported range {:?}, using the default",
+                    size,
+                    self.config.buffer_size()
+                );
+            } else {
+                match build(&stream_config) {
+                    Ok(s) => built = Some(s),
+                    Err(e) => eprintln!(
+                        "Failed to use buffer size {} ({}), using the default",
+                        size, e
+                    ),
+                }
+            }
+        }
+        let stream = match built {
+            Some(stream) => stream,
+            None => {
+                stream_config.buffer_size = cpal::BufferSize::Default;
+                build(&stream_config)?
+            }
+        };
+        println!("Using buffer size: {:?}", stream_config.buffer_size);
+
+        self.buffer_size = stream_config.buffer_size;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        !self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Opens the device again, or the default one if it is gone. Only succeeds with the
+    /// same sample rate and channel count, the mix was set up for those.
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        if self.is_connected() {
+            return Ok(());
+        }
+        // Some backends hold on to the device until the dead stream is dropped
+        self.stream = None;
+
+        let host = cpal::default_host();
+        let device = host
+            .output_devices()?
+            .find(|d| d.name().is_ok_and(|n| n == self.device_name))
+            .or_else(|| host.default_output_device())
+            .ok_or(anyhow::anyhow!("No output device available"))?;
+        let mut stream_config = self.config.config();
+        stream_config.buffer_size = self.buffer_size;
+        self.failed.store(false, Ordering::Relaxed);
+        match self.build(&device, &stream_config) {
+            Ok(stream) => {
+                println!("Reconnected to output device: {}", device.name()?);
+                self.device = device;
+                self.stream = Some(stream);
+                Ok(())
+            }
+            Err(e) => {
+                self.failed.store(true, Ordering::Relaxed);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Opens a stream taking samples of type `T`. `render` mixes in f32, including the
+/// limiter, and the result is converted to `T` afterwards.
+fn build_stream<T>(
+    device: &cpal::Device,
+    stream_config: &cpal::StreamConfig,
+    render: Arc<Mutex<RenderCallback>>,
+    failed: Arc<AtomicBool>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    // Only grows when the device asks for a larger buffer than before
+    let mut mix: Vec<f32> = Vec::new();
+    device.build_output_stream(
+        stream_config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            let latency = timestamp.playback.duration_since(&timestamp.callback);
+            mix.clear();
+            mix.resize(data.len(), 0.0);
+            (render.lock().unwrap())(&mut mix, latency.unwrap_or_default());
+            for (d, s) in data.iter_mut().zip(&mix) {
+                *d = T::from_sample(*s);
+            }
+        },
+        move |e| {
+            eprintln!("an error occurred on the output audio stream: {}", e);
+            // Most backends stop the stream after an error, so it is rebuilt
+            failed.store(true, Ordering::Relaxed);
+        },
+        None,
+    )
+}
+
+/// A sink without a device, the owner pulls the audio with `render`.
+/// Keeps the callback deterministic, e.g. for tests.
+pub struct NullSink {
+    sample_rate: usize,
+    num_channels: usize,
+    render: Option<RenderCallback>,
+}
+
+impl NullSink {
+    pub fn new(sample_rate: usize, num_channels: usize) -> NullSink {
+        NullSink {
+            sample_rate,
+            num_channels,
+            render: None,
+        }
+    }
+
+    /// Renders the next `frames` interleaved frames, silence if not playing yet
+    pub fn render(&mut self, frames: usize) -> Vec<f32> {
+        let mut data = vec![0.0; frames * self.num_channels];
+        if let Some(render) = &mut self.render {
+            render(&mut data, Duration::ZERO);
+        }
+        data
+    }
+}
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn play(&mut self, render: RenderCallback) -> anyhow::Result<()> {
+        self.render = Some(render);
+        Ok(())
+    }
+}
+
+/// The device's default output config, or the closest supported one with `channels` channels
+fn output_config(
+    device: &cpal::Device,
+    channels: Option<u16>,
+) -> anyhow::Result<cpal::SupportedStreamConfig> {
+    let default = device.default_output_config()?;
+    let Some(channels) = channels.filter(|&c| c != default.channels()) else {
+        return Ok(default);
+    };
+
+    // Prefer keeping the default sample rate and format, only the channel count should change
+    let sample_rate = default.sample_rate();
+    let candidates: Vec<_> = device
+        .supported_output_configs()?
+        .filter(|c| c.channels() == channels)
+        .collect();
+    let with_rate = |c: &cpal::SupportedStreamConfigRange| {
+        (c.min_sample_rate()..=c.max_sample_rate()).contains(&sample_rate)
+    };
+    let best = candidates
+        .iter()
+        .find(|c| with_rate(c) && c.sample_format() == default.sample_format())
+        .or_else(|| candidates.iter().find(|c| with_rate(c)))
+        .map(|c| c.clone().with_sample_rate(sample_rate))
+        .or_else(|| candidates.first().map(|c| c.clone().with_max_sample_rate()));
+
+    match best {
+        Some(config) => Ok(config),
+        None => {
+            let mut supported: Vec<u16> = device
+                .supported_output_configs()?
+                .map(|c| c.channels())
+                .collect();
+            supported.sort();
+            supported.dedup();
+            eprintln!(
+                "The output device doesn't support {} channels (supported: {:?}), using {}",
+                channels,
+                supported,
+                default.channels()
+            );
+            Ok(default)
+        }
+    }
+}
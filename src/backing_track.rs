@@ -0,0 +1,321 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    sync::mpsc::{self, Receiver, SyncSender, TryRecvError},
+};
+
+/// Frames decoded per chunk sent to the audio thread
+const CHUNK_FRAMES: usize = 4096;
+/// Chunks decoded ahead of playback, about a second at common sample rates
+const CHUNKS_AHEAD: usize = 16;
+/// Room for every chunk buffer there is, the queued ones plus the one the decoder fills
+/// and the one playing, so returning one never fails for a lack of space
+const RECYCLED_CHUNKS: usize = CHUNKS_AHEAD + 2;
+/// Bytes read of a `fmt ` chunk, enough for WAVE_FORMAT_EXTENSIBLE. Longer ones are
+/// skipped past instead of trusting the length for an allocation.
+const MAX_FMT_LEN: u64 = 64;
+
+/// An audio file streamed from disk by a decoder thread, resampled to the output rate.
+/// Only the decoded chunks just ahead of playback are held in memory.
+pub struct BackingTrack {
+    /// Output frame at which the track starts, set by the mixer
+    pub(crate) start: usize,
+    chunks: Receiver<Vec<f32>>,
+    /// Played chunks go back to the decoder to be refilled, so the audio thread never frees them
+    recycle: SyncSender<Vec<f32>>,
+    /// Interleaved stereo frames of the chunk being played
+    chunk: Vec<f32>,
+    pos: usize,
+    /// Whether the file has more than one channel
+    pub stereo: bool,
+}
+
+impl BackingTrack {
+    /// Opens a WAV file and starts decoding it for output at `sample_rate`
+    pub fn open(path: &Path, sample_rate: usize) -> anyhow::Result<BackingTrack> {
+        let is_wav = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+        if !is_wav {
+            // MP3 and the like need a decoder this build doesn't have
+            anyhow::bail!(
+                "Only WAV backing tracks are supported, convert {} to WAV first",
+                path.display()
+            );
+        }
+
+        let wav = WavReader::open(path)?;
+        let stereo = wav.channels > 1;
+        let (send, chunks) = mpsc::sync_channel(CHUNKS_AHEAD);
+        let (recycle, recycled) = mpsc::sync_channel(RECYCLED_CHUNKS);
+        std::thread::spawn(move || {
+            decode(wav, sample_rate, send, &recycled);
+            // Frees what the track hands back after the end of the file, until it is dropped
+            for _ in recycled {}
+        });
+
+        Ok(BackingTrack {
+            start: 0,
+            chunks,
+            recycle,
+            chunk: Vec::new(),
+            pos: 0,
+            stereo,
+        })
+    }
+
+    /// The stereo frame to play at output frame `time`, None once the track has ended.
+    /// Plays silence if the decoder falls behind.
+    pub(crate) fn next_frame(&mut self, time: usize) -> Option<(f32, f32)> {
+        if time < self.start {
+            return Some((0.0, 0.0));
+        }
+        if self.pos >= self.chunk.len() {
+            match self.chunks.try_recv() {
+                Ok(chunk) => {
+                    let played = std::mem::replace(&mut self.chunk, chunk);
+                    // Fails only if the decoder is gone too, which leaves nobody else to free it
+                    let _ = self.recycle.try_send(played);
+                    self.pos = 0;
+                }
+                Err(TryRecvError::Empty) => return Some((0.0, 0.0)),
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+        let frame = (self.chunk[self.pos], self.chunk[self.pos + 1]);
+        self.pos += 2;
+        Some(frame)
+    }
+}
+
+impl Drop for BackingTrack {
+    /// Hands the chunks back to the decoder thread, which is where they are freed
+    fn drop(&mut self) {
+        let _ = self.recycle.try_send(std::mem::take(&mut self.chunk));
+        for chunk in self.chunks.try_iter() {
+            let _ = self.recycle.try_send(chunk);
+        }
+    }
+}
+
+/// Decodes the file into stereo chunks at `sample_rate` until it ends or the track is dropped.
+/// Chunks are refilled from `recycled` when the track has handed any back.
+fn decode(
+    mut wav: WavReader,
+    sample_rate: usize,
+    send: SyncSender<Vec<f32>>,
+    recycled: &Receiver<Vec<f32>>,
+) {
+    let step = wav.sample_rate as f64 / sample_rate as f64;
+    let mut next_frame = move || match wav.next_frame() {
+        Ok(frame) => frame,
+        Err(e) => {
+            eprintln!("Failed to read backing track: {}", e);
+            None
+        }
+    };
+
+    // Linear interpolation between the source frames `a` and `b`
+    let (Some(mut a), Some(mut b)) = (next_frame(), next_frame()) else {
+        return;
+    };
+    let mut pos = 0.0f64;
+    loop {
+        let mut chunk = match recycled.try_recv() {
+            Ok(mut chunk) => {
+                chunk.clear();
+                chunk
+            }
+            Err(_) => Vec::with_capacity(CHUNK_FRAMES * 2),
+        };
+        let mut ended = false;
+        while chunk.len() < CHUNK_FRAMES * 2 {
+            while pos >= 1.0 {
+                match next_frame() {
+                    Some(frame) => (a, b) = (b, frame),
+                    None => ended = true,
+                }
+                pos -= 1.0;
+            }
+            if ended {
+                break;
+            }
+            let t = pos as f32;
+            chunk.push(a.0 + (b.0 - a.0) * t);
+            chunk.push(a.1 + (b.1 - a.1) * t);
+            pos += step;
+        }
+
+        // Sending fails once the track is stopped
+        if (!chunk.is_empty() && send.send(chunk).is_err()) || ended {
+            return;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SampleFormat {
+    U8,
+    I16,
+    I24,
+    I32,
+    F32,
+}
+
+impl SampleFormat {
+    fn bytes(self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::I16 => 2,
+            SampleFormat::I24 => 3,
+            SampleFormat::I32 | SampleFormat::F32 => 4,
+        }
+    }
+}
+
+/// Reads the frames of a PCM or float WAV file one at a time
+pub(crate) struct WavReader {
+    reader: BufReader<File>,
+    pub(crate) channels: usize,
+    pub(crate) sample_rate: usize,
+    format: SampleFormat,
+    /// Bytes of sample data left
+    remaining: u64,
+}
+
+impl WavReader {
+    pub(crate) fn open(path: &Path) -> anyhow::Result<WavReader> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            anyhow::bail!("Not a WAV file");
+        }
+
+        let mut fmt = None;
+        loop {
+            let mut chunk_header = [0u8; 8];
+            if reader.read_exact(&mut chunk_header).is_err() {
+                anyhow::bail!("WAV file has no data chunk");
+            }
+            let len = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap()) as u64;
+            match &chunk_header[0..4] {
+                b"fmt " => {
+                    let mut data = vec![0u8; len.min(MAX_FMT_LEN) as usize];
+                    if reader.read_exact(&mut data).is_err() {
+                        anyhow::bail!("Truncated WAV format chunk");
+                    }
+                    fmt = Some(parse_fmt(&data)?);
+                    reader.seek(SeekFrom::Current((len - data.len() as u64) as i64))?;
+                }
+                b"data" => {
+                    let Some((channels, sample_rate, format)) = fmt else {
+                        anyhow::bail!("WAV file has no format chunk before its data");
+                    };
+                    // A truncated file, or one written while streaming, plays what it has
+                    let left_in_file = file_len.saturating_sub(reader.stream_position()?);
+                    return Ok(WavReader {
+                        reader,
+                        channels,
+                        sample_rate,
+                        format,
+                        remaining: len.min(left_in_file),
+                    });
+                }
+                _ => {
+                    reader.seek(SeekFrom::Current(len as i64))?;
+                }
+            }
+            // Chunks are padded to an even length
+            if len % 2 == 1 {
+                reader.seek(SeekFrom::Current(1))?;
+            }
+        }
+    }
+
+    /// The next frame as a stereo pair, mono files play on both sides
+    fn next_frame(&mut self) -> std::io::Result<Option<(f32, f32)>> {
+        let bytes = self.format.bytes();
+        let frame_len = bytes * self.channels;
+        if self.remaining < frame_len as u64 {
+            return Ok(None);
+        }
+        self.remaining -= frame_len as u64;
+
+        let mut frame = [0u8; 4 * 2];
+        let read = frame_len.min(frame.len());
+        self.reader.read_exact(&mut frame[..read])?;
+        // Channels past the first two are dropped
+        self.reader
+            .seek(SeekFrom::Current((frame_len - read) as i64))?;
+
+        let left = decode_sample(&frame[..bytes], self.format);
+        let right = if self.channels > 1 {
+            decode_sample(&frame[bytes..bytes * 2], self.format)
+        } else {
+            left
+        };
+        Ok(Some((left, right)))
+    }
+
+    /// The next frame with all of its channels averaged
+    pub(crate) fn next_mono_frame(&mut self) -> std::io::Result<Option<f32>> {
+        let bytes = self.format.bytes();
+        let frame_len = bytes * self.channels;
+        if self.remaining < frame_len as u64 {
+            return Ok(None);
+        }
+        self.remaining -= frame_len as u64;
+
+        let mut frame = vec![0u8; frame_len];
+        self.reader.read_exact(&mut frame)?;
+        let sum: f32 = frame
+            .chunks_exact(bytes)
+            .map(|sample| decode_sample(sample, self.format))
+            .sum();
+        Ok(Some(sum / self.channels as f32))
+    }
+}
+
+/// Channel count, sample rate and format from a `fmt ` chunk
+fn parse_fmt(data: &[u8]) -> anyhow::Result<(usize, usize, SampleFormat)> {
+    if data.len() < 16 {
+        anyhow::bail!("Invalid WAV format chunk");
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+    let mut tag = u16_at(0);
+    let channels = u16_at(2) as usize;
+    let sample_rate = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+    let bits = u16_at(14);
+    // WAVE_FORMAT_EXTENSIBLE keeps the actual format in the sub-format GUID
+    if tag == 0xFFFE && data.len() >= 26 {
+        tag = u16_at(24);
+    }
+
+    let format = match (tag, bits) {
+        (1, 8) => SampleFormat::U8,
+        (1, 16) => SampleFormat::I16,
+        (1, 24) => SampleFormat::I24,
+        (1, 32) => SampleFormat::I32,
+        (3, 32) => SampleFormat::F32,
+        _ => anyhow::bail!("Unsupported WAV format {} with {} bits", tag, bits),
+    };
+    if channels == 0 || sample_rate == 0 {
+        anyhow::bail!("Invalid WAV format chunk");
+    }
+    Ok((channels, sample_rate, format))
+}
+
+fn decode_sample(b: &[u8], format: SampleFormat) -> f32 {
+    match format {
+        SampleFormat::U8 => (b[0] as f32 - 128.0) / 128.0,
+        SampleFormat::I16 => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+        SampleFormat::I24 => i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0,
+        SampleFormat::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+        SampleFormat::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+    }
+}
@@ -0,0 +1,39 @@
+/// Draws the beat of the bar as a large number on a display-like panel, "1" on the downbeat.
+/// `beats` is the (fractional) number of beats since the pattern started, None while stopped.
+pub fn beat_counter(ui: &mut egui::Ui, beats: Option<f64>, beats_per_bar: usize, size: f32) {
+    let digits = beats_per_bar.max(1).to_string().len();
+    let font = egui::FontId::monospace(size);
+    let width = size * 0.65 * digits as f32 + size * 0.4;
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(width, size * 1.2), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let visuals = ui.visuals();
+    painter.rect_filled(rect, size * 0.1, visuals.extreme_bg_color);
+
+    // Unlit segments behind the digits, like on a real display
+    painter.text(
+        rect.center(),
+        egui::Align2::CENTER_CENTER,
+        "8".repeat(digits),
+        font.clone(),
+        visuals.widgets.inactive.bg_fill,
+    );
+
+    // Counted in is before the first downbeat, nothing to show yet
+    let beat = beats
+        .filter(|&b| b >= 0.0)
+        .map(|b| b.floor() as usize % beats_per_bar.max(1));
+    if let Some(beat) = beat {
+        let color = if beat == 0 {
+            visuals.selection.bg_fill
+        } else {
+            visuals.strong_text_color()
+        };
+        painter.text(
+            rect.center(),
+            egui::Align2::CENTER_CENTER,
+            format!("{:>width$}", beat + 1, width = digits),
+            font,
+            color,
+        );
+    }
+}
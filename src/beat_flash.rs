@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Which beats the visual indicators light up on. The clicks play on every beat regardless.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum FlashBeats {
+    #[default]
+    Every,
+    /// Only the downbeat, to find the bar without a light blinking all the time
+    Downbeat,
+}
+
+impl FlashBeats {
+    pub const ALL: [FlashBeats; 2] = [FlashBeats::Every, FlashBeats::Downbeat];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FlashBeats::Every => "every beat",
+            FlashBeats::Downbeat => "downbeats only",
+        }
+    }
+
+    fn includes(self, beat_in_bar: usize) -> bool {
+        self == FlashBeats::Every || beat_in_bar == 0
+    }
+}
+
+/// Draws a light that flashes on the beat, in a different color on the downbeat, for
+/// `duration_ms` or at most one beat. `beats` is the (fractional) number of beats since the
+/// pattern started, None while stopped, and `beat_secs` the length of a beat.
+#[cfg(feature = "gui")]
+pub fn beat_flash(
+    ui: &mut egui::Ui,
+    beats: Option<f64>,
+    beats_per_bar: usize,
+    beat_secs: f64,
+    flash_beats: FlashBeats,
+    duration_ms: u32,
+    size: f32,
+) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+
+    let beat_in_bar = |beats: f64| beats as usize % beats_per_bar.max(1);
+    let color = match beats {
+        Some(beats)
+            if beats >= 0.0
+                && beats.fract() * beat_secs * 1000.0 < duration_ms as f64
+                && flash_beats.includes(beat_in_bar(beats)) =>
+        {
+            if beat_in_bar(beats) == 0 {
+                egui::Color32::from_rgb(255, 140, 0)
+            } else {
+                egui::Color32::from_rgb(80, 170, 255)
+            }
+        }
+        _ => ui.visuals().faint_bg_color,
+    };
+    painter.circle_filled(rect.center(), size * 0.45, color);
+}
+
+/// The click light halves its brightness this often after a click
+const CLICK_LIGHT_HALF_LIFE_SECS: f32 = 0.04;
+
+/// Draws a light that lights up whenever a click is heard and then fades, following the
+/// clicks actually played rather than the beat grid. `since_click` is the time since the last.
+#[cfg(feature = "gui")]
+pub fn click_light(ui: &mut egui::Ui, since_click: Option<Duration>, size: f32) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+    let brightness = since_click.map_or(0.0, |t| {
+        0.5f32.powf(t.as_secs_f32() / CLICK_LIGHT_HALF_LIFE_SECS)
+    });
+    let off = egui::Rgba::from(ui.visuals().faint_bg_color);
+    let on = egui::Rgba::from(egui::Color32::from_rgb(255, 255, 255));
+    let color = off * (1.0 - brightness) + on * brightness;
+    ui.painter_at(rect)
+        .circle_filled(rect.center(), size * 0.45, egui::Color32::from(color));
+}
+
+/// Beats per second above which the window only flashes on downbeats,
+/// staying below the flash rates known to trigger seizures
+const MAX_FLASHES_PER_SECOND: f64 = 3.0;
+
+/// Flashing the whole window background on the beat, for when a small light is easy to miss
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenFlash {
+    pub enabled: bool,
+    pub downbeat_color: [u8; 3],
+    pub beat_color: [u8; 3],
+    /// How far the background is tinted towards the flash colors, from 0 to 1
+    pub intensity: f32,
+    pub duration_ms: u32,
+}
+
+impl Default for ScreenFlash {
+    fn default() -> Self {
+        ScreenFlash {
+            enabled: false,
+            downbeat_color: [255, 140, 0],
+            beat_color: [80, 170, 255],
+            intensity: 0.5,
+            duration_ms: 100,
+        }
+    }
+}
+
+#[cfg(feature = "gui")]
+impl ScreenFlash {
+    /// The window background for the current position, `beat_secs` being the length of a beat
+    pub fn background(
+        &self,
+        base: egui::Color32,
+        beats: Option<f64>,
+        beats_per_bar: usize,
+        beat_secs: f64,
+        flash_beats: FlashBeats,
+    ) -> egui::Color32 {
+        let Some(beats) = beats.filter(|&b| self.enabled && b >= 0.0 && beat_secs > 0.0) else {
+            return base;
+        };
+        if beats.fract() * beat_secs * 1000.0 >= self.duration_ms as f64 {
+            return base;
+        }
+
+        let beat_in_bar = beats as usize % beats_per_bar.max(1);
+        let [r, g, b] = if beat_in_bar == 0 {
+            self.downbeat_color
+        } else if flash_beats.includes(beat_in_bar) && beat_secs * MAX_FLASHES_PER_SECOND >= 1.0 {
+            self.beat_color
+        } else {
+            return base;
+        };
+        let t = self.intensity.clamp(0.0, 1.0);
+        let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+        egui::Color32::from_rgb(mix(base.r(), r), mix(base.g(), g), mix(base.b(), b))
+    }
+}
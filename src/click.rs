@@ -0,0 +1,467 @@
+use std::{collections::HashMap, f64::consts::TAU, sync::Arc, time::Duration};
+
+/// Amplitude envelope shape of a click
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Envelope {
+    /// Starts at full volume and decays exponentially
+    Decay,
+    /// Reverse of `Decay`, swelling up to full volume before a short release
+    Swell,
+    /// Constant volume with short fades at both ends
+    Gate,
+    /// Short attack followed by a steep exponential decay
+    Percussive,
+}
+impl Envelope {
+    pub const ALL: [Envelope; 4] = [
+        Envelope::Decay,
+        Envelope::Swell,
+        Envelope::Gate,
+        Envelope::Percussive,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Envelope::Decay => "decay",
+            Envelope::Swell => "swell",
+            Envelope::Gate => "gate",
+            Envelope::Percussive => "percussive",
+        }
+    }
+}
+
+/// Oscillator a click is generated from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Waveform {
+    Sine,
+    /// Brighter than the sine, with all the odd harmonics
+    Square,
+    Triangle,
+    /// White noise band-passed around the click's frequency, cuts through a loud band
+    Noise,
+}
+impl Waveform {
+    pub const ALL: [Waveform; 4] = [
+        Waveform::Sine,
+        Waveform::Square,
+        Waveform::Triangle,
+        Waveform::Noise,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Sine => "sine",
+            Waveform::Square => "square",
+            Waveform::Triangle => "triangle",
+            Waveform::Noise => "noise burst",
+        }
+    }
+}
+
+/// Clicks shorter than this are raised to it, so there is always something to play
+pub const MIN_CLICK_LENGTH: Duration = Duration::from_millis(5);
+
+/// How a click is synthesized, apart from its pitch and level
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClickShape {
+    /// Length of a tone click, the other timbres scale along with it
+    pub length: Duration,
+    pub envelope: Envelope,
+    /// How far below the start the decaying envelopes end up, in dB
+    pub decay_db: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for ClickShape {
+    fn default() -> Self {
+        ClickShape {
+            length: Duration::from_millis(100),
+            envelope: Envelope::Decay,
+            decay_db: 40.0,
+            waveform: Waveform::Sine,
+        }
+    }
+}
+
+pub fn generate_click(sample_rate: usize, freq: f32, gain: f32, shape: ClickShape) -> Vec<f32> {
+    let length = shape.length.max(MIN_CLICK_LENGTH);
+    let n = ((length.as_secs_f64() * sample_rate as f64) as usize).max(1);
+    let mut result = Vec::with_capacity(n);
+
+    // Track the phase incrementally and wrap it, so f32 precision holds up for long buffers
+    let phase_step = (TAU * freq as f64 / sample_rate as f64) as f32;
+    let mut phase = 0.0f32;
+    // One-pole low-passes an octave below and above the frequency, their difference is the band
+    let smoothing = |f: f32| 1.0 - (-std::f32::consts::TAU * f / sample_rate as f32).exp();
+    let (low_smoothing, high_smoothing) = (smoothing(freq / 2.0), smoothing(freq * 2.0));
+    let (mut low, mut high) = (0.0f32, 0.0f32);
+    let mut noise = NoiseGenerator(0x2545F491);
+
+    for _ in 0..n {
+        // Position within the cycle from 0 to 1
+        let cycle = phase / TAU as f32;
+        result.push(match shape.waveform {
+            Waveform::Sine => phase.sin(),
+            Waveform::Square => {
+                if cycle < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            // Starts at zero rising, in phase with the sine
+            Waveform::Triangle => 4.0 * ((cycle + 0.75).fract() - 0.5).abs() - 1.0,
+            Waveform::Noise => {
+                let white = noise.next();
+                low += low_smoothing * (white - low);
+                high += high_smoothing * (white - high);
+                // The band keeps about a third of the noise's level, brought back up to full scale
+                (3.0 * (high - low)).clamp(-1.0, 1.0)
+            }
+        });
+
+        phase += phase_step;
+        if phase >= TAU as f32 {
+            phase -= TAU as f32;
+        }
+    }
+
+    apply_envelope(
+        &mut result,
+        sample_rate,
+        gain,
+        shape.envelope,
+        shape.decay_db,
+    );
+    result
+}
+
+/// The f64 reference `generate_click` is kept close to. Every sample is computed from its
+/// absolute position instead of accumulating the phase and the envelope, which is slower.
+pub fn generate_click_f64(sample_rate: usize, freq: f32, gain: f32, shape: ClickShape) -> Vec<f32> {
+    let length = shape.length.max(MIN_CLICK_LENGTH);
+    let n = ((length.as_secs_f64() * sample_rate as f64) as usize).max(1);
+    let (freq, rate) = (freq as f64, sample_rate as f64);
+
+    let smoothing = |f: f64| 1.0 - (-TAU * f / rate).exp();
+    let (low_smoothing, high_smoothing) = (smoothing(freq / 2.0), smoothing(freq * 2.0));
+    let (mut low, mut high) = (0.0f64, 0.0f64);
+    let mut noise = NoiseGenerator(0x2545F491);
+
+    let decay_db = match shape.envelope {
+        Envelope::Percussive => shape.decay_db as f64 * 1.5,
+        _ => shape.decay_db as f64,
+    };
+    let minimum_volume = 10f64.powf(-decay_db / 20.0);
+    let ramp_len = |ms: usize| usize::min(sample_rate * ms / 1000, n / 2);
+    let (fade_in, fade_out) = match shape.envelope {
+        Envelope::Decay => (0, 0),
+        Envelope::Swell => (0, ramp_len(2)),
+        Envelope::Gate => (ramp_len(1), ramp_len(1)),
+        Envelope::Percussive => (ramp_len(1), 0),
+    };
+
+    (0..n)
+        .map(|i| {
+            let cycle = (freq * i as f64 / rate).fract();
+            let oscillator = match shape.waveform {
+                Waveform::Sine => (TAU * cycle).sin(),
+                Waveform::Square => {
+                    if cycle < 0.5 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                Waveform::Triangle => 4.0 * ((cycle + 0.75).fract() - 0.5).abs() - 1.0,
+                Waveform::Noise => {
+                    let white = noise.next() as f64;
+                    low += low_smoothing * (white - low);
+                    high += high_smoothing * (white - high);
+                    (3.0 * (high - low)).clamp(-1.0, 1.0)
+                }
+            };
+
+            let progress = i as f64 / n as f64;
+            let mut level = gain as f64
+                * match shape.envelope {
+                    Envelope::Decay | Envelope::Percussive => minimum_volume.powf(progress),
+                    Envelope::Swell => minimum_volume.powf(1.0 - progress),
+                    Envelope::Gate => 1.0,
+                };
+            if i < fade_in {
+                level *= i as f64 / fade_in as f64;
+            }
+            if n - 1 - i < fade_out {
+                level *= (n - 1 - i) as f64 / fade_out as f64;
+            }
+            (level * oscillator) as f32
+        })
+        .collect()
+}
+
+/// One second of a sine that loops without a seam, for `Playback::loop_sustain`.
+/// The frequency is rounded to a whole Hz so the buffer holds a whole number of cycles.
+pub fn sustain_tone(sample_rate: usize, freq: f32, gain: f32) -> Vec<f32> {
+    let cycles = freq.round() as f64;
+    (0..sample_rate)
+        .map(|i| gain * (TAU * cycles * i as f64 / sample_rate as f64).sin() as f32)
+        .collect()
+}
+
+/// Shapes a raw oscillator buffer with the envelope, scaled by `gain`. The decaying shapes
+/// fall by `decay_db` over the buffer, the percussive one half as much again.
+pub fn apply_envelope(
+    samples: &mut [f32],
+    sample_rate: usize,
+    gain: f32,
+    shape: Envelope,
+    decay_db: f32,
+) {
+    let n = samples.len();
+
+    // The per-sample decay is very close to 1.0, so compute it in f64 to avoid the
+    // rounding error of the root compounding over the whole buffer.
+    let decay_db = match shape {
+        Envelope::Percussive => decay_db as f64 * 1.5,
+        _ => decay_db as f64,
+    };
+    let minimum_volume = 10f64.powf(-decay_db / 20.0);
+    let decay_factor = minimum_volume.powf(1.0 / n as f64) as f32;
+
+    let (mut envelope, envelope_factor) = match shape {
+        Envelope::Decay | Envelope::Percussive => (gain, decay_factor),
+        Envelope::Swell => (gain * minimum_volume as f32, 1.0 / decay_factor),
+        Envelope::Gate => (gain, 1.0),
+    };
+    for s in samples.iter_mut() {
+        *s *= envelope;
+        envelope *= envelope_factor;
+    }
+
+    // Fades so the shapes that don't start or end near silence don't pop
+    let ramp_len = |ms: usize| usize::min(sample_rate * ms / 1000, n / 2);
+    let (fade_in, fade_out) = match shape {
+        Envelope::Decay => (0, 0),
+        Envelope::Swell => (0, ramp_len(2)),
+        Envelope::Gate => (ramp_len(1), ramp_len(1)),
+        Envelope::Percussive => (ramp_len(1), 0),
+    };
+    for (i, s) in samples[..fade_in].iter_mut().enumerate() {
+        *s *= i as f32 / fade_in as f32;
+    }
+    for (i, s) in samples[n - fade_out..].iter_mut().rev().enumerate() {
+        *s *= i as f32 / fade_out as f32;
+    }
+}
+
+/// Built-in click sounds
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Timbre {
+    /// A plain click of the selected `Waveform`
+    Tone,
+    Woodblock,
+    Rim,
+    Cowbell,
+    Beep,
+}
+impl Timbre {
+    pub const ALL: [Timbre; 5] = [
+        Timbre::Tone,
+        Timbre::Woodblock,
+        Timbre::Rim,
+        Timbre::Cowbell,
+        Timbre::Beep,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Timbre::Tone => "tone",
+            Timbre::Woodblock => "woodblock",
+            Timbre::Rim => "rim",
+            Timbre::Cowbell => "cowbell",
+            Timbre::Beep => "digital beep",
+        }
+    }
+
+    /// Synthesizes the timbre, `freq` sets the pitch relative to the timbre's character
+    pub fn generate(self, sample_rate: usize, freq: f32, gain: f32, shape: ClickShape) -> Vec<f32> {
+        // Relative to the 100 ms of a tone click
+        let length = match self {
+            Timbre::Tone => 1.0,
+            Timbre::Woodblock => 0.6,
+            Timbre::Rim => 0.3,
+            Timbre::Cowbell => 2.0,
+            Timbre::Beep => 0.6,
+        };
+        let length = shape.length.max(MIN_CLICK_LENGTH).mul_f64(length);
+        let n = ((length.as_secs_f64() * sample_rate as f64) as usize).max(1);
+        let t = |i: usize| i as f32 / sample_rate as f32;
+        let sine = |f: f32, i: usize| (std::f32::consts::TAU * f * t(i)).sin();
+        let square = |f: f32, i: usize| if (f * t(i)).fract() < 0.5 { 1.0 } else { -1.0 };
+
+        let mut samples: Vec<f32> = match self {
+            Timbre::Tone => return generate_click(sample_rate, freq, gain, shape),
+            // Two inharmonic partials, the upper one dying out quickly
+            Timbre::Woodblock => (0..n)
+                .map(|i| {
+                    let fast_decay = (-t(i) * 120.0).exp();
+                    0.7 * sine(freq, i) + 0.3 * fast_decay * sine(freq * 2.76, i)
+                })
+                .collect(),
+            // A noise burst over a high ping
+            Timbre::Rim => {
+                let mut noise = NoiseGenerator(0x2545F491);
+                (0..n)
+                    .map(|i| 0.6 * noise.next() + 0.4 * sine(freq * 2.0, i))
+                    .collect()
+            }
+            // Two detuned square waves, like the classic drum machine cowbell
+            Timbre::Cowbell => {
+                let (low, high) = (freq * 0.92, freq * 1.36);
+                (0..n)
+                    .map(|i| 0.35 * (square(low, i) + square(high, i)))
+                    .collect()
+            }
+            Timbre::Beep => (0..n).map(|i| 0.5 * square(freq, i)).collect(),
+        };
+        apply_envelope(
+            &mut samples,
+            sample_rate,
+            gain,
+            shape.envelope,
+            shape.decay_db,
+        );
+        samples
+    }
+}
+
+/// A snare hit as interleaved stereo frames, for `Playback::stereo`. The noise differs
+/// between the channels, so the hit sounds wide instead of coming from the center.
+pub fn stereo_snare(sample_rate: usize, gain: f32) -> Vec<f32> {
+    let n = sample_rate * 150 / 1000;
+    let mut noise = [NoiseGenerator(0x2545F491), NoiseGenerator(0x9E3779B9)];
+    let mut samples = Vec::with_capacity(2 * n);
+    for i in 0..n {
+        let t = i as f32 / sample_rate as f32;
+        let body = 0.4 * (std::f32::consts::TAU * 180.0 * t).sin() * (-t * 40.0).exp();
+        let snares = (-t * 25.0).exp();
+        for channel in &mut noise {
+            samples.push(gain * (body + 0.6 * snares * channel.next()));
+        }
+    }
+    samples
+}
+
+/// Deterministic white noise, so generated sounds are identical on every run
+struct NoiseGenerator(u32);
+impl NoiseGenerator {
+    fn next(&mut self) -> f32 {
+        // xorshift32
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// Semitone offsets of the major scale degrees
+const MAJOR_SCALE: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// Frequency of a major scale degree (0-based, may exceed one octave) above `root`
+pub fn scale_degree_freq(root: f32, degree: usize) -> f32 {
+    let octave = (degree / 7) as i32;
+    let semitones = MAJOR_SCALE[degree % 7] + 12 * octave;
+    root * 2.0f32.powf(semitones as f32 / 12.0)
+}
+
+/// Click buffers by frequency, so changing the pattern doesn't regenerate them
+pub struct ClickCache {
+    sample_rate: usize,
+    shape: ClickShape,
+    clicks: HashMap<u32, Arc<Vec<f32>>>,
+}
+impl ClickCache {
+    pub fn new(sample_rate: usize, shape: ClickShape) -> ClickCache {
+        ClickCache {
+            sample_rate,
+            shape,
+            clicks: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, freq: f32) -> Arc<Vec<f32>> {
+        self.clicks
+            .entry(freq.to_bits())
+            .or_insert_with(|| Arc::new(generate_click(self.sample_rate, freq, 1.0, self.shape)))
+            .clone()
+    }
+}
+
+/// Playback tags of the click roles, so their samples can be swapped while playing
+pub mod tags {
+    pub const HI: u64 = 1;
+    pub const MID: u64 = 2;
+    pub const LO: u64 = 3;
+    pub const FILL: u64 = 4;
+    pub const PRE_BEAT: u64 = 5;
+    pub const REFERENCE: u64 = 6;
+    pub const TUPLET: u64 = 7;
+    pub const CROSS_ACCENT: u64 = 8;
+    pub const SUBDIVISION: u64 = 9;
+}
+
+/// The click samples the scheduler picks from
+pub struct Sounds {
+    pub hi: Arc<Vec<f32>>,
+    pub mid: Arc<Vec<f32>>,
+    pub lo: Arc<Vec<f32>>,
+    pub fill: Arc<Vec<f32>>,
+    pub pre_beat: Arc<Vec<f32>>,
+    /// The unaccented clicks subdividing a beat
+    pub tuplet: Arc<Vec<f32>>,
+    /// The accent recurring across the bar lines
+    pub cross_accent: Arc<Vec<f32>>,
+    /// The soft ticks on the shorter notes between the beats
+    pub subdivision: Arc<Vec<f32>>,
+    pub cache: ClickCache,
+}
+impl Sounds {
+    /// `timbres` are the sounds of the hi, mid and lo clicks, all of them shaped by `shape`
+    pub fn new(sample_rate: usize, shape: ClickShape, timbres: [Timbre; 3]) -> Sounds {
+        let [hi, mid, lo] = timbres;
+        let click =
+            |timbre: Timbre, freq, gain| Arc::new(timbre.generate(sample_rate, freq, gain, shape));
+        // Half as long as the beats, so it is over well before the beat it leads into
+        let pre_beat_shape = ClickShape {
+            length: shape.length / 2,
+            ..shape
+        };
+
+        Sounds {
+            hi: click(hi, 880.0, 1.0),
+            mid: click(mid, 659.25, 1.0),
+            lo: click(lo, 440.0, 1.0),
+            fill: click(hi, 1318.5, 1.0),
+            pre_beat: Arc::new(generate_click(sample_rate, 880.0, 1.0, pre_beat_shape)),
+            tuplet: click(lo, 440.0, 0.4),
+            cross_accent: click(mid, 1174.66, 0.7),
+            subdivision: click(lo, 587.33, 0.3),
+            cache: ClickCache::new(sample_rate, shape),
+        }
+    }
+
+    /// The samples of each tagged click role
+    pub fn tagged(&self) -> [(u64, Arc<Vec<f32>>); 8] {
+        [
+            (tags::HI, self.hi.clone()),
+            (tags::MID, self.mid.clone()),
+            (tags::LO, self.lo.clone()),
+            (tags::FILL, self.fill.clone()),
+            (tags::PRE_BEAT, self.pre_beat.clone()),
+            (tags::TUPLET, self.tuplet.clone()),
+            (tags::CROSS_ACCENT, self.cross_accent.clone()),
+            (tags::SUBDIVISION, self.subdivision.clone()),
+        ]
+    }
+}
@@ -0,0 +1,247 @@
+use std::{collections::HashMap, ops::RangeInclusive, path::PathBuf};
+
+use metronome::pattern::AccentLevel;
+use metronome::player::{DEFAULT_MAX_VOICES, DEFAULT_VOLUME_RANGE_DB};
+use serde::{Deserialize, Serialize};
+
+use crate::beat_flash::{FlashBeats, ScreenFlash};
+use crate::midi_input::Trigger;
+use crate::midi_output::FeedbackMapping;
+
+/// User settings persisted between runs
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Multiplier applied to all text sizes
+    pub ui_scale: f32,
+    pub dark_mode: bool,
+    /// Show a swinging pendulum following the beat
+    pub pendulum: bool,
+    /// Show upcoming clicks on a scrolling timeline
+    pub timeline: bool,
+    /// Show the beat of the bar as a large number
+    pub beat_counter: bool,
+    /// Show a light flashing on every click as it is heard
+    pub click_light: bool,
+    pub screen_flash: ScreenFlash,
+    /// Beats the beat light and the window flash light up on
+    pub flash_beats: FlashBeats,
+    /// How long the beat light stays lit
+    pub beat_light_ms: u32,
+    /// Show only the tempo, play/stop and a beat flash
+    pub mini_mode: bool,
+    /// Address to serve the HTTP remote control on, e.g. "127.0.0.1:8080". Disabled if unset.
+    pub remote_address: Option<String>,
+    /// Number of output channels to open the device with, instead of its default
+    pub output_channels: Option<u16>,
+    /// Mix down to a single channel, e.g. for a mono PA feed. Opens a mono stream if the
+    /// device has one, otherwise plays the same signal on all of its channels.
+    pub mono_output: bool,
+    /// Attenuate clicks starting together, so dense patterns don't clip
+    pub overlap_protection: bool,
+    /// Clicks sounding at once, the oldest ones beyond it are cut off
+    pub max_voices: usize,
+    /// Pass the output through linearly instead of soft clipping it, for measurements
+    pub bypass_limiter: bool,
+    /// Window geometry when the app was last used
+    pub window: Option<WindowGeometry>,
+    /// Startup settings, overridden by environment variables and command line arguments
+    pub bpm: Option<f32>,
+    pub numerator: Option<usize>,
+    pub subdivision: Option<usize>,
+    pub volume_db: Option<f32>,
+    /// Bounds of the volume control
+    pub min_volume_db: f32,
+    pub max_volume_db: f32,
+    /// Name of the output device, the system default if unset
+    pub device: Option<String>,
+    /// MIDI input port to listen to, and the messages mapped to actions
+    pub midi_input: Option<String>,
+    pub midi_start_stop: Option<Trigger>,
+    pub midi_tap: Option<Trigger>,
+    /// Wait for the next bar of the MIDI clock the input sends before starting
+    pub midi_clock_start: bool,
+    /// Bars of clicks on every beat played before the pattern when starting, 0 to 2
+    pub count_in_bars: usize,
+    /// MIDI output port to report tempo and beat position to, and the messages used
+    pub midi_feedback_output: Option<String>,
+    pub midi_feedback: FeedbackMapping,
+    /// Measured delay from a click being scheduled to a tap along with it, taken out of taps
+    pub latency_offset_ms: f32,
+    /// Accent pattern per time signature, keyed like "7/8"
+    pub accents: HashMap<String, Vec<AccentLevel>>,
+    /// Muted beats per time signature, keyed like `accents`
+    pub muted_beats: HashMap<String, Vec<bool>>,
+    /// Saved setups in the order they were added, the first nine recalled with the number keys
+    pub presets: Vec<Preset>,
+}
+
+/// Tempo, meter, accents and volume saved under a name
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub bpm: f32,
+    pub numerator: usize,
+    pub subdivision: usize,
+    pub accents: Vec<AccentLevel>,
+    pub volume_db: f32,
+}
+
+/// Outer position and inner size of the window in logical pixels
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl WindowGeometry {
+    /// Rejects geometry that is corrupt or has the window almost entirely off the desktop.
+    /// The monitor size isn't known before the window opens, so only the top left is checked here.
+    pub fn is_plausible(&self) -> bool {
+        let values = [self.x, self.y, self.width, self.height];
+        values.iter().all(|v| v.is_finite())
+            && (100.0..=16384.0).contains(&self.width)
+            && (100.0..=16384.0).contains(&self.height)
+            && self.x > 50.0 - self.width
+            && self.y >= 0.0
+            && self.x < 16384.0
+            && self.y < 16384.0
+    }
+}
+
+/// Key of a time signature in the per-meter settings, like "7/8"
+fn meter_key(numerator: usize, subdivision: usize) -> String {
+    format!("{}/{}", numerator, subdivision)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ui_scale: 4.0,
+            dark_mode: true,
+            pendulum: false,
+            beat_counter: false,
+            click_light: false,
+            timeline: false,
+            screen_flash: ScreenFlash::default(),
+            flash_beats: FlashBeats::default(),
+            beat_light_ms: 100,
+            mini_mode: false,
+            remote_address: None,
+            output_channels: None,
+            mono_output: false,
+            overlap_protection: false,
+            max_voices: DEFAULT_MAX_VOICES,
+            bypass_limiter: false,
+            window: None,
+            bpm: None,
+            numerator: None,
+            subdivision: None,
+            volume_db: None,
+            min_volume_db: *DEFAULT_VOLUME_RANGE_DB.start(),
+            max_volume_db: *DEFAULT_VOLUME_RANGE_DB.end(),
+            device: None,
+            midi_input: None,
+            midi_start_stop: None,
+            midi_tap: None,
+            midi_clock_start: false,
+            count_in_bars: 0,
+            midi_feedback_output: None,
+            midi_feedback: FeedbackMapping::default(),
+            latency_offset_ms: 0.0,
+            accents: HashMap::new(),
+            muted_beats: HashMap::new(),
+            presets: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        directories::ProjectDirs::from("", "", "metronome")
+            .map(|dirs| dirs.config_dir().join("config.toml"))
+    }
+
+    /// Loads the config file, falling back to the defaults if it is missing or malformed
+    pub fn load() -> Config {
+        let Some(path) = Self::path() else {
+            return Config::default();
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+                eprintln!("Ignoring malformed config {}: {}", path.display(), e);
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        }
+    }
+
+    /// Sets the startup settings, returning whether any of them changed
+    pub fn set_startup(
+        &mut self,
+        bpm: f32,
+        numerator: usize,
+        subdivision: usize,
+        volume_db: f32,
+    ) -> bool {
+        let startup = (
+            Some(bpm),
+            Some(numerator),
+            Some(subdivision),
+            Some(volume_db),
+        );
+        let changed = (self.bpm, self.numerator, self.subdivision, self.volume_db) != startup;
+        (self.bpm, self.numerator, self.subdivision, self.volume_db) = startup;
+        changed
+    }
+
+    /// The saved accent pattern of a time signature, or the default one
+    pub fn accents_for(&self, numerator: usize, subdivision: usize) -> Vec<AccentLevel> {
+        self.accents
+            .get(&meter_key(numerator, subdivision))
+            .filter(|accents| accents.len() == numerator)
+            .cloned()
+            .unwrap_or_else(|| AccentLevel::default_pattern(numerator))
+    }
+
+    pub fn set_accents(&mut self, numerator: usize, subdivision: usize, accents: &[AccentLevel]) {
+        self.accents
+            .insert(meter_key(numerator, subdivision), accents.to_vec());
+    }
+
+    /// The saved muted beats of a time signature, none by default
+    pub fn muted_for(&self, numerator: usize, subdivision: usize) -> Vec<bool> {
+        self.muted_beats
+            .get(&meter_key(numerator, subdivision))
+            .filter(|muted| muted.len() == numerator)
+            .cloned()
+            .unwrap_or_else(|| vec![false; numerator])
+    }
+
+    pub fn set_muted(&mut self, numerator: usize, subdivision: usize, muted: &[bool]) {
+        self.muted_beats
+            .insert(meter_key(numerator, subdivision), muted.to_vec());
+    }
+
+    /// The configured volume bounds, or the defaults if they don't make sense
+    pub fn volume_range_db(&self) -> RangeInclusive<f32> {
+        let (min, max) = (self.min_volume_db, self.max_volume_db);
+        if min.is_finite() && max.is_finite() && min < max {
+            min..=max
+        } else {
+            eprintln!("Ignoring invalid volume range {}..={} dB", min, max);
+            DEFAULT_VOLUME_RANGE_DB
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = Self::path().ok_or(anyhow::anyhow!("No config directory available"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
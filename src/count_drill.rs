@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+/// Practice drill: a few bars of clicks, a few silent bars, then a single reference downbeat.
+/// Tapping the beats through the silence shows how well the tempo was kept.
+pub struct CountDrill {
+    pub play_bars: usize,
+    pub silent_bars: usize,
+    run: Option<Run>,
+}
+
+struct Run {
+    start: Instant,
+    beat: Duration,
+    /// Beats from the start where the silence begins and where the reference click is
+    first_silent_beat: usize,
+    reference_beat: usize,
+    /// Signed distance of each tap from its nearest beat, positive when late
+    offsets_ms: Vec<f64>,
+}
+
+pub struct DrillStats {
+    pub taps: usize,
+    /// Average offset from the beat, positive when late
+    pub mean_ms: f64,
+    /// Standard deviation of the offsets
+    pub spread_ms: f64,
+}
+
+impl CountDrill {
+    pub fn new() -> CountDrill {
+        CountDrill {
+            play_bars: 2,
+            silent_bars: 2,
+            run: None,
+        }
+    }
+
+    /// Starts measuring a drill whose first click is heard at `start`
+    pub fn start(&mut self, start: Instant, beat: Duration, beats_per_bar: usize) {
+        self.run = Some(Run {
+            start,
+            beat,
+            first_silent_beat: self.play_bars * beats_per_bar,
+            reference_beat: (self.play_bars + self.silent_bars) * beats_per_bar,
+            offsets_ms: Vec::new(),
+        });
+    }
+
+    pub fn cancel(&mut self) {
+        self.run = None;
+    }
+
+    /// Whether taps are still being recorded, up to one beat past the reference click
+    pub fn is_running(&self) -> bool {
+        self.run
+            .as_ref()
+            .is_some_and(|run| run.start.elapsed() < run.beat * (run.reference_beat as u32 + 1))
+    }
+
+    /// Records a tap, ignoring taps outside the silence and the reference click
+    pub fn tap(&mut self, at: Instant) {
+        let Some(run) = self.run.as_mut() else {
+            return;
+        };
+        let Some(since_start) = at.checked_duration_since(run.start) else {
+            return;
+        };
+
+        let beats = since_start.as_secs_f64() / run.beat.as_secs_f64();
+        let nearest = beats.round();
+        if (run.first_silent_beat as f64..=run.reference_beat as f64).contains(&nearest) {
+            run.offsets_ms
+                .push((beats - nearest) * run.beat.as_secs_f64() * 1000.0);
+        }
+    }
+
+    /// Results of the current or last drill, once there is at least one tap
+    pub fn stats(&self) -> Option<DrillStats> {
+        let offsets = &self.run.as_ref()?.offsets_ms;
+        if offsets.is_empty() {
+            return None;
+        }
+        let n = offsets.len() as f64;
+        let mean_ms = offsets.iter().sum::<f64>() / n;
+        let variance = offsets.iter().map(|o| (o - mean_ms).powi(2)).sum::<f64>() / n;
+        Some(DrillStats {
+            taps: offsets.len(),
+            mean_ms,
+            spread_ms: variance.sqrt(),
+        })
+    }
+}
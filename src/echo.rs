@@ -0,0 +1,79 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::player::Playback;
+
+/// Echo taps quieter than this are not scheduled
+const MIN_ECHO_GAIN: f32 = 0.05;
+/// Upper bound on scheduled echo taps per click, regardless of feedback
+const MAX_ECHO_TAPS: usize = 8;
+/// Feedback is capped below 1.0 so the echoes always die out
+pub const MAX_FEEDBACK: f32 = 0.9;
+
+/// Echo delay time as a note value
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EchoDelay {
+    Eighth,
+    DottedEighth,
+    Quarter,
+    DottedQuarter,
+}
+impl EchoDelay {
+    pub const ALL: [EchoDelay; 4] = [
+        EchoDelay::Eighth,
+        EchoDelay::DottedEighth,
+        EchoDelay::Quarter,
+        EchoDelay::DottedQuarter,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EchoDelay::Eighth => "eighth",
+            EchoDelay::DottedEighth => "dotted eighth",
+            EchoDelay::Quarter => "quarter",
+            EchoDelay::DottedQuarter => "dotted quarter",
+        }
+    }
+
+    /// Length of the delay in quarter notes
+    pub fn quarters(self) -> f32 {
+        match self {
+            EchoDelay::Eighth => 0.5,
+            EchoDelay::DottedEighth => 0.75,
+            EchoDelay::Quarter => 1.0,
+            EchoDelay::DottedQuarter => 1.5,
+        }
+    }
+}
+
+/// Schedules decaying copies of `playbacks`, each tap `delay` samples after the previous one
+/// and `feedback` times as loud.
+pub fn echo_playbacks(playbacks: &[Playback], delay: usize, feedback: f32) -> Vec<Playback> {
+    let feedback = feedback.clamp(0.0, MAX_FEEDBACK);
+
+    // Playbacks mostly share a handful of samples, so attenuate each of them only once per tap
+    let mut attenuated = HashMap::<(*const Vec<f32>, usize), Arc<Vec<f32>>>::new();
+
+    let mut echoes = Vec::new();
+    for tap in 1..=MAX_ECHO_TAPS {
+        let gain = feedback.powi(tap as i32);
+        if gain < MIN_ECHO_GAIN {
+            break;
+        }
+
+        for p in playbacks {
+            let samples = attenuated
+                .entry((Arc::as_ptr(&p.samples), tap))
+                .or_insert_with(|| Arc::new(p.samples.iter().map(|s| s * gain).collect()))
+                .clone();
+
+            echoes.push(Playback {
+                start: p.start + tap * delay,
+                samples,
+                // Attenuated copies must not be swapped for the original sample
+                tag: None,
+                ..p.clone()
+            });
+        }
+    }
+    echoes
+}
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use metronome::click::{ClickShape, Sounds, Timbre};
+
+use crate::{config::Config, open_player, Startup};
+
+/// How often the output is checked on while playing
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Plays the startup pattern without opening a window, until the process is stopped
+pub fn run(config: &Config, startup: &Startup) -> anyhow::Result<()> {
+    let mut player = open_player(config, startup.device.as_deref())?;
+    player.set_volume_db(startup.clamped_volume_db(config));
+
+    let mut sounds = Sounds::new(
+        player.sample_rate(),
+        ClickShape::default(),
+        [Timbre::Tone; 3],
+    );
+    let schedule = startup
+        .pattern(config)
+        .schedule(startup.bpm, player.sample_rate(), &mut sounds);
+    player.replace_playbacks(schedule.playbacks, schedule.bar_duration, false);
+    println!(
+        "Playing {}/{} at {} BPM, press Ctrl-C to stop",
+        startup.numerator, startup.subdivision, startup.bpm
+    );
+
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        player.keep_connected();
+    }
+}
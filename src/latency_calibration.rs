@@ -0,0 +1,103 @@
+use std::time::{Duration, Instant};
+
+/// Number of clicks played to tap along with
+pub const CLICKS: usize = 12;
+/// The first clicks only give the beat, taps on them aren't measured
+const WARM_UP_CLICKS: usize = 2;
+/// Time between the clicks
+pub const INTERVAL: Duration = Duration::from_millis(600);
+
+/// Measures how far taps along with a series of clicks land from when the clicks were
+/// scheduled to be heard. That covers output latency the driver doesn't report as well as
+/// the latency of the keyboard or MIDI input.
+pub struct LatencyCalibration {
+    run: Option<Run>,
+}
+
+struct Run {
+    start: Instant,
+    /// Signed distance of each tap from its nearest click, positive when late
+    offsets_ms: Vec<f64>,
+}
+
+pub struct Calibration {
+    pub taps: usize,
+    /// Suggested latency offset, the median of the tap offsets
+    pub offset_ms: f64,
+    /// Standard deviation of the tap offsets, large if the taps were sloppy
+    pub spread_ms: f64,
+}
+
+impl LatencyCalibration {
+    pub fn new() -> LatencyCalibration {
+        LatencyCalibration { run: None }
+    }
+
+    /// Starts measuring, the first click is heard at `start`
+    pub fn start(&mut self, start: Instant) {
+        self.run = Some(Run {
+            start,
+            offsets_ms: Vec::new(),
+        });
+    }
+
+    pub fn cancel(&mut self) {
+        self.run = None;
+    }
+
+    /// Whether taps are still being recorded, up to one interval past the last click
+    pub fn is_running(&self) -> bool {
+        self.run
+            .as_ref()
+            .is_some_and(|run| run.start.elapsed() < INTERVAL * CLICKS as u32)
+    }
+
+    pub fn tap(&mut self, at: Instant) {
+        let Some(run) = self.run.as_mut() else {
+            return;
+        };
+        let Some(since_start) = at.checked_duration_since(run.start) else {
+            return;
+        };
+
+        let clicks = since_start.as_secs_f64() / INTERVAL.as_secs_f64();
+        let nearest = clicks.round();
+        if (WARM_UP_CLICKS as f64..CLICKS as f64).contains(&nearest) {
+            run.offsets_ms
+                .push((clicks - nearest) * INTERVAL.as_secs_f64() * 1000.0);
+        }
+    }
+
+    /// The result of the current or last run, once at least half of the clicks were tapped
+    pub fn result(&self) -> Option<Calibration> {
+        let offsets = &self.run.as_ref()?.offsets_ms;
+        if offsets.len() < (CLICKS - WARM_UP_CLICKS) / 2 {
+            return None;
+        }
+        let mut sorted = offsets.clone();
+        sorted.sort_by(f64::total_cmp);
+        let offset_ms = sorted[sorted.len() / 2];
+
+        let n = offsets.len() as f64;
+        let mean = offsets.iter().sum::<f64>() / n;
+        let variance = offsets.iter().map(|o| (o - mean).powi(2)).sum::<f64>() / n;
+        Some(Calibration {
+            taps: offsets.len(),
+            offset_ms,
+            spread_ms: variance.sqrt(),
+        })
+    }
+}
+
+/// The time a tap at `at` responded to, with the latency offset taken out
+pub fn compensate(at: Instant, offset_ms: f32) -> Instant {
+    if !offset_ms.is_finite() {
+        return at;
+    }
+    let offset = Duration::from_secs_f32(offset_ms.abs() / 1000.0);
+    if offset_ms >= 0.0 {
+        at.checked_sub(offset).unwrap_or(at)
+    } else {
+        at + offset
+    }
+}
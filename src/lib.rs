@@ -0,0 +1,11 @@
+pub mod audio_sink;
+pub mod backing_track;
+pub mod click;
+pub mod echo;
+pub mod midi_file;
+pub mod monitor;
+pub mod pattern;
+pub mod player;
+pub mod samples;
+pub mod tap_tempo;
+pub mod wav;
@@ -1,187 +1,2500 @@
+// Without the window most of the app goes unused, only what the terminal player needs is kept
+#![cfg_attr(not(feature = "gui"), allow(dead_code, unused_imports))]
+
 use std::{
-    f64::consts::TAU,
+    collections::HashMap,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use beat_flash::FlashBeats;
+use config::{Config, Preset, WindowGeometry};
+use count_drill::CountDrill;
+use latency_calibration::LatencyCalibration;
+use metronome::{
+    audio_sink::{AudioSink, CpalSink, NullSink},
+    backing_track::BackingTrack,
+    click::{self, tags, ClickShape, Envelope, Sounds, Timbre, Waveform},
+    echo::{self, EchoDelay},
+    midi_file::{TempoMap, TempoMapFollower},
+    pattern::{parse_grouping, AccentLevel, Pattern, Schedule},
+    player::{self, Playback},
+    tap_tempo::{TapDivision, TapSnap, TapTempo},
+    wav,
 };
+use midi_input::MidiInput;
+use midi_output::MidiFeedback;
+use practice_streak::PracticeStreak;
+use remote::{RemoteCommand, RemoteServer, RemoteState, Transport};
+use sample_file::SampleFile;
+use sleep_timer::SleepTimer;
+use tap_meter::{Detection, TapMeter};
+#[cfg(feature = "gui")]
+use tempo_match::TempoMatch;
+use tempo_trainer::TempoTrainer;
 
-use player::Playback;
+#[cfg(feature = "gui")]
+mod beat_counter;
+mod beat_flash;
+mod config;
+mod count_drill;
+mod headless;
+mod latency_calibration;
+mod midi_clock;
+mod midi_input;
+mod midi_output;
+#[cfg(feature = "gui")]
+mod pendulum;
+mod practice_streak;
+mod remote;
+mod sample_file;
+mod sleep_timer;
+mod tap_meter;
+mod tempo_expr;
+#[cfg(feature = "gui")]
+mod tempo_match;
+mod tempo_trainer;
+#[cfg(feature = "gui")]
+mod timeline;
+#[cfg(feature = "gui")]
+mod waveform;
 
-mod player;
+/// How far a single nudge moves the phase
+const NUDGE_STEP_MS: f64 = 5.0;
+
+/// Tempos the metronome plays at, whichever way they are set
+const BPM_RANGE: RangeInclusive<f32> = 30.0..=400.0;
 
 fn main() -> anyhow::Result<()> {
-    let player = player::Player::start()?;
-
-    let hi_click = Arc::new(generate_click(
-        player.sample_rate(),
-        Duration::from_millis(100),
-        880.0,
-        1.0,
-    ));
-    let mid_click = Arc::new(generate_click(
-        player.sample_rate(),
-        Duration::from_millis(100),
-        659.25,
-        1.0,
-    ));
-    let lo_click = Arc::new(generate_click(
-        player.sample_rate(),
-        Duration::from_millis(100),
-        440.0,
-        1.0,
-    ));
-
-    let mut bpm = 120.0;
-    let mut numerator = 4;
-    let mut subdivision = 4;
+    let config = Config::load();
+    let startup = Startup::resolve(&config);
+    #[cfg(feature = "gui")]
+    if !startup.headless {
+        return run_window(config, startup);
+    }
+    headless::run(&config, &startup)
+}
+
+/// Opens the metronome's window and plays until it is closed
+#[cfg(feature = "gui")]
+fn run_window(mut config: Config, startup: Startup) -> anyhow::Result<()> {
+    let mut player = open_player(&config, startup.device.as_deref())?;
+    // The device chosen in the UI or at startup, None for the default
+    let mut output_device = startup.device.clone();
+    let mut output_devices: Vec<String> = Vec::new();
+    let mut output_device_error: Option<String> = None;
+    let volume_range = config.volume_range_db();
+    let startup_volume_db = startup.clamped_volume_db(&config);
+    player.set_volume_db(startup_volume_db);
+
+    let mut click_shape = ClickShape::default();
+    let mut timbres = [Timbre::Tone; 3];
+    let mut sounds = Sounds::new(player.sample_rate(), click_shape, timbres);
+
+    let mut bpm = startup.bpm;
+    let startup_pattern = startup.pattern(&config);
+    let mut patterns = [startup_pattern.clone(), startup_pattern];
+    // The meter each pattern's accents belong to, to restore the saved ones when it changes
+    let mut accent_meters = patterns.clone().map(|p| (p.numerator, p.subdivision));
+    let mut active_pattern = 0;
+    let mut alternate_every = 0;
     let mut tap_tempo = TapTempo::new();
-    let mut volume_db = 0.0;
+    // Typed tempo expression, and why it couldn't be applied
+    let mut tempo_text = String::new();
+    let mut tempo_text_error: Option<String> = None;
+    let mut grouping_text = String::new();
+    let mut grouping_error: Option<String> = None;
+    let mut preset_name = String::new();
+    // The last tempo ratio applied and the tempo it led to
+    let mut last_ratio: Option<(&str, f32)> = None;
+    let mut volume_db = startup_volume_db;
+    // Boosting above 0 dB needs a confirmation, unless the app was started that loud on purpose
+    let mut boost_confirmed = volume_db > 0.0;
+    let mut confirming_boost = false;
+    let mut show_debug = false;
+    // Guards tempo and meter against accidental changes while performing
+    let mut tempo_locked = false;
+    let mut midi_path = String::new();
+    let mut midi_follower: Option<TempoMapFollower> = None;
+    let mut midi_error: Option<String> = None;
+    // Accumulated in milliseconds and converted to whole samples as a total,
+    // so repeated nudges don't accumulate rounding errors
+    let mut nudge_ms = 0.0f64;
+    let mut channel_gains = vec![1.0; player.num_channels()];
+    let mut sleep_timer = SleepTimer::new();
+    let mut tempo_trainer = TempoTrainer::new();
+    let mut stopped_by_sleep_timer = false;
+    let mut playing = true;
+    // When the clicks mixed recently are heard, for the click light
+    let mut pending_clicks: Vec<Instant> = Vec::new();
+    // Mixer time of the last beat streamed to the remote clients
+    let mut last_remote_beat: Option<usize> = None;
+    let mut last_click: Option<Instant> = None;
+    // Playing, but with the player's position held where it was paused
+    let mut paused = false;
+    let mut streak = PracticeStreak::new(player.sample_rate());
+    // Window size to go back to when leaving mini mode
+    let mut full_window_size: Option<egui::Vec2> = None;
+    let mut midi_input = config.midi_input.as_deref().and_then(|port| {
+        MidiInput::connect(port)
+            .map_err(|e| eprintln!("Failed to open MIDI input: {}", e))
+            .ok()
+    });
+    let mut midi_ports: Vec<String> = Vec::new();
+    let mut midi_feedback = config.midi_feedback_output.as_deref().and_then(|port| {
+        MidiFeedback::connect(port)
+            .map_err(|e| eprintln!("Failed to open MIDI output: {}", e))
+            .ok()
+    });
+    let mut midi_output_ports: Vec<String> = Vec::new();
+    let mut midi_feedback_error: Option<String> = None;
+    let mut midi_input_error: Option<String> = None;
+    // The action the next incoming MIDI message gets mapped to
+    let mut midi_learn: Option<MidiAction> = None;
+    // Custom WAV files replacing the downbeat, accent and beat clicks
+    let mut sample_paths: [String; 3] = Default::default();
+    let mut sample_files: [Option<SampleFile>; 3] = Default::default();
+    let mut watch_samples = false;
+    let mut track_path = String::new();
+    let mut export_path = String::from("click.wav");
+    let mut export_bars = 8;
+    let mut export_result: Option<Result<String, String>> = None;
+    let mut track_volume_db = 0.0;
+    let mut track_count_in = true;
+    let mut track_error: Option<String> = None;
+    // Opened and waiting to be started together with the click
+    let mut pending_track: Option<BackingTrack> = None;
+    // Start on a wall-clock boundary, so several machines with synced clocks click together
+    let mut sync_every_secs = 10u64;
+    let mut pending_start: Option<Instant> = None;
+    // Set when starting, so the next schedule begins with the count-in
+    let mut count_in_pending = false;
+    // Play was pressed while following a MIDI clock that hasn't reached a bar line or started
+    let mut waiting_for_downbeat = false;
+    let mut count_drill = CountDrill::new();
+    let mut calibration = LatencyCalibration::new();
+    // Taps go to the tempo tuner instead of tap tempo while it is open
+    let mut tempo_match: Option<TempoMatch> = None;
+    // Taps go here instead while the meter is being tapped in, Shift marks a downbeat
+    let mut tap_meter: Option<TapMeter> = None;
+    // The playbacks and bar length last sent to the player, for the timeline
+    let mut scheduled: (Vec<Playback>, usize) = (Vec::new(), 0);
+    let mut layers: Vec<ClickLayer> = Vec::new();
+    let mut next_layer_id = player::MAIN_LAYER + 1;
+    // Id, tempo, numerator and accents of each layer as last scheduled
+    let mut last_layers: Vec<(u64, f32, usize, Vec<AccentLevel>)> = Vec::new();
+    let mut main_layer_solo = false;
+    // Bars of clicks alternating with silent ones while the pattern keeps running
+    let mut bar_gate_on = false;
+    let mut bar_gate = player::BarGate {
+        play_bars: 2,
+        mute_bars: 2,
+    };
+    // A sustained tone to tune to, independent of the pattern
+    let mut reference_on = false;
+    let mut reference_freq = 440.0f32;
+
+    let remote = config.remote_address.as_deref().and_then(|address| {
+        // The clicks start playing with the window
+        let initial = RemoteState::new(
+            Transport::Playing,
+            bpm,
+            patterns[0].numerator,
+            patterns[0].subdivision,
+            volume_db,
+        );
+        RemoteServer::start(address, initial)
+            .map_err(|e| eprintln!("Failed to start remote control on {}: {}", address, e))
+            .ok()
+    });
+
+    let mut last_state = (bpm * 2.0, patterns.clone(), active_pattern, alternate_every);
+    let mut last_sound = (click_shape, timbres);
 
-    let mut last_state = (bpm * 2.0, numerator, subdivision);
+    let saved_window = config.window.filter(|w| w.is_plausible());
+    let native_options = eframe::NativeOptions {
+        initial_window_pos: saved_window.map(|w| egui::pos2(w.x, w.y)),
+        initial_window_size: saved_window.map(|w| egui::vec2(w.width, w.height)),
+        ..Default::default()
+    };
+    // Every config change is saved once the window and the settings have stayed put for
+    // a moment, not on every frame of a drag
+    let mut config_changed: Option<Instant> = None;
+    let mut save_on_exit = SaveOnExit(None);
+    let mut first_frame = true;
 
-    eframe::run_simple_native("metronome", Default::default(), move |ctx, _frame| {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.vertical_centered(|ui| {
-                for (_, x) in ui.style_mut().text_styles.iter_mut() {
-                    x.size *= 4.0;
+    eframe::run_simple_native("metronome", native_options, move |ctx, frame| {
+        let window_info = &frame.info().window_info;
+        if let Some(position) = window_info.position {
+            let on_screen = window_info.monitor_size.is_none_or(|monitor| {
+                egui::Rect::from_min_size(egui::Pos2::ZERO, monitor)
+                    .intersects(egui::Rect::from_min_size(position, window_info.size))
+            });
+            if first_frame && !on_screen {
+                frame.set_centered();
+            } else if !window_info.minimized && !window_info.fullscreen {
+                let geometry = WindowGeometry {
+                    x: position.x,
+                    y: position.y,
+                    width: window_info.size.x,
+                    height: window_info.size.y,
+                };
+                if config.window != Some(geometry) {
+                    config.window = Some(geometry);
+                    config_changed = Some(Instant::now());
                 }
+            }
+            first_frame = false;
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::F3)) {
+            show_debug = !show_debug;
+        }
+        if show_debug {
+            egui::Window::new("Debug").show(ctx, |ui| {
+                ui.label(format!("Active playbacks: {}", player.active_playbacks()));
+                ui.label(match player.buffer_size() {
+                    Some(size) => format!("Buffer size: {} frames", size),
+                    None => "Buffer size: default".to_string(),
+                });
+            });
+            ctx.request_repaint();
+        }
 
-                ui.add(
-                    egui::DragValue::new(&mut bpm)
-                        .clamp_range(30.0..=400.0)
-                        .suffix(" BPM"),
-                );
-                if ui.button("Tap Tempo").clicked() {
-                    if let Some(tapped_bpm) = tap_tempo.tap() {
-                        bpm = tapped_bpm;
+        player.keep_connected();
+        if !player.is_connected() {
+            ctx.request_repaint_after(Duration::from_millis(500));
+        }
+
+        if key_tapped(ctx, egui::Key::Space) && playing {
+            paused = !paused;
+            player.set_playing(!paused);
+        }
+        if key_tapped(ctx, egui::Key::B) {
+            active_pattern = 1 - active_pattern;
+        }
+        // Index into the presets of the one to load this frame
+        let mut load_preset = None;
+        let number_keys = [
+            egui::Key::Num1,
+            egui::Key::Num2,
+            egui::Key::Num3,
+            egui::Key::Num4,
+            egui::Key::Num5,
+            egui::Key::Num6,
+            egui::Key::Num7,
+            egui::Key::Num8,
+            egui::Key::Num9,
+        ];
+        for (i, key) in number_keys.into_iter().enumerate() {
+            if key_tapped(ctx, key) {
+                load_preset = Some(i);
+            }
+        }
+        let mut midi_tap = false;
+        let mut midi_start_stop = false;
+        if let Some(input) = &mut midi_input {
+            for trigger in input.poll() {
+                match midi_learn.take() {
+                    Some(action) => {
+                        match action {
+                            MidiAction::StartStop => config.midi_start_stop = Some(trigger),
+                            MidiAction::TapTempo => config.midi_tap = Some(trigger),
+                        }
+                        config_changed = Some(Instant::now());
+                    }
+                    None => {
+                        midi_start_stop |= config.midi_start_stop == Some(trigger);
+                        midi_tap |= config.midi_tap == Some(trigger);
                     }
                 }
-            });
-            ui.horizontal(|ui| {
-                ui.vertical(|ui| {
-                    ui.set_width(20.0);
-                    ui.add(egui::DragValue::new(&mut numerator).clamp_range(0..=32));
-                    ui.menu_button(subdivision.to_string(), |ui| {
-                        for i in [4, 8, 16, 32] {
-                            if ui.button(i.to_string()).clicked() {
-                                subdivision = i;
+            }
+            ctx.request_repaint_after(Duration::from_millis(20));
+        }
+
+        if key_tapped(ctx, egui::Key::Enter) || midi_tap {
+            if calibration.is_running() {
+                calibration.tap(Instant::now());
+            } else if count_drill.is_running() {
+                count_drill.tap(latency_calibration::compensate(
+                    Instant::now(),
+                    config.latency_offset_ms,
+                ));
+            } else if let Some(tap_meter) = &mut tap_meter {
+                let downbeat = !midi_tap && ctx.input(|i| i.modifiers.shift);
+                tap_meter.tap(Instant::now(), downbeat);
+            } else if let Some(tempo_match) = &mut tempo_match {
+                tempo_match.taps.tap();
+            } else if !tempo_locked {
+                tap_tempo.tap();
+            }
+        }
+        if count_drill.is_running() || calibration.is_running() {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+        // Tapping only stages a tempo, so mis-taps don't jerk the running click around
+        if let Some(tapped_bpm) = tap_tempo.take_settled().filter(|_| !tempo_locked) {
+            bpm = tapped_bpm.clamp(*BPM_RANGE.start(), *BPM_RANGE.end());
+        }
+        if tap_tempo.staged().is_some() {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+
+        // Set by a remote client asking to start or stop
+        let mut remote_play = None;
+        if let Some(remote) = &remote {
+            for command in remote.commands.try_iter() {
+                match command {
+                    RemoteCommand::Play(true) if paused => {
+                        paused = false;
+                        player.set_playing(true);
+                    }
+                    RemoteCommand::Play(play) => remote_play = Some(play),
+                    RemoteCommand::Bpm(_) | RemoteCommand::Meter(..) if tempo_locked => {}
+                    RemoteCommand::Bpm(new_bpm) => {
+                        bpm = new_bpm.clamp(*BPM_RANGE.start(), *BPM_RANGE.end())
+                    }
+                    RemoteCommand::Meter(numerator, subdivision) => {
+                        patterns[active_pattern].set_meter(numerator, subdivision);
+                    }
+                    RemoteCommand::VolumeDb(db) => {
+                        let max = if boost_confirmed {
+                            *volume_range.end()
+                        } else {
+                            volume_range.end().min(0.0)
+                        };
+                        volume_db = db.clamp(*volume_range.start(), max);
+                        player.set_volume_db(volume_db);
+                        sleep_timer.cancel();
+                    }
+                }
+            }
+            // Keep polling for commands while the window is idle
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+
+        if sleep_timer.take_expired() {
+            // Restore the volume, so resuming plays at the level set in the UI
+            player.clear_playbacks();
+            player.stop_track();
+            player.set_volume_db(volume_db);
+            playing = false;
+            stopped_by_sleep_timer = true;
+        }
+        if sleep_timer.is_running() {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+        streak.update(playing, player.position_samples());
+        if playing {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+
+        let mut nudge_by = 0.0;
+        if !tempo_locked {
+            if key_tapped(ctx, egui::Key::ArrowLeft) {
+                nudge_by -= NUDGE_STEP_MS;
+            }
+            if key_tapped(ctx, egui::Key::ArrowRight) {
+                nudge_by += NUDGE_STEP_MS;
+            }
+        }
+
+        if let Some(section) = midi_follower
+            .as_ref()
+            .and_then(|f| f.current())
+            .filter(|_| !tempo_locked)
+        {
+            bpm = section.bpm.clamp(*BPM_RANGE.start(), *BPM_RANGE.end());
+            patterns[active_pattern].set_meter(section.numerator, section.denominator);
+            ctx.request_repaint_after(Duration::from_millis(10));
+        }
+
+        ctx.set_visuals(if config.dark_mode {
+            egui::Visuals::dark()
+        } else {
+            egui::Visuals::light()
+        });
+
+        // Beats since the current pattern started, for the visualizations
+        // In exact samples, a rounded beat would drift away from the clicks over time
+        let beat_duration = patterns[active_pattern].beat_duration(bpm, player.sample_rate());
+        let beats = player
+            .pattern_position()
+            .filter(|_| playing && beat_duration >= 1.0)
+            .map(|position| position as f64 / beat_duration);
+        if let Some(feedback) = &mut midi_feedback {
+            let numerator = patterns[active_pattern].numerator;
+            feedback.update(&config.midi_feedback, bpm, beats, numerator);
+            if playing {
+                ctx.request_repaint_after(Duration::from_millis(10));
+            }
+        }
+        // Drained even with the light off, so it doesn't start with a backlog
+        for event in player.beat_events().try_iter() {
+            if config.click_light {
+                pending_clicks.push(player.instant_of(event.sample_time));
+            }
+            if let Some(remote) = &remote {
+                // Of all the clicks, the ones on the beat grid are streamed, once per beat
+                let time = event.sample_time;
+                let beat = player
+                    .pattern_position_at(time)
+                    .filter(|_| beat_duration >= 1.0)
+                    .map(|position| position as f64 / beat_duration)
+                    .filter(|b| *b > -0.5 && (b - b.round()).abs() * beat_duration < 1.0);
+                if let Some(beat) = beat.filter(|_| last_remote_beat != Some(time)) {
+                    last_remote_beat = Some(time);
+                    let numerator = patterns[active_pattern].numerator.max(1);
+                    remote.beat(player.instant_of(time), beat.round() as usize % numerator);
+                }
+            }
+        }
+        let now = Instant::now();
+        if let Some(&heard) = pending_clicks.iter().filter(|&&at| at <= now).max() {
+            last_click = Some(heard);
+        }
+        pending_clicks.retain(|&at| at > now);
+        let animated = config.mini_mode
+            || config.pendulum
+            || config.timeline
+            || config.beat_counter
+            || config.click_light;
+        if playing && (animated || config.screen_flash.enabled) {
+            ctx.request_repaint();
+        }
+        let background = config.screen_flash.background(
+            ctx.style().visuals.panel_fill,
+            beats,
+            patterns[active_pattern].numerator,
+            beat_duration / player.sample_rate() as f64,
+            config.flash_beats,
+        );
+
+        let mut resize_to = None;
+        let mut toggle_playing = midi_start_stop || remote_play.is_some_and(|play| play != playing);
+        let panel_frame = egui::Frame::central_panel(&ctx.style()).fill(background);
+        egui::CentralPanel::default()
+            .frame(panel_frame)
+            .show(ctx, |ui| {
+                let mut reschedule = false;
+                let mut update_reference = false;
+                let mut samples_changed = false;
+                // Set by the ratio buttons, the new tempo starts with the next bar
+                let mut ratio_applied = false;
+                let bars = beats.map(|b| b / patterns[active_pattern].numerator.max(1) as f64);
+                // The trainer waits while the tempo is locked
+                let trainer_step = tempo_trainer.update(bars.filter(|_| !tempo_locked), bpm);
+                if let Some(next) = trainer_step {
+                    bpm = next;
+                }
+                // Another output device was picked, None for the default
+                let mut switch_device: Option<Option<String>> = None;
+                if !player.is_connected() {
+                    ui.colored_label(egui::Color32::RED, "Audio output lost, reconnecting...");
+                }
+                if config.mini_mode {
+                    ui.horizontal(|ui| {
+                        beat_flash::beat_flash(
+                            ui,
+                            beats,
+                            patterns[active_pattern].numerator,
+                            beat_duration / player.sample_rate() as f64,
+                            config.flash_beats,
+                            config.beat_light_ms,
+                            24.0,
+                        );
+                        ui.add_enabled(
+                            !tempo_locked,
+                            egui::DragValue::new(&mut bpm)
+                                .clamp_range(BPM_RANGE)
+                                .suffix(" BPM"),
+                        );
+                        let label = if playing || waiting_for_downbeat {
+                            "⏹"
+                        } else {
+                            "▶"
+                        };
+                        toggle_playing = ui.button(label).clicked();
+                        if ui.button("⛶").on_hover_text("Show all controls").clicked() {
+                            config.mini_mode = false;
+                            resize_to = Some(full_window_size.unwrap_or(egui::vec2(800.0, 600.0)));
+                        }
+                    });
+                } else {
+                    ui.vertical_centered(|ui| {
+                        for (_, x) in ui.style_mut().text_styles.iter_mut() {
+                            x.size *= config.ui_scale;
+                        }
+
+                        ui.horizontal(|ui| {
+                            let label = if playing {
+                                "⏹ Stop"
+                            } else if waiting_for_downbeat {
+                                "⏹ Cancel"
+                            } else {
+                                "▶ Play"
+                            };
+                            toggle_playing = ui.button(label).clicked();
+                            let pause_label = if paused { "⏵ Resume" } else { "⏸ Pause" };
+                            if ui
+                                .add_enabled(playing, egui::Button::new(pause_label))
+                                .on_hover_text("Space")
+                                .clicked()
+                            {
+                                paused = !paused;
+                                player.set_playing(!paused);
+                            }
+                            if ui
+                                .add(
+                                    egui::DragValue::new(&mut config.count_in_bars)
+                                        .clamp_range(0..=2)
+                                        .prefix("Count-in ")
+                                        .suffix(" bars"),
+                                )
+                                .changed()
+                            {
+                                config_changed = Some(Instant::now());
+                            }
+                            if waiting_for_downbeat {
+                                ui.label("Waiting for downbeat...");
+                            }
+                            if ui.button("Mini mode").clicked() {
+                                config.mini_mode = true;
+                                full_window_size = Some(ctx.screen_rect().size());
+                                resize_to = Some(egui::vec2(260.0, 50.0));
+                            }
+                            let minutes_seconds = |d: Duration| {
+                                format!("{}:{:02}", d.as_secs() / 60, d.as_secs() % 60)
+                            };
+                            ui.label(format!(
+                                "Streak {}, longest {}",
+                                minutes_seconds(streak.current(player.position_samples())),
+                                minutes_seconds(streak.longest())
+                            ));
+                        });
+                        let lock_label = if tempo_locked {
+                            "🔒 Locked"
+                        } else {
+                            "🔓 Lock"
+                        };
+                        ui.toggle_value(&mut tempo_locked, lock_label);
+                        ui.add_enabled_ui(!tempo_locked, |ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut bpm)
+                                    .clamp_range(BPM_RANGE)
+                                    .suffix(" BPM"),
+                            );
+                            ui.horizontal(|ui| {
+                                let response = ui.add(
+                                    egui::TextEdit::singleline(&mut tempo_text)
+                                        .hint_text("dotted-quarter = 80")
+                                        .desired_width(120.0 * config.ui_scale),
+                                );
+                                let entered = response.lost_focus()
+                                    && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                                if entered || ui.button("Set").clicked() {
+                                    match tempo_expr::parse_tempo(&tempo_text) {
+                                        Ok(parsed) => {
+                                            bpm =
+                                                parsed.clamp(*BPM_RANGE.start(), *BPM_RANGE.end());
+                                            tempo_text_error = None;
+                                        }
+                                        Err(e) => tempo_text_error = Some(e.to_string()),
+                                    }
+                                }
+                            });
+                            if let Some(e) = &tempo_text_error {
+                                ui.colored_label(egui::Color32::RED, e);
+                            }
+                            ui.horizontal(|ui| {
+                                for (name, ratio) in [
+                                    ("×1/2", 0.5),
+                                    ("×2/3", 2.0 / 3.0),
+                                    ("×3/2", 1.5),
+                                    ("×2", 2.0),
+                                ] {
+                                    let target = bpm * ratio;
+                                    let in_range = BPM_RANGE.contains(&target);
+                                    if ui.add_enabled(in_range, egui::Button::new(name)).clicked() {
+                                        bpm = target;
+                                        last_ratio = Some((name, target));
+                                        ratio_applied = true;
+                                    }
+                                }
+                                if let Some((name, target)) = last_ratio {
+                                    ui.label(format!("{} → {:.1} BPM", name, target));
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                if ui.button("Tap Tempo").clicked() {
+                                    tap_tempo.tap();
+                                }
+                                if let Some(staged) = tap_tempo.staged() {
+                                    ui.label(format!("{:.1} BPM", staged));
+                                    if ui.button("Apply").clicked() {
+                                        bpm = staged.clamp(*BPM_RANGE.start(), *BPM_RANGE.end());
+                                        tap_tempo.apply();
+                                    }
+                                }
+                            });
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("tap as:");
+                            ui.menu_button(tap_tempo.division.name(), |ui| {
+                                for division in TapDivision::ALL {
+                                    if ui.button(division.name()).clicked() {
+                                        tap_tempo.division = division;
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                            ui.label("snap:");
+                            ui.menu_button(tap_tempo.snap.name(), |ui| {
+                                for snap in TapSnap::ALL {
+                                    if ui.button(snap.name()).clicked() {
+                                        tap_tempo.snap = snap;
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        });
+
+                        if config.pendulum {
+                            pendulum::pendulum(ui, beats, 40.0 * config.ui_scale);
+                        }
+                        if config.click_light {
+                            beat_flash::click_light(
+                                ui,
+                                last_click.map(|at| now.saturating_duration_since(at)),
+                                32.0 * config.ui_scale,
+                            );
+                        }
+                        if config.beat_counter {
+                            beat_counter::beat_counter(
+                                ui,
+                                beats,
+                                patterns[active_pattern].numerator,
+                                48.0 * config.ui_scale,
+                            );
+                        }
+                    });
+                    if config.timeline {
+                        // Two bars ahead, the pattern loops so its playbacks cover any window
+                        let (playbacks, bar_duration) = &scheduled;
+                        let position = player
+                            .pattern_position()
+                            .filter(|_| playing && *bar_duration > 0);
+                        let window = match position {
+                            Some(position) => {
+                                let start = position.max(0) as usize;
+                                start..start + 2 * bar_duration
+                            }
+                            None => 0..0,
+                        };
+                        let onsets = player::onsets(playbacks, window.clone());
+                        timeline::timeline(ui, &onsets, window, 8.0 * config.ui_scale);
+                    }
+                    ui.horizontal(|ui| {
+                        for (i, name) in ["A", "B"].into_iter().enumerate() {
+                            ui.selectable_value(
+                                &mut active_pattern,
+                                i,
+                                format!("Pattern {}", name),
+                            );
+                        }
+                        ui.add(
+                            egui::DragValue::new(&mut alternate_every)
+                                .clamp_range(0..=32)
+                                .prefix("Alternate every ")
+                                .suffix(" bars"),
+                        );
+                    });
+
+                    let pattern = &mut patterns[active_pattern];
+                    ui.horizontal(|ui| {
+                        ui.vertical(|ui| {
+                            ui.set_enabled(!tempo_locked);
+                            ui.set_width(20.0);
+                            ui.add(
+                                egui::DragValue::new(&mut pattern.numerator).clamp_range(0..=32),
+                            );
+                            ui.menu_button(pattern.subdivision.to_string(), |ui| {
+                                for i in [4, 8, 16, 32] {
+                                    if ui.button(i.to_string()).clicked() {
+                                        pattern.subdivision = i;
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        });
+                        ui.add_enabled_ui(!tempo_locked, |ui| {
+                            let label = match pattern.click_note {
+                                n if n <= pattern.subdivision => "Clicks: beats".to_string(),
+                                n => format!("Clicks: 1/{}", n),
+                            };
+                            ui.menu_button(label, |ui| {
+                                for i in [4, 8, 16, 32] {
+                                    if i >= pattern.subdivision
+                                        && ui.button(format!("1/{}", i)).clicked()
+                                    {
+                                        pattern.click_note = i;
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        });
+                        if pattern.clicks_per_beat() > 1 {
+                            ui.add(
+                                egui::Slider::new(&mut pattern.swing, 0.0..=0.75)
+                                    .custom_formatter(|v, _| format!("{:.0}%", v * 100.0))
+                                    .text("swing"),
+                            );
+                        }
+                        ui.add_enabled(
+                            !tempo_locked,
+                            egui::DragValue::new(&mut pattern.tuplet)
+                                .clamp_range(1..=9)
+                                .suffix(" per beat"),
+                        );
+                        if pattern.tuplet > 1 && player.num_channels() > 1 {
+                            ui.add(
+                                egui::Slider::new(&mut pattern.tuplet_spread, 0.0..=1.0)
+                                    .text("spread"),
+                            );
+                        }
+                        ui.checkbox(&mut pattern.pre_beat, "Pre-beat");
+                        ui.add(
+                            egui::DragValue::new(&mut pattern.fill_every)
+                                .clamp_range(0..=32)
+                                .prefix("Fill cue every ")
+                                .suffix(" bars"),
+                        );
+                        ui.add(
+                            egui::DragValue::new(&mut pattern.cross_accent_every)
+                                .clamp_range(0..=32)
+                                .prefix("Accent every ")
+                                .suffix(" beats"),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::Slider::new(&mut pattern.drop_probability, 0.0..=1.0)
+                                .custom_formatter(|v, _| format!("{:.0}%", v * 100.0))
+                                .text("of the beats left out"),
+                        );
+                        if pattern.drop_probability > 0.0 {
+                            ui.checkbox(&mut pattern.drop_downbeats, "Downbeats too");
+                            ui.add(egui::DragValue::new(&mut pattern.drop_seed).prefix("seed "));
+                        }
+                    });
+
+                    let subdiv_duration = pattern.subdiv_duration(bpm, player.sample_rate());
+                    if subdiv_duration == 0 {
+                        ui.colored_label(
+                            egui::Color32::YELLOW,
+                            "Beats are too short to be played at this tempo",
+                        );
+                    } else if subdiv_duration / pattern.tuplet.max(pattern.clicks_per_beat())
+                        < sounds.hi.len()
+                    {
+                        ui.colored_label(
+                            egui::Color32::YELLOW,
+                            "Clicks overlap at this tempo and subdivision",
+                        );
+                    }
+                    ui.horizontal(|ui| {
+                        let mut changed = false;
+                        if ui
+                            .checkbox(
+                                &mut config.overlap_protection,
+                                "Soften clicks landing together",
+                            )
+                            .changed()
+                        {
+                            player.set_overlap_protection(config.overlap_protection);
+                            changed = true;
+                        }
+                        if ui
+                            .add(
+                                egui::DragValue::new(&mut config.max_voices)
+                                    .clamp_range(1..=256)
+                                    .prefix("at most ")
+                                    .suffix(" voices"),
+                            )
+                            .changed()
+                        {
+                            player.set_max_voices(config.max_voices);
+                            changed = true;
+                        }
+                        if changed {
+                            config_changed = Some(Instant::now());
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Echo:");
+                        ui.menu_button(pattern.echo_delay.name(), |ui| {
+                            for delay in EchoDelay::ALL {
+                                if ui.button(delay.name()).clicked() {
+                                    pattern.echo_delay = delay;
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                        ui.add(
+                            egui::Slider::new(&mut pattern.echo_feedback, 0.0..=echo::MAX_FEEDBACK)
+                                .text("feedback"),
+                        );
+                    });
+
+                    ui.checkbox(&mut pattern.spatial, "Walk beats across the stereo field");
+
+                    ui.add_enabled_ui(!tempo_locked, |ui| {
+                        ui.horizontal(|ui| {
+                            let mut euclidean = pattern.euclidean_hits.is_some();
+                            if ui.checkbox(&mut euclidean, "Euclidean rhythm").changed() {
+                                pattern.euclidean_hits =
+                                    euclidean.then_some(pattern.numerator.div_ceil(2));
+                            }
+                            if let Some(hits) = &mut pattern.euclidean_hits {
+                                ui.add(
+                                    egui::DragValue::new(hits)
+                                        .clamp_range(1..=pattern.numerator.max(1))
+                                        .suffix(" hits over"),
+                                );
+                                ui.add(
+                                    egui::DragValue::new(&mut pattern.numerator)
+                                        .clamp_range(1..=32)
+                                        .suffix(" steps"),
+                                );
+                            }
+                        });
+                    });
+
+                    if !pattern.melodic
+                        && accent_meters[active_pattern] == (pattern.numerator, pattern.subdivision)
+                    {
+                        ui.horizontal(|ui| {
+                            ui.label("Accents:");
+                            let mut changed = false;
+                            for accent in pattern.accents.iter_mut() {
+                                if ui.small_button(accent.symbol()).clicked() {
+                                    *accent = accent.next();
+                                    changed = true;
+                                }
+                            }
+                            // Grouping sets the number of beats, which the lock holds
+                            let response = ui.add_enabled(
+                                !tempo_locked,
+                                egui::TextEdit::singleline(&mut grouping_text)
+                                    .hint_text("3+3+2")
+                                    .desired_width(60.0 * config.ui_scale),
+                            );
+                            let entered = response.lost_focus()
+                                && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                            let grouped = ui
+                                .add_enabled(!tempo_locked, egui::Button::new("Group"))
+                                .clicked();
+                            if !tempo_locked && (entered || grouped) {
+                                match parse_grouping(&grouping_text) {
+                                    Ok(groups) => {
+                                        // The meter changes with the number of beats, the saved
+                                        // accents of the new one are what gets loaded
+                                        pattern.numerator = groups.iter().sum();
+                                        pattern.accents = AccentLevel::grouped(&groups);
+                                        grouping_error = None;
+                                        changed = true;
+                                    }
+                                    Err(e) => grouping_error = Some(e.to_string()),
+                                }
+                            }
+                            if let Some(e) = &grouping_error {
+                                ui.colored_label(egui::Color32::RED, e);
+                            }
+                            if changed {
+                                config.set_accents(
+                                    pattern.numerator,
+                                    pattern.subdivision,
+                                    &pattern.accents,
+                                );
+                                config_changed = Some(Instant::now());
+                            }
+                        });
+                    }
+
+                    if accent_meters[active_pattern] == (pattern.numerator, pattern.subdivision) {
+                        ui.horizontal(|ui| {
+                            ui.label("Mute:");
+                            let mut changed = false;
+                            pattern.muted.resize(pattern.numerator, false);
+                            for (i, muted) in pattern.muted.iter_mut().enumerate() {
+                                changed |= ui.toggle_value(muted, (i + 1).to_string()).changed();
+                            }
+                            if changed {
+                                config.set_muted(
+                                    pattern.numerator,
+                                    pattern.subdivision,
+                                    &pattern.muted,
+                                );
+                                config_changed = Some(Instant::now());
+                            }
+                        });
+                    }
+
+                    pattern.beat_degrees.resize(pattern.numerator, 1);
+                    ui.checkbox(&mut pattern.melodic, "Melodic clicks");
+                    if pattern.melodic {
+                        ui.horizontal(|ui| {
+                            ui.label("Scale degrees:");
+                            for degree in pattern.beat_degrees.iter_mut() {
+                                ui.add(egui::DragValue::new(degree).clamp_range(1..=15));
+                            }
+                        });
+                    }
+
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add(
+                                egui::DragValue::new(&mut volume_db)
+                                    .clamp_range(volume_range.clone())
+                                    .suffix("db"),
+                            )
+                            .changed()
+                        {
+                            if volume_db > 0.0 && !boost_confirmed {
+                                volume_db = 0.0;
+                                confirming_boost = true;
+                            }
+                            player.set_volume_db(volume_db);
+                            sleep_timer.cancel();
+                        }
+                    });
+                    if confirming_boost {
+                        ui.horizontal(|ui| {
+                            ui.colored_label(
+                                egui::Color32::YELLOW,
+                                "Above 0 dB the click gets distorted and very loud",
+                            );
+                            if ui.button("Allow").clicked() {
+                                boost_confirmed = true;
+                                confirming_boost = false;
+                            }
+                            if ui.button("Cancel").clicked() {
+                                confirming_boost = false;
+                            }
+                        });
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.set_enabled(!tempo_locked);
+                        if ui.button("Nudge earlier").clicked() {
+                            nudge_by -= NUDGE_STEP_MS;
+                        }
+                        if ui.button("Nudge later").clicked() {
+                            nudge_by += NUDGE_STEP_MS;
+                        }
+                        ui.label(format!("{:+} ms", nudge_ms));
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Envelope:");
+                        ui.menu_button(click_shape.envelope.name(), |ui| {
+                            for e in Envelope::ALL {
+                                if ui.button(e.name()).clicked() {
+                                    click_shape.envelope = e;
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                        let mut length_ms = click_shape.length.as_millis() as u64;
+                        let min_ms = click::MIN_CLICK_LENGTH.as_millis() as u64;
+                        if ui
+                            .add(
+                                egui::DragValue::new(&mut length_ms)
+                                    .clamp_range(min_ms..=500)
+                                    .suffix(" ms long"),
+                            )
+                            .changed()
+                        {
+                            click_shape.length = Duration::from_millis(length_ms);
+                        }
+                        ui.add_enabled(
+                            click_shape.envelope != Envelope::Gate,
+                            egui::DragValue::new(&mut click_shape.decay_db)
+                                .clamp_range(6.0..=80.0)
+                                .prefix("decaying by ")
+                                .suffix(" dB"),
+                        );
+                    });
+
+                    ui.horizontal(|ui| {
+                        for (timbre, role) in timbres.iter_mut().zip(["Downbeat", "Accent", "Beat"])
+                        {
+                            ui.label(format!("{}:", role));
+                            ui.menu_button(timbre.name(), |ui| {
+                                for t in Timbre::ALL {
+                                    if ui.button(t.name()).clicked() {
+                                        *timbre = t;
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        }
+                    });
+                    ui.collapsing("Presets", |ui| {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut preset_name)
+                                    .hint_text("Name")
+                                    .desired_width(100.0 * config.ui_scale),
+                            );
+                            let name = preset_name.trim();
+                            if ui
+                                .add_enabled(!name.is_empty(), egui::Button::new("Save"))
+                                .clicked()
+                            {
+                                let pattern = &patterns[active_pattern];
+                                let preset = Preset {
+                                    name: name.to_string(),
+                                    bpm,
+                                    numerator: pattern.numerator,
+                                    subdivision: pattern.subdivision,
+                                    accents: pattern.accents.clone(),
+                                    volume_db,
+                                };
+                                // Saving under an existing name updates that preset in place
+                                match config.presets.iter_mut().find(|p| p.name == preset.name) {
+                                    Some(existing) => *existing = preset,
+                                    None => config.presets.push(preset),
+                                }
+                                preset_name.clear();
+                                config_changed = Some(Instant::now());
+                            }
+                        });
+                        let mut remove = None;
+                        for (i, preset) in config.presets.iter().enumerate() {
+                            ui.horizontal(|ui| {
+                                let key = if i < 9 {
+                                    format!("{}: ", i + 1)
+                                } else {
+                                    String::new()
+                                };
+                                let label = format!(
+                                    "{}{} - {:.0} BPM {}/{}",
+                                    key,
+                                    preset.name,
+                                    preset.bpm,
+                                    preset.numerator,
+                                    preset.subdivision
+                                );
+                                if ui.button(label).clicked() {
+                                    load_preset = Some(i);
+                                }
+                                if ui.small_button("Remove").clicked() {
+                                    remove = Some(i);
+                                }
+                            });
+                        }
+                        if let Some(i) = remove {
+                            config.presets.remove(i);
+                            config_changed = Some(Instant::now());
+                        }
+                    });
+                    ui.collapsing("Custom samples", |ui| {
+                        let roles = ["Downbeat", "Accent", "Beat"];
+                        for ((path, file), role) in sample_paths
+                            .iter_mut()
+                            .zip(sample_files.iter_mut())
+                            .zip(roles)
+                        {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("{}:", role));
+                                ui.text_edit_singleline(path);
+                                if ui.button("Load").clicked() {
+                                    *file = Some(SampleFile::open(
+                                        PathBuf::from(&*path),
+                                        player.sample_rate(),
+                                    ));
+                                    samples_changed = true;
+                                }
+                                if file.is_some() && ui.button("Clear").clicked() {
+                                    *file = None;
+                                    samples_changed = true;
+                                }
+                            });
+                            if let Some(e) = file.as_ref().and_then(|f| f.error.as_ref()) {
+                                ui.colored_label(egui::Color32::RED, e);
+                            }
+                        }
+                        ui.checkbox(&mut watch_samples, "Reload when the files change");
+                    });
+                    ui.collapsing("Layers", |ui| {
+                        ui.label("Independent metronomes playing along, e.g. at another tempo");
+                        if ui
+                            .checkbox(&mut main_layer_solo, "Solo the main pattern")
+                            .changed()
+                        {
+                            player.set_layer_solo(player::MAIN_LAYER, main_layer_solo);
+                        }
+                        layers.retain_mut(|layer| {
+                            let mut keep = true;
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut layer.name)
+                                        .desired_width(80.0 * config.ui_scale),
+                                );
+                                ui.add(
+                                    egui::DragValue::new(&mut layer.bpm)
+                                        .clamp_range(BPM_RANGE)
+                                        .suffix(" BPM"),
+                                );
+                                ui.add(
+                                    egui::DragValue::new(&mut layer.numerator)
+                                        .clamp_range(1..=32)
+                                        .suffix(" beats"),
+                                );
+                                let numerator = layer.numerator;
+                                layer.accents.truncate(numerator);
+                                let added = layer.accents.len()..numerator;
+                                layer.accents.extend(added.map(AccentLevel::default_for));
+                                for accent in layer.accents.iter_mut() {
+                                    if ui.small_button(accent.symbol()).clicked() {
+                                        *accent = accent.next();
+                                    }
+                                }
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut layer.volume_db)
+                                            .clamp_range(-36.0..=0.0)
+                                            .suffix("db"),
+                                    )
+                                    .changed()
+                                {
+                                    player.set_layer_volume_db(layer.id, layer.volume_db);
+                                }
+                                if ui.checkbox(&mut layer.muted, "Mute").changed() {
+                                    player.set_layer_muted(layer.id, layer.muted);
+                                }
+                                if ui.checkbox(&mut layer.solo, "Solo").changed() {
+                                    player.set_layer_solo(layer.id, layer.solo);
+                                }
+                                if ui.button("Remove").clicked() {
+                                    player.clear_layer(layer.id);
+                                    player.set_layer_solo(layer.id, false);
+                                    keep = false;
+                                }
+                            });
+                            keep
+                        });
+                        if ui.button("Add layer").clicked() {
+                            layers.push(ClickLayer {
+                                id: next_layer_id,
+                                name: format!("Layer {}", next_layer_id),
+                                bpm: (bpm * 1.5).clamp(*BPM_RANGE.start(), *BPM_RANGE.end()),
+                                numerator: 4,
+                                accents: AccentLevel::default_pattern(4),
+                                volume_db: -6.0,
+                                muted: false,
+                                solo: false,
+                            });
+                            player.set_layer_volume_db(next_layer_id, -6.0);
+                            next_layer_id += 1;
+                        }
+                    });
+                    ui.collapsing("Click waveform", |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Waveform:");
+                            ui.menu_button(click_shape.waveform.name(), |ui| {
+                                for w in Waveform::ALL {
+                                    if ui.button(w.name()).clicked() {
+                                        click_shape.waveform = w;
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        });
+                        waveform::waveform(
+                            ui,
+                            &sounds.hi,
+                            player.sample_rate(),
+                            30.0 * config.ui_scale,
+                        );
+                    });
+
+                    ui.collapsing("Appearance", |ui| {
+                        let mut changed = ui
+                            .add(
+                                egui::Slider::new(&mut config.ui_scale, 1.0..=8.0).text("UI scale"),
+                            )
+                            .changed();
+                        changed |= ui.checkbox(&mut config.dark_mode, "Dark theme").changed();
+                        changed |= ui.checkbox(&mut config.pendulum, "Pendulum").changed();
+                        changed |= ui.checkbox(&mut config.timeline, "Timeline").changed();
+                        changed |= ui
+                            .checkbox(&mut config.beat_counter, "Beat counter")
+                            .changed();
+                        changed |= ui
+                            .checkbox(&mut config.click_light, "Light on every click")
+                            .changed();
+                        ui.horizontal(|ui| {
+                            ui.label("Flash on");
+                            ui.menu_button(config.flash_beats.name(), |ui| {
+                                for flash_beats in FlashBeats::ALL {
+                                    if ui.button(flash_beats.name()).clicked() {
+                                        config.flash_beats = flash_beats;
+                                        changed = true;
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                            changed |= ui
+                                .add(
+                                    egui::DragValue::new(&mut config.beat_light_ms)
+                                        .clamp_range(20..=1000)
+                                        .prefix("beat light ")
+                                        .suffix(" ms"),
+                                )
+                                .changed();
+                        });
+                        let flash = &mut config.screen_flash;
+                        changed |= ui
+                            .checkbox(&mut flash.enabled, "Flash the window")
+                            .changed();
+                        if flash.enabled {
+                            ui.horizontal(|ui| {
+                                ui.label("Downbeat");
+                                changed |= ui
+                                    .color_edit_button_srgb(&mut flash.downbeat_color)
+                                    .changed();
+                                ui.label("Beat");
+                                changed |=
+                                    ui.color_edit_button_srgb(&mut flash.beat_color).changed();
+                                changed |= ui
+                                    .add(
+                                        egui::Slider::new(&mut flash.intensity, 0.1..=1.0)
+                                            .text("intensity"),
+                                    )
+                                    .changed();
+                                changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut flash.duration_ms)
+                                            .clamp_range(20..=300)
+                                            .suffix(" ms"),
+                                    )
+                                    .changed();
+                            });
+                        }
+                        if changed {
+                            config_changed = Some(Instant::now());
+                        }
+                    });
+
+                    ui.collapsing("Output device", |ui| {
+                        let current = output_device.as_deref().unwrap_or("default");
+                        let response = ui.menu_button(format!("Device: {}", current), |ui| {
+                            if ui.button("default").clicked() {
+                                switch_device = Some(None);
                                 ui.close_menu();
                             }
+                            for name in &output_devices {
+                                if ui.button(name).clicked() {
+                                    switch_device = Some(Some(name.clone()));
+                                    ui.close_menu();
+                                }
+                            }
+                            if output_devices.is_empty() {
+                                ui.label("No output devices found");
+                            }
+                        });
+                        // Enumerating devices can take a while, so only do it when opening the menu
+                        if response.response.clicked() {
+                            match player::Player::list_output_devices() {
+                                Ok(devices) => output_devices = devices,
+                                Err(e) => output_device_error = Some(e.to_string()),
+                            }
+                        }
+                        if let Some(e) = &output_device_error {
+                            ui.colored_label(egui::Color32::RED, e);
+                        }
+                    });
+                    ui.collapsing("Channel trim", |ui| {
+                        if ui
+                            .checkbox(&mut config.mono_output, "Mono output")
+                            .on_hover_text("Opens a mono stream from the next start")
+                            .changed()
+                        {
+                            player.set_mono(config.mono_output);
+                            config_changed = Some(Instant::now());
+                        }
+                        if ui
+                            .checkbox(&mut config.bypass_limiter, "Bypass limiter")
+                            .on_hover_text("Linear output for measurements, loud mixes clip hard")
+                            .changed()
+                        {
+                            player.bypass_limiter(config.bypass_limiter);
+                            config_changed = Some(Instant::now());
+                        }
+                        let mut changed = false;
+                        for (ch, gain) in channel_gains.iter_mut().enumerate() {
+                            changed |= ui
+                                .add(
+                                    egui::DragValue::new(gain)
+                                        .clamp_range(0.0..=2.0)
+                                        .speed(0.01)
+                                        .prefix(format!("ch {}: ", ch + 1)),
+                                )
+                                .changed();
+                        }
+                        if changed {
+                            player.set_channel_gains(channel_gains.clone());
+                        }
+                    });
+
+                    ui.collapsing("MIDI tempo map", |ui| {
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut midi_path);
+                            if ui.button("Load").clicked() {
+                                match TempoMap::load(Path::new(&midi_path)) {
+                                    Ok(map) => {
+                                        midi_follower = Some(TempoMapFollower::new(map));
+                                        midi_error = None;
+                                    }
+                                    Err(e) => midi_error = Some(e.to_string()),
+                                }
+                            }
+                        });
+                        if let Some(e) = &midi_error {
+                            ui.colored_label(egui::Color32::RED, e);
+                        }
+                        if let Some(follower) = &mut midi_follower {
+                            ui.label(format!(
+                                "{} tempo/meter sections",
+                                follower.map.sections.len()
+                            ));
+                            if follower.is_following() {
+                                if ui.button("Stop following").clicked() {
+                                    follower.stop();
+                                }
+                            } else if ui.button("Follow").clicked() {
+                                follower.start();
+                            }
                         }
                     });
-                });
-            });
 
-            if ui
-                .add(
-                    egui::DragValue::new(&mut volume_db)
-                        .clamp_range(-36.0..=36.0)
-                        .suffix("db"),
-                )
-                .changed()
-            {
-                player.set_volume_db(volume_db);
-            }
+                    ui.collapsing("MIDI control", |ui| {
+                        ui.horizontal(|ui| {
+                            let current =
+                                midi_input.as_ref().map_or("none", |i| i.port_name.as_str());
+                            let response = ui.menu_button(format!("Input: {}", current), |ui| {
+                                for port in &midi_ports {
+                                    if ui.button(port).clicked() {
+                                        match MidiInput::connect(port) {
+                                            Ok(input) => {
+                                                midi_input = Some(input);
+                                                midi_input_error = None;
+                                                config.midi_input = Some(port.clone());
+                                                config_changed = Some(Instant::now());
+                                            }
+                                            Err(e) => midi_input_error = Some(e.to_string()),
+                                        }
+                                        ui.close_menu();
+                                    }
+                                }
+                                if midi_ports.is_empty() {
+                                    ui.label("No MIDI inputs found");
+                                }
+                            });
+                            // Enumerating ports is slow on some systems, so only do it when opening the menu
+                            if response.response.clicked() {
+                                midi_ports = MidiInput::ports();
+                            }
+                        });
+                        if let Some(e) = &midi_input_error {
+                            ui.colored_label(egui::Color32::RED, e);
+                        }
+                        ui.horizontal(|ui| {
+                            if ui
+                                .checkbox(
+                                    &mut config.midi_clock_start,
+                                    "Start on the next bar of the MIDI clock",
+                                )
+                                .changed()
+                            {
+                                config_changed = Some(Instant::now());
+                            }
+                            let clock = midi_input.as_ref().map(|i| &i.clock);
+                            if let Some(bpm) = clock.and_then(|c| c.bpm()) {
+                                let state = if clock.is_some_and(|c| c.running()) {
+                                    "playing"
+                                } else {
+                                    "stopped"
+                                };
+                                ui.label(format!("clock {:.1} BPM, {}", bpm, state));
+                            }
+                        });
+                        for (action, trigger) in [
+                            (MidiAction::StartStop, config.midi_start_stop),
+                            (MidiAction::TapTempo, config.midi_tap),
+                        ] {
+                            ui.horizontal(|ui| {
+                                ui.label(format!(
+                                    "{}: {}",
+                                    action.name(),
+                                    trigger.map_or("unassigned".to_string(), |t| t.name())
+                                ));
+                                if midi_learn == Some(action) {
+                                    if ui.button("Waiting for MIDI... (cancel)").clicked() {
+                                        midi_learn = None;
+                                    }
+                                } else if ui.button("Learn").clicked() {
+                                    midi_learn = Some(action);
+                                }
+                            });
+                        }
+
+                        ui.horizontal(|ui| {
+                            let current = midi_feedback
+                                .as_ref()
+                                .map_or("none", |o| o.port_name.as_str());
+                            let response =
+                                ui.menu_button(format!("Tempo display: {}", current), |ui| {
+                                    if ui.button("none").clicked() {
+                                        midi_feedback = None;
+                                        config.midi_feedback_output = None;
+                                        config_changed = Some(Instant::now());
+                                        ui.close_menu();
+                                    }
+                                    for port in &midi_output_ports {
+                                        if ui.button(port).clicked() {
+                                            match MidiFeedback::connect(port) {
+                                                Ok(output) => {
+                                                    midi_feedback = Some(output);
+                                                    midi_feedback_error = None;
+                                                    config.midi_feedback_output =
+                                                        Some(port.clone());
+                                                    config_changed = Some(Instant::now());
+                                                }
+                                                Err(e) => midi_feedback_error = Some(e.to_string()),
+                                            }
+                                            ui.close_menu();
+                                        }
+                                    }
+                                });
+                            if response.response.clicked() {
+                                midi_output_ports = MidiFeedback::ports();
+                            }
+                        });
+                        if let Some(e) = &midi_feedback_error {
+                            ui.colored_label(egui::Color32::RED, e);
+                        }
+                        if midi_feedback.is_some() {
+                            let mapping = &mut config.midi_feedback;
+                            let mut changed = false;
+                            ui.horizontal(|ui| {
+                                let mut channel = mapping.channel + 1;
+                                changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut channel)
+                                            .clamp_range(1..=16)
+                                            .prefix("ch "),
+                                    )
+                                    .changed();
+                                mapping.channel = channel - 1;
+                                changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut mapping.tempo_nrpn)
+                                            .clamp_range(0..=16383)
+                                            .prefix("tempo NRPN "),
+                                    )
+                                    .changed();
+                                changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut mapping.beat_cc)
+                                            .clamp_range(0..=127)
+                                            .prefix("beat CC "),
+                                    )
+                                    .changed();
+                                changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut mapping.bar_cc)
+                                            .clamp_range(0..=127)
+                                            .prefix("bar CC "),
+                                    )
+                                    .changed();
+                            });
+                            if changed {
+                                config_changed = Some(Instant::now());
+                            }
+                        }
+                    });
+                    ui.collapsing("Tempo match", |ui| {
+                        let mut open = tempo_match.is_some();
+                        if ui
+                            .checkbox(&mut open, "Compare taps to a target tempo")
+                            .changed()
+                        {
+                            tempo_match = open.then(|| TempoMatch::new(bpm));
+                        }
+                        if let Some(tempo_match) = &mut tempo_match {
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::DragValue::new(&mut tempo_match.target_bpm)
+                                        .clamp_range(BPM_RANGE)
+                                        .prefix("target ")
+                                        .suffix(" BPM"),
+                                );
+                                if ui.button("Tap (Enter)").clicked() {
+                                    tempo_match.taps.tap();
+                                }
+                                if !tempo_locked && ui.button("Click at the target").clicked() {
+                                    bpm = tempo_match.target_bpm;
+                                }
+                            });
+                            tempo_match.needle(ui, 60.0 * config.ui_scale);
+                            ui.label(match tempo_match.deviation_percent() {
+                                Some(d) => format!("{} ({:+.1}%)", tempo_match.feedback(), d),
+                                None => tempo_match.feedback().to_string(),
+                            });
+                        }
+                    });
+                    ui.collapsing("Tap the meter", |ui| {
+                        let mut open = tap_meter.is_some();
+                        if ui
+                            .checkbox(&mut open, "Tap beats (Enter) and downbeats (Shift+Enter)")
+                            .changed()
+                        {
+                            tap_meter = open.then(TapMeter::new);
+                        }
+                        if let Some(meter) = &mut tap_meter {
+                            ui.horizontal(|ui| {
+                                if ui.button("Downbeat").clicked() {
+                                    meter.tap(Instant::now(), true);
+                                }
+                                if ui.button("Beat").clicked() {
+                                    meter.tap(Instant::now(), false);
+                                }
+                                if ui.button("Reset").clicked() {
+                                    meter.reset();
+                                }
+                            });
+                            match meter.detect() {
+                                Detection::Incomplete => {
+                                    ui.label(format!("{} bars tapped, keep going", meter.bars()));
+                                }
+                                Detection::Inconsistent(reason) => {
+                                    ui.horizontal(|ui| {
+                                        ui.colored_label(
+                                            egui::Color32::YELLOW,
+                                            format!("Please retry, {}", reason),
+                                        );
+                                        if ui.button("Retry").clicked() {
+                                            meter.reset();
+                                        }
+                                    });
+                                }
+                                Detection::Meter {
+                                    numerator,
+                                    bpm: beat_bpm,
+                                } => {
+                                    let pattern = &mut patterns[active_pattern];
+                                    // The tapped beats are the pattern's subdivision notes
+                                    let tapped_bpm = beat_bpm * 4.0 / pattern.subdivision as f32;
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!(
+                                            "{}/{} at {:.1} BPM",
+                                            numerator, pattern.subdivision, tapped_bpm
+                                        ));
+                                        if !tempo_locked && ui.button("Use").clicked() {
+                                            let subdivision = pattern.subdivision;
+                                            pattern.set_meter(numerator, subdivision);
+                                            bpm = tapped_bpm
+                                                .clamp(*BPM_RANGE.start(), *BPM_RANGE.end());
+                                        }
+                                    });
+                                }
+                            }
+                        }
+                    });
+                    ui.collapsing("Gap click", |ui| {
+                        let mut changed = ui
+                            .checkbox(&mut bar_gate_on, "Leave out bars of clicks")
+                            .changed();
+                        ui.horizontal(|ui| {
+                            changed |= ui
+                                .add(
+                                    egui::DragValue::new(&mut bar_gate.play_bars)
+                                        .clamp_range(1..=16)
+                                        .suffix(" bars of clicks, then"),
+                                )
+                                .changed();
+                            changed |= ui
+                                .add(
+                                    egui::DragValue::new(&mut bar_gate.mute_bars)
+                                        .clamp_range(1..=16)
+                                        .suffix(" silent bars"),
+                                )
+                                .changed();
+                        });
+                        if changed {
+                            player.set_bar_gate(bar_gate_on.then_some(bar_gate));
+                        }
+                    });
+                    ui.collapsing("Count drill", |ui| {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut count_drill.play_bars)
+                                    .clamp_range(1..=16)
+                                    .suffix(" bars of clicks, then"),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut count_drill.silent_bars)
+                                    .clamp_range(1..=16)
+                                    .suffix(" silent bars"),
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            if count_drill.is_running() {
+                                if ui.button("Tap beat (Enter)").clicked() {
+                                    count_drill.tap(latency_calibration::compensate(
+                                        Instant::now(),
+                                        config.latency_offset_ms,
+                                    ));
+                                }
+                            } else if ui.button("Start drill").clicked() {
+                                let sample_rate = player.sample_rate();
+                                let pattern = &patterns[active_pattern];
+                                let schedule = pattern.schedule(bpm, sample_rate, &mut sounds);
+                                if schedule.bar_duration >= 1.0 {
+                                    let bars = count_drill.play_bars + count_drill.silent_bars;
+                                    let mut playbacks = schedule.unroll(count_drill.play_bars);
+                                    // A single downbeat to compare the silent count against
+                                    let downbeat = bars as f64 * schedule.bar_duration;
+                                    playbacks.push(
+                                        Playback::new(sounds.hi.clone())
+                                            .offset(downbeat.round() as usize)
+                                            .repeat_exact(schedule.bar_duration, Some(0)),
+                                    );
+
+                                    // A little ahead, so the start time is known exactly
+                                    let start = Instant::now() + Duration::from_millis(100);
+                                    player.start_at(playbacks, schedule.bar_duration, start);
+                                    let beat = pattern.beat_duration(bpm, sample_rate)
+                                        / sample_rate as f64;
+                                    count_drill.start(
+                                        start,
+                                        Duration::from_secs_f64(beat),
+                                        pattern.numerator,
+                                    );
+                                }
+                            }
+                            if !count_drill.is_running()
+                                && ui.button("Back to the metronome").clicked()
+                            {
+                                reschedule = true;
+                            }
+                        });
+                        if let Some(stats) = count_drill.stats() {
+                            ui.label(format!(
+                                "{} taps, {:+.0} ms off on average, spread {:.0} ms",
+                                stats.taps, stats.mean_ms, stats.spread_ms
+                            ));
+                        }
+                    });
+                    ui.collapsing("Latency calibration", |ui| {
+                        ui.label("Tap Enter along with the clicks, in time with what you hear.");
+                        ui.horizontal(|ui| {
+                            if calibration.is_running() {
+                                if ui.button("Tap (Enter)").clicked() {
+                                    calibration.tap(Instant::now());
+                                }
+                            } else if ui.button("Start calibration").clicked() {
+                                let sample_rate = player.sample_rate();
+                                let interval = (latency_calibration::INTERVAL.as_secs_f64()
+                                    * sample_rate as f64)
+                                    .round()
+                                    as usize;
+                                let clicks = Playback::new(sounds.hi.clone())
+                                    .repeat(interval, Some(latency_calibration::CLICKS - 1));
+
+                                // A little ahead, so the start time is known exactly
+                                let start = Instant::now() + Duration::from_millis(100);
+                                player.start_at(vec![clicks], 0.0, start);
+                                scheduled = (Vec::new(), 0);
+                                calibration.start(start);
+                            }
+                            if !calibration.is_running()
+                                && ui.button("Back to the metronome").clicked()
+                            {
+                                reschedule = true;
+                            }
+                        });
+                        if let Some(result) = calibration.result() {
+                            ui.horizontal(|ui| {
+                                ui.label(format!(
+                                    "{} taps, {:+.0} ms late, spread {:.0} ms",
+                                    result.taps, result.offset_ms, result.spread_ms
+                                ));
+                                if !calibration.is_running() && ui.button("Use").clicked() {
+                                    config.latency_offset_ms = result.offset_ms.round() as f32;
+                                    config_changed = Some(Instant::now());
+                                }
+                            });
+                        }
+                        if ui
+                            .add(
+                                egui::DragValue::new(&mut config.latency_offset_ms)
+                                    .clamp_range(-500.0..=500.0)
+                                    .prefix("Latency offset ")
+                                    .suffix(" ms"),
+                            )
+                            .changed()
+                        {
+                            config_changed = Some(Instant::now());
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Restart on the clock").clicked() {
+                            pending_start = Some(next_clock_boundary(sync_every_secs));
+                            reschedule = true;
+                        }
+                        ui.add(
+                            egui::DragValue::new(&mut sync_every_secs)
+                                .clamp_range(1..=60)
+                                .prefix("every ")
+                                .suffix(" s"),
+                        );
+                    });
+                    ui.collapsing("Export click track", |ui| {
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut export_path);
+                            ui.add(
+                                egui::DragValue::new(&mut export_bars)
+                                    .clamp_range(1..=999)
+                                    .suffix(" bars"),
+                            );
+                            if ui.button("Export").clicked() {
+                                // Rendered through a mixer set up like the output, with the
+                                // volume, bar gate and layers, so the file sounds like what plays
+                                let sample_rate = player.sample_rate();
+                                let schedule = if alternate_every > 0 {
+                                    Schedule::alternate(
+                                        &patterns[0].schedule(bpm, sample_rate, &mut sounds),
+                                        &patterns[1].schedule(bpm, sample_rate, &mut sounds),
+                                        alternate_every,
+                                    )
+                                } else {
+                                    patterns[active_pattern].schedule(bpm, sample_rate, &mut sounds)
+                                };
+                                let channels = player.num_channels().min(2);
+                                let path = Path::new(&export_path);
+                                let export = || -> anyhow::Result<()> {
+                                    let mut mix = player::Player::with_sink(NullSink::new(
+                                        sample_rate,
+                                        channels,
+                                    ))?;
+                                    apply_output_config(&mut mix, &config);
+                                    let layers = layers
+                                        .iter()
+                                        .map(|layer| player::TrackLayer {
+                                            id: layer.id,
+                                            playbacks: layer_pattern(
+                                                layer.numerator,
+                                                layer.accents.clone(),
+                                            )
+                                            .schedule(layer.bpm, sample_rate, &mut sounds)
+                                            .playbacks,
+                                            volume_db: layer.volume_db,
+                                            muted: layer.muted,
+                                            solo: layer.solo,
+                                        })
+                                        .collect();
+                                    let track = player::ClickTrack {
+                                        playbacks: schedule.playbacks,
+                                        bar_lengths: schedule.bar_lengths,
+                                        bars: export_bars,
+                                        volume_db,
+                                        bar_gate: bar_gate_on.then_some(bar_gate),
+                                        main_solo: main_layer_solo,
+                                        layers,
+                                    };
+                                    let output = player::export_click_track(&mut mix, track);
+                                    wav::write_wav(path, &output, sample_rate, channels)?;
+                                    Ok(())
+                                };
+                                export_result = Some(
+                                    export()
+                                        .map(|()| format!("Saved {}", path.display()))
+                                        .map_err(|e| e.to_string()),
+                                );
+                            }
+                        });
+                        match &export_result {
+                            Some(Ok(message)) => {
+                                ui.label(message);
+                            }
+                            Some(Err(e)) => {
+                                ui.colored_label(egui::Color32::RED, e);
+                            }
+                            None => {}
+                        }
+                    });
+                    ui.collapsing("Backing track", |ui| {
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut track_path);
+                            if player.track_playing() {
+                                if ui.button("Stop").clicked() {
+                                    player.stop_track();
+                                }
+                            } else if ui.button("Play").clicked() {
+                                match BackingTrack::open(
+                                    Path::new(&track_path),
+                                    player.sample_rate(),
+                                ) {
+                                    Ok(track) => {
+                                        pending_track = Some(track);
+                                        track_error = None;
+                                        reschedule = true;
+                                        if !playing {
+                                            update_reference = true;
+                                            last_layers.clear();
+                                        }
+                                        playing = true;
+                                    }
+                                    Err(e) => track_error = Some(e.to_string()),
+                                }
+                            }
+                        });
+                        if let Some(e) = &track_error {
+                            ui.colored_label(egui::Color32::RED, e);
+                        }
+                        ui.horizontal(|ui| {
+                            if ui
+                                .add(
+                                    egui::DragValue::new(&mut track_volume_db)
+                                        .clamp_range(-36.0..=12.0)
+                                        .prefix("volume ")
+                                        .suffix("db"),
+                                )
+                                .changed()
+                            {
+                                player.set_track_volume_db(track_volume_db);
+                            }
+                            ui.checkbox(&mut track_count_in, "One bar count-in");
+                        });
+                    });
+                    if player.track_playing() {
+                        ctx.request_repaint_after(Duration::from_millis(250));
+                    }
+                    ui.collapsing("Reference pitch", |ui| {
+                        ui.horizontal(|ui| {
+                            update_reference |= ui.checkbox(&mut reference_on, "Drone").changed();
+                            update_reference |= ui
+                                .add(
+                                    egui::DragValue::new(&mut reference_freq)
+                                        .clamp_range(55.0..=1760.0)
+                                        .speed(1.0)
+                                        .max_decimals(0)
+                                        .suffix(" Hz"),
+                                )
+                                .changed();
+                        });
+                    });
 
-            let new_state = (bpm, numerator, subdivision);
-            if last_state != new_state {
-                last_state = new_state;
-
-                let subdiv_duration = ((player.sample_rate() as f32 * 60.0 * 4.0)
-                    / bpm
-                    / subdivision as f32) as usize;
-                let bar_duration = subdiv_duration * numerator;
-
-                player.clear_playbacks();
-                player.add_playbacks(
-                    (0..numerator)
-                        .map(|i| {
-                            let sample = if i == 0 {
-                                hi_click.clone()
-                            } else if i % 2 == 1 {
-                                lo_click.clone()
+                    ui.collapsing("Tempo trainer", |ui| {
+                        ui.checkbox(&mut tempo_trainer.enabled, "Speed up while playing");
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut tempo_trainer.base_bpm)
+                                    .clamp_range(BPM_RANGE)
+                                    .prefix("From ")
+                                    .suffix(" BPM"),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut tempo_trainer.max_bpm)
+                                    .clamp_range(BPM_RANGE)
+                                    .prefix("to ")
+                                    .suffix(" BPM"),
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut tempo_trainer.step_bpm)
+                                    .clamp_range(0.5..=50.0)
+                                    .speed(0.1)
+                                    .prefix("+")
+                                    .suffix(" BPM"),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut tempo_trainer.bars_per_step)
+                                    .clamp_range(1..=64)
+                                    .prefix("every ")
+                                    .suffix(" bars"),
+                            );
+                        });
+                        if tempo_trainer.enabled && !playing && ui.button("Start").clicked() {
+                            toggle_playing = true;
+                        }
+                    });
+
+                    ui.collapsing("Sleep timer", |ui| {
+                        ui.horizontal(|ui| {
+                            if let Some(remaining) = sleep_timer.remaining() {
+                                let secs = remaining.as_secs();
+                                ui.label(format!(
+                                    "Fading out, {}:{:02} left",
+                                    secs / 60,
+                                    secs % 60
+                                ));
+                                if ui.button("Cancel").clicked() {
+                                    sleep_timer.cancel();
+                                    player.set_volume_db(volume_db);
+                                }
                             } else {
-                                mid_click.clone()
-                            };
+                                ui.add(
+                                    egui::DragValue::new(&mut sleep_timer.minutes)
+                                        .clamp_range(1.0..=120.0)
+                                        .speed(0.1)
+                                        .suffix(" min"),
+                                );
+                                if ui.button("Start").clicked() {
+                                    sleep_timer.start();
+                                    player
+                                        .ramp_volume_db(f32::NEG_INFINITY, sleep_timer.duration());
+                                }
+                            }
+                        });
+                        if stopped_by_sleep_timer {
+                            ui.horizontal(|ui| {
+                                ui.label("Stopped by the sleep timer");
+                                toggle_playing |= !playing && ui.button("Resume").clicked();
+                            });
+                        }
+                    });
+                }
+
+                if nudge_by != 0.0 {
+                    let to_samples = |ms: f64| (ms * player.sample_rate() as f64 / 1000.0).round();
+                    let offset = to_samples(nudge_ms + nudge_by) - to_samples(nudge_ms);
+                    nudge_ms += nudge_by;
+                    player.nudge_phase(offset as isize);
+                }
+
+                if let Some(device) = switch_device {
+                    match open_player(&config, device.as_deref()) {
+                        Ok(new_player) => {
+                            // Dropping the old player fades its stream out and stops it
+                            player = new_player;
+                            player.set_volume_db(volume_db);
+                            paused = false;
+                            output_device_error = None;
+                            channel_gains = vec![1.0; player.num_channels()];
+                            streak = PracticeStreak::new(player.sample_rate());
+                            // The sample rate may differ, everything is rendered again for it
+                            for file in sample_files.iter_mut().flatten() {
+                                *file = SampleFile::open(file.path.clone(), player.sample_rate());
+                            }
+                            samples_changed = true;
+                            reschedule = true;
+                            update_reference = true;
+                            last_layers.clear();
+                            output_device = device;
+                            config.device = output_device.clone();
+                            config_changed = Some(Instant::now());
+                        }
+                        Err(e) => output_device_error = Some(e.to_string()),
+                    }
+                }
+
+                let following_clock = config.midi_clock_start && midi_input.is_some();
+                if toggle_playing && !playing && (following_clock || waiting_for_downbeat) {
+                    waiting_for_downbeat = !waiting_for_downbeat;
+                    toggle_playing = false;
+                }
+                if waiting_for_downbeat {
+                    let numerator = patterns[active_pattern].numerator;
+                    let downbeat = midi_input
+                        .as_ref()
+                        .and_then(|input| input.clock.next_bar(numerator));
+                    if let Some(downbeat) = downbeat {
+                        waiting_for_downbeat = false;
+                        pending_start = Some(downbeat);
+                        toggle_playing = true;
+                    }
+                }
+
+                if toggle_playing {
+                    playing = !playing;
+                    if paused {
+                        paused = false;
+                        player.set_playing(true);
+                    }
+                    if playing {
+                        if tempo_trainer.enabled && !tempo_locked {
+                            bpm = tempo_trainer.restart();
+                        }
+                        reschedule = true;
+                        count_in_pending = true;
+                        update_reference = true;
+                        last_layers.clear();
+                    } else {
+                        player.clear_playbacks();
+                        player.stop_track();
+                        count_drill.cancel();
+                        calibration.cancel();
+                    }
+                }
 
-                            Playback::new(sample)
-                                .offset(i * subdiv_duration)
-                                .repeat(bar_duration, None)
-                        })
-                        .collect(),
-                );
+                if update_reference {
+                    player.remove_playbacks_by_tag(tags::REFERENCE);
+                    if reference_on && playing {
+                        let tone = click::sustain_tone(player.sample_rate(), reference_freq, 0.3);
+                        player.add_playbacks(vec![Playback::new(Arc::new(tone))
+                            .tag(tags::REFERENCE)
+                            .loop_sustain()]);
+                    }
+                }
+
+                if watch_samples {
+                    for file in sample_files.iter_mut().flatten() {
+                        samples_changed |= file.poll(player.sample_rate());
+                    }
+                    ui.ctx().request_repaint_after(Duration::from_millis(500));
+                }
+                if last_sound != (click_shape, timbres) || samples_changed {
+                    last_sound = (click_shape, timbres);
+                    sounds = Sounds::new(player.sample_rate(), click_shape, timbres);
+                    // A failed reload keeps the last version of the file that loaded
+                    let custom = [&mut sounds.hi, &mut sounds.mid, &mut sounds.lo];
+                    for (sound, file) in custom.into_iter().zip(&sample_files) {
+                        if let Some(samples) = file.as_ref().and_then(|f| f.samples.clone()) {
+                            *sound = samples;
+                        }
+                    }
+                    if patterns.iter().any(|p| p.melodic || p.echo_feedback > 0.0) {
+                        // Melodic clicks and echoes aren't tagged, so they need rescheduling
+                        reschedule = true;
+                    } else {
+                        for (tag, samples) in sounds.tagged() {
+                            player.set_samples(tag, samples);
+                        }
+                    }
+                }
+
+                let preset = load_preset
+                    .and_then(|i| config.presets.get(i))
+                    .filter(|_| !tempo_locked);
+                if let Some(preset) = preset {
+                    bpm = preset.bpm.clamp(*BPM_RANGE.start(), *BPM_RANGE.end());
+                    let pattern = &mut patterns[active_pattern];
+                    pattern.set_meter(preset.numerator, preset.subdivision);
+                    pattern.accents = preset.accents.clone();
+                    pattern.muted = config.muted_for(pattern.numerator, pattern.subdivision);
+                    // So the accents saved for the meter don't replace the preset's below
+                    accent_meters[active_pattern] = (pattern.numerator, pattern.subdivision);
+                    let max = if boost_confirmed {
+                        *volume_range.end()
+                    } else {
+                        volume_range.end().min(0.0)
+                    };
+                    volume_db = preset.volume_db.clamp(*volume_range.start(), max);
+                    player.set_volume_db(volume_db);
+                }
+
+                for (pattern, meter) in patterns.iter_mut().zip(accent_meters.iter_mut()) {
+                    if *meter != (pattern.numerator, pattern.subdivision) {
+                        *meter = (pattern.numerator, pattern.subdivision);
+                        pattern.accents =
+                            config.accents_for(pattern.numerator, pattern.subdivision);
+                        pattern.muted = config.muted_for(pattern.numerator, pattern.subdivision);
+                    }
+                }
+
+                let layer_state: Vec<_> = layers
+                    .iter()
+                    .map(|l| (l.id, l.bpm, l.numerator, l.accents.clone()))
+                    .collect();
+                if playing && layer_state != last_layers {
+                    // Only layers that changed restart, the others keep their phase
+                    for state in &layer_state {
+                        if !last_layers.contains(state) {
+                            let (id, layer_bpm, numerator, accents) = state.clone();
+                            let pattern = layer_pattern(numerator, accents);
+                            let schedule =
+                                pattern.schedule(layer_bpm, player.sample_rate(), &mut sounds);
+                            player.replace_layer(id, schedule.playbacks);
+                        }
+                    }
+                    last_layers = layer_state;
+                }
+
+                let new_state = (bpm, patterns.clone(), active_pattern, alternate_every);
+                if playing && (reschedule || last_state != new_state) {
+                    stopped_by_sleep_timer = false;
+                    if count_drill.is_running() {
+                        count_drill.cancel();
+                    }
+                    if calibration.is_running() {
+                        calibration.cancel();
+                    }
+                    // Switching between A and B alone waits for the bar to end, anything else
+                    // restarts the pattern right away.
+                    let mut switched = last_state.clone();
+                    switched.2 = active_pattern;
+                    // A backing track restarts the click with it
+                    // So does a tempo ratio, keeping the downbeats in phase across the change
+                    // The trainer's steps wait for the bar line too
+                    let at_next_bar =
+                        (switched == new_state || ratio_applied || trainer_step.is_some())
+                            && pending_track.is_none()
+                            && pending_start.is_none();
+
+                    last_state = new_state;
+
+                    let sample_rate = player.sample_rate();
+                    let schedule = if alternate_every > 0 {
+                        Schedule::alternate(
+                            &patterns[0].schedule(bpm, sample_rate, &mut sounds),
+                            &patterns[1].schedule(bpm, sample_rate, &mut sounds),
+                            alternate_every,
+                        )
+                    } else {
+                        patterns[active_pattern].schedule(bpm, sample_rate, &mut sounds)
+                    };
+                    let bar_duration = schedule.bar_duration;
+                    scheduled = (schedule.playbacks.clone(), bar_duration.round() as usize);
+                    // The first bar, which is A's when alternating
+                    let first_bar = schedule.bar_lengths[0];
+                    let mut playbacks = schedule.playbacks;
+                    let pattern = &patterns[active_pattern];
+                    let count_in_beats = config.count_in_bars.min(2) * pattern.numerator;
+                    // Samples before the pattern starts, whole bars so the downbeats stay on the bar lines
+                    let mut count_in = 0;
+                    if std::mem::take(&mut count_in_pending) && count_in_beats > 0 {
+                        let beat = pattern.beat_duration(bpm, sample_rate);
+                        count_in = (beat * count_in_beats as f64).round() as usize;
+                        for playback in &mut playbacks {
+                            playback.start += count_in;
+                        }
+                        playbacks.push(
+                            Playback::new(sounds.hi.clone())
+                                .tag(tags::HI)
+                                .repeat_exact(beat, Some(count_in_beats - 1)),
+                        );
+                    }
+                    player.replace_playbacks_with_count_in(
+                        playbacks,
+                        schedule.bar_lengths,
+                        at_next_bar,
+                        pending_start.take(),
+                        count_in,
+                    );
+                    if let Some(track) = pending_track.take() {
+                        let track_count_in = if track_count_in { first_bar } else { 0.0 };
+                        player.play_track(track, count_in + track_count_in.round() as usize);
+                    }
+
+                    if let Some(remote) = &remote {
+                        let pattern = &patterns[active_pattern];
+                        remote.update(|state| {
+                            state.bpm = bpm;
+                            state.numerator = pattern.numerator;
+                            state.subdivision = pattern.subdivision;
+                        });
+                    }
+                }
+                if let Some(remote) = &remote {
+                    remote.update(|state| {
+                        state.volume_db = volume_db;
+                        state.transport = match (playing, paused) {
+                            (false, _) => Transport::Stopped,
+                            (true, false) => Transport::Playing,
+                            (true, true) => Transport::Paused,
+                        };
+                    });
+                }
+            });
+        if let Some(size) = resize_to {
+            frame.set_window_size(size);
+            config_changed = Some(Instant::now());
+        }
+
+        // The tempo, meter and volume the app was left with are the ones it starts with next time.
+        // Checked last, so the changes made during this frame are saved with them.
+        let pattern = &patterns[active_pattern];
+        if config.set_startup(bpm, pattern.numerator, pattern.subdivision, volume_db) {
+            config_changed = Some(Instant::now());
+        }
+        if let Some(changed) = config_changed {
+            if changed.elapsed() >= Duration::from_secs(1) {
+                config_changed = None;
+                save_on_exit.set(None);
+                save_config(&config);
+            } else {
+                save_on_exit.set(Some(config.clone()));
+                ctx.request_repaint_after(Duration::from_millis(250));
             }
-        });
+        }
     })
     .unwrap();
 
     Ok(())
 }
 
-struct TapTempo {
-    taps: Vec<f32>,
-    last: Instant,
+/// Writes the config file, reporting a failure on the terminal
+fn save_config(config: &Config) {
+    if let Err(e) = config.save() {
+        eprintln!("Failed to save config: {}", e);
+    }
 }
-impl TapTempo {
-    pub fn new() -> TapTempo {
-        TapTempo {
-            taps: Vec::new(),
-            last: Instant::now(),
+
+/// Holds config changes that haven't been saved yet, writing them when the window closes
+struct SaveOnExit(Option<Config>);
+
+impl SaveOnExit {
+    fn set(&mut self, unsaved: Option<Config>) {
+        self.0 = unsaved;
+    }
+}
+
+impl Drop for SaveOnExit {
+    fn drop(&mut self) {
+        if let Some(config) = self.0.take() {
+            save_config(&config);
         }
     }
+}
 
-    pub fn tap(&mut self) -> Option<f32> {
-        let now = Instant::now();
-        let duration = (now - self.last).as_secs_f32();
-        self.last = now;
+/// An extra metronome running alongside the main one, e.g. at another tempo
+struct ClickLayer {
+    id: u64,
+    name: String,
+    bpm: f32,
+    numerator: usize,
+    /// Accent per beat, kept as long as the numerator
+    accents: Vec<AccentLevel>,
+    volume_db: f32,
+    muted: bool,
+    solo: bool,
+}
 
-        if self
-            .taps
-            .last()
-            .map_or(false, |&v| v < duration * 0.5 || v > duration * 2.0)
-        {
-            self.taps.clear();
-            None
-        } else {
-            self.taps.push(duration);
+/// The pattern a click layer plays, plain beats with their accents
+fn layer_pattern(numerator: usize, accents: Vec<AccentLevel>) -> Pattern {
+    Pattern {
+        numerator,
+        accents,
+        ..Pattern::default()
+    }
+}
 
-            let mean = geometric_mean(self.taps.iter().copied());
-            Some(60.0 / mean)
+/// What an incoming MIDI message can be mapped to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum MidiAction {
+    StartStop,
+    TapTempo,
+}
+
+impl MidiAction {
+    fn name(self) -> &'static str {
+        match self {
+            MidiAction::StartStop => "Start/stop",
+            MidiAction::TapTempo => "Tap tempo",
         }
     }
 }
 
-fn geometric_mean(values: impl Iterator<Item = f32>) -> f32 {
-    let mut n = 0;
+/// Opens the output device called `device` or the default one, with the output settings
+/// from `config` applied
+fn open_player(config: &Config, device: Option<&str>) -> anyhow::Result<player::Player> {
+    let channels = if config.mono_output {
+        Some(1)
+    } else {
+        config.output_channels
+    };
+    let mut player = player::Player::with_sink(CpalSink::open(device, None, channels)?)?;
+    apply_output_config(&mut player, config);
+    Ok(player)
+}
 
-    values
-        .fold(1.0, |a, b| {
-            println!("{} {}", a, b);
-            n += 1;
-            a * b
-        })
-        .powf(1.0 / n as f32)
+/// Applies the output settings from `config` that shape the mix
+fn apply_output_config<S: AudioSink>(player: &mut player::Player<S>, config: &Config) {
+    player.set_mono(config.mono_output);
+    player.set_overlap_protection(config.overlap_protection);
+    player.set_max_voices(config.max_voices);
+    player.bypass_limiter(config.bypass_limiter);
+    player.set_volume_range_db(config.volume_range_db());
+}
+
+/// Initial settings. Each is taken from the first of these that sets it:
+/// command line arguments (`--bpm 140`), environment variables (`METRONOME_BPM=140`),
+/// the config file, and finally the built-in defaults.
+struct Startup {
+    bpm: f32,
+    numerator: usize,
+    subdivision: usize,
+    /// As given, `clamped_volume_db` fits it into the configured range
+    volume_db: f32,
+    device: Option<String>,
+    /// Play from the terminal without opening a window. Set by `--headless`, and by giving
+    /// any of `PLAYBACK_ARGUMENTS` on the command line unless `--gui` asks for the window.
+    /// Builds without the `gui` feature always play from the terminal.
+    #[cfg(feature = "gui")]
+    headless: bool,
 }
 
-fn generate_click(sample_rate: usize, duration: Duration, freq: f32, gain: f32) -> Vec<f32> {
-    let freq = freq as f64;
-    let gain = gain as f64;
-    let duration = duration.as_secs_f64();
+impl Startup {
+    fn resolve(config: &Config) -> Startup {
+        let args = command_line_settings();
+        let defaults = Pattern::default();
+        // `--meter` and `--numerator` on the command line both beat the environment, so the
+        // meter is resolved one source at a time rather than one setting at a time
+        let arg_meter: Option<Meter> = argument(&args, "meter");
+        let env_meter: Option<Meter> = env_setting("meter");
+        Startup {
+            bpm: setting(&args, "bpm")
+                .or(config.bpm)
+                .unwrap_or(120.0)
+                .clamp(*BPM_RANGE.start(), *BPM_RANGE.end()),
+            numerator: argument(&args, "numerator")
+                .or(arg_meter.map(|m| m.0))
+                .or_else(|| env_setting("numerator"))
+                .or(env_meter.map(|m| m.0))
+                .or(config.numerator)
+                .unwrap_or(defaults.numerator)
+                .min(32),
+            subdivision: argument(&args, "subdivision")
+                .or(arg_meter.map(|m| m.1))
+                .or_else(|| env_setting("subdivision"))
+                .or(env_meter.map(|m| m.1))
+                .or(config.subdivision)
+                .unwrap_or(defaults.subdivision)
+                .max(1),
+            volume_db: setting(&args, "volume_db")
+                .or(config.volume_db)
+                .unwrap_or(0.0),
+            device: setting(&args, "device").or(config.device.clone()),
+            #[cfg(feature = "gui")]
+            headless: args.contains_key("headless")
+                || (!args.contains_key("gui")
+                    && PLAYBACK_ARGUMENTS
+                        .iter()
+                        .any(|&name| args.contains_key(name))),
+        }
+    }
+
+    /// The startup volume within the configured range
+    fn clamped_volume_db(&self, config: &Config) -> f32 {
+        let range = config.volume_range_db();
+        self.volume_db.clamp(*range.start(), *range.end())
+    }
+
+    /// The pattern to start with, with the accents saved for its meter
+    fn pattern(&self, config: &Config) -> Pattern {
+        Pattern {
+            numerator: self.numerator,
+            subdivision: self.subdivision,
+            accents: config.accents_for(self.numerator, self.subdivision),
+            muted: config.muted_for(self.numerator, self.subdivision),
+            ..Pattern::default()
+        }
+    }
+}
 
-    let n = (duration * sample_rate as f64) as usize;
-    let mut result = Vec::with_capacity(n);
+/// A time signature like "7/8" given with `--meter`
+#[derive(Clone, Copy)]
+struct Meter(usize, usize);
 
-    let minimum_volume = 0.01f64;
-    let decay_factor = minimum_volume.powf(1.0 / n as f64);
+impl FromStr for Meter {
+    type Err = ();
 
-    let mut envelope = 1.0;
-    for i in 0..n {
-        let w = (TAU * i as f64) / sample_rate as f64;
+    fn from_str(s: &str) -> Result<Meter, ()> {
+        let (numerator, denominator) = s.split_once('/').ok_or(())?;
+        let numerator = numerator.trim().parse().map_err(|_| ())?;
+        let denominator = denominator.trim().parse().map_err(|_| ())?;
+        Ok(Meter(numerator, denominator))
+    }
+}
 
-        let sine_wave = (w * freq).sin();
-        result.push((gain * envelope * sine_wave) as f32);
+/// Arguments that are given without a value
+const COMMAND_LINE_FLAGS: [&str; 2] = ["headless", "gui"];
+/// Arguments that set up what plays, `metronome --bpm 120 --meter 4/4` plays right away
+const PLAYBACK_ARGUMENTS: [&str; 6] = [
+    "bpm",
+    "meter",
+    "numerator",
+    "subdivision",
+    "volume_db",
+    "device",
+];
 
-        envelope *= decay_factor;
+/// `--name value` and `--name=value` pairs from the command line, with dashes in the name
+/// changed to underscores. Flags like `--headless` map to an empty value.
+fn command_line_settings() -> HashMap<String, String> {
+    let mut settings = HashMap::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(arg) = arg.strip_prefix("--") else {
+            eprintln!("Ignoring unexpected argument '{}'", arg);
+            continue;
+        };
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None if COMMAND_LINE_FLAGS.contains(&arg) => (arg.to_string(), Some(String::new())),
+            None => (arg.to_string(), args.next()),
+        };
+        match value {
+            Some(value) => {
+                settings.insert(name.replace('-', "_"), value);
+            }
+            None => eprintln!("Missing value for --{}", name),
+        }
     }
+    settings
+}
+
+/// The setting `name` from the command line, else from `METRONOME_<NAME>`.
+/// Values that don't parse are reported and skipped.
+fn setting<T: FromStr>(args: &HashMap<String, String>, name: &str) -> Option<T> {
+    argument(args, name).or_else(|| env_setting(name))
+}
+
+/// The setting `name` from the command line only
+fn argument<T: FromStr>(args: &HashMap<String, String>, name: &str) -> Option<T> {
+    parse_setting(&format!("--{}", name.replace('_', "-")), args.get(name)?)
+}
+
+/// The setting `name` from `METRONOME_<NAME>` only
+fn env_setting<T: FromStr>(name: &str) -> Option<T> {
+    let env_name = format!("METRONOME_{}", name.to_uppercase());
+    parse_setting(&env_name, &std::env::var(&env_name).ok()?)
+}
 
-    result
+/// `value` parsed, or `None` with a message naming where it came from
+fn parse_setting<T: FromStr>(source: &str, value: &str) -> Option<T> {
+    value
+        .parse()
+        .map_err(|_| eprintln!("Ignoring invalid {} '{}'", source, value))
+        .ok()
+}
+
+/// The next time the system clock is at a whole multiple of `secs` seconds
+fn next_clock_boundary(secs: u64) -> Instant {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let period = Duration::from_secs(secs.max(1));
+    let into_period = Duration::from_nanos((since_epoch.as_nanos() % period.as_nanos()) as u64);
+    Instant::now() + (period - into_period)
+}
+
+/// Whether `key` was pressed this frame, ignoring key-repeats and keys typed into text fields
+#[cfg(feature = "gui")]
+fn key_tapped(ctx: &egui::Context, key: egui::Key) -> bool {
+    !ctx.wants_keyboard_input()
+        && ctx.input(|i| {
+            i.events.iter().any(|e| {
+                matches!(e, egui::Event::Key { key: k, pressed: true, repeat: false, .. } if *k == key)
+            })
+        })
 }
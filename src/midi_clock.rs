@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+/// MIDI clock sends this many ticks per quarter note
+const TICKS_PER_BEAT: u64 = 24;
+/// Weight of the newest tick in the smoothed tick period, the rest is the running average
+const SMOOTHING: f64 = 0.1;
+
+/// A realtime message from a device sending MIDI clock
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ClockEvent {
+    Tick(Instant),
+    /// Start from the top, the next tick is the first downbeat
+    Start,
+    /// Carry on from the song position last set
+    Continue,
+    Stop,
+    /// Song position in sixteenth notes, sent before continuing from the middle of a song
+    SongPosition(u16),
+}
+
+impl ClockEvent {
+    pub fn from_message(message: &[u8], at: Instant) -> Option<ClockEvent> {
+        match *message {
+            [0xF8] => Some(ClockEvent::Tick(at)),
+            [0xFA] => Some(ClockEvent::Start),
+            [0xFB] => Some(ClockEvent::Continue),
+            [0xFC] => Some(ClockEvent::Stop),
+            [0xF2, lsb, msb] => Some(ClockEvent::SongPosition(
+                (lsb as u16 & 0x7F) | (msb as u16 & 0x7F) << 7,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Follows the transport and phase of an external MIDI clock
+#[derive(Default)]
+pub struct MidiClock {
+    running: bool,
+    /// Ticks since the start of the song, the next tick to arrive has this index
+    ticks: u64,
+    last_tick: Option<Instant>,
+    tick_period: Option<Duration>,
+}
+
+impl MidiClock {
+    pub fn receive(&mut self, event: ClockEvent) {
+        match event {
+            ClockEvent::Tick(at) => {
+                if let Some(last) = self.last_tick {
+                    let period = at.saturating_duration_since(last);
+                    self.tick_period = Some(match self.tick_period {
+                        Some(smoothed) => {
+                            smoothed.mul_f64(1.0 - SMOOTHING) + period.mul_f64(SMOOTHING)
+                        }
+                        None => period,
+                    });
+                }
+                self.last_tick = Some(at);
+                if self.running {
+                    self.ticks += 1;
+                }
+            }
+            ClockEvent::Start => {
+                self.running = true;
+                self.ticks = 0;
+            }
+            ClockEvent::Continue => self.running = true,
+            ClockEvent::Stop => self.running = false,
+            ClockEvent::SongPosition(sixteenths) => {
+                self.ticks = sixteenths as u64 * TICKS_PER_BEAT / 4;
+            }
+        }
+    }
+
+    /// Whether the sending device is playing
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    /// The tempo of the clock once two ticks arrived, also while stopped
+    pub fn bpm(&self) -> Option<f32> {
+        let period = self.tick_period.filter(|p| !p.is_zero())?;
+        Some((60.0 / (period.as_secs_f64() * TICKS_PER_BEAT as f64)) as f32)
+    }
+
+    /// When the next bar of `numerator` quarter notes starts on the sender's side,
+    /// extrapolated from the last tick. None while stopped or before the tempo is known.
+    pub fn next_bar(&self, numerator: usize) -> Option<Instant> {
+        if !self.running {
+            return None;
+        }
+        let period = self.tick_period?;
+        let last_tick = self.last_tick?;
+        let bar = TICKS_PER_BEAT * numerator.max(1) as u64;
+        let next_bar = self.ticks.div_ceil(bar) * bar;
+        // The last tick that arrived had index `ticks - 1`
+        let ticks_ahead = (next_bar + 1).saturating_sub(self.ticks) as u32;
+        Some(last_tick + period * ticks_ahead)
+    }
+}
@@ -0,0 +1,270 @@
+use std::path::Path;
+use std::time::Instant;
+
+use crate::pattern::{MAX_BEATS, SUBDIVISIONS};
+
+/// A stretch of a MIDI file with constant tempo and meter
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Section {
+    /// Start of the section in seconds from the beginning of the file
+    pub start: f64,
+    /// Quarter notes per minute
+    pub bpm: f32,
+    pub numerator: usize,
+    pub denominator: usize,
+}
+
+/// Tempo and time signature changes of a standard MIDI file, sorted by start time.
+/// Always contains at least one section starting at 0.
+pub struct TempoMap {
+    pub sections: Vec<Section>,
+}
+
+impl TempoMap {
+    pub fn load(path: &Path) -> anyhow::Result<TempoMap> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<TempoMap> {
+        let mut reader = Reader { data, pos: 0 };
+
+        let (header, header_len) = reader.chunk_header()?;
+        if header != *b"MThd" || header_len < 6 {
+            anyhow::bail!("Not a standard MIDI file");
+        }
+        let _format = reader.u16()?;
+        let num_tracks = reader.u16()?;
+        let division = reader.u16()?;
+        reader.skip(header_len - 6)?;
+        if division & 0x8000 != 0 {
+            anyhow::bail!("SMPTE time division is not supported");
+        }
+        if division == 0 {
+            anyhow::bail!("MIDI file without ticks per quarter note");
+        }
+        let ticks_per_quarter = division as u64;
+
+        let mut events = Vec::new();
+        for _ in 0..num_tracks {
+            let (id, len) = reader.chunk_header()?;
+            let track = reader.take(len)?;
+            if id == *b"MTrk" {
+                read_track_meta_events(track, &mut events)?;
+            }
+        }
+        // Stable, so events at the same tick keep their file order
+        events.sort_by_key(|e| e.tick);
+
+        Ok(Self::from_events(&events, ticks_per_quarter))
+    }
+
+    fn from_events(events: &[MetaEvent], ticks_per_quarter: u64) -> TempoMap {
+        let mut current = Section {
+            start: 0.0,
+            bpm: 120.0,
+            numerator: 4,
+            denominator: 4,
+        };
+        let mut sections = vec![current];
+        let mut last_tick = 0u64;
+
+        for event in events {
+            // Tempo automation is approximated by snapping every change to the beat grid,
+            // so there is at most one tempo update per beat.
+            let beat_ticks = (ticks_per_quarter * 4 / current.denominator as u64).max(1);
+            let tick = event.tick / beat_ticks * beat_ticks;
+
+            if tick > last_tick {
+                let quarters = (tick - last_tick) as f64 / ticks_per_quarter as f64;
+                current.start += quarters * 60.0 / current.bpm as f64;
+                last_tick = tick;
+            }
+
+            match event.kind {
+                MetaKind::Tempo(micros_per_quarter) => {
+                    current.bpm = 60_000_000.0 / micros_per_quarter.max(1) as f32;
+                }
+                MetaKind::TimeSignature(numerator, denominator) => {
+                    (current.numerator, current.denominator) =
+                        supported_meter(numerator, denominator);
+                }
+            }
+
+            match sections.last_mut() {
+                Some(last) if last.start == current.start => *last = current,
+                _ => sections.push(current),
+            }
+        }
+
+        TempoMap { sections }
+    }
+
+    /// The section that is active `seconds` into the file
+    pub fn section_at(&self, seconds: f64) -> &Section {
+        let i = self.sections.partition_point(|s| s.start <= seconds);
+        &self.sections[i.saturating_sub(1)]
+    }
+}
+
+/// The nearest time signature the metronome plays, e.g. 2/2 as 4/4. Half and whole notes are
+/// split into quarters, keeping the bar length. The rest is clamped to `MAX_BEATS` beats of
+/// 32nd notes at the most.
+fn supported_meter(numerator: usize, denominator: usize) -> (usize, usize) {
+    let (mut numerator, mut denominator) = (numerator.max(1), denominator.max(1));
+    while denominator < SUBDIVISIONS[0] {
+        numerator *= 2;
+        denominator *= 2;
+    }
+    (
+        numerator.min(MAX_BEATS),
+        denominator.min(SUBDIVISIONS[SUBDIVISIONS.len() - 1]),
+    )
+}
+
+/// Transport following a tempo map in real time
+pub struct TempoMapFollower {
+    pub map: TempoMap,
+    started: Option<Instant>,
+}
+
+impl TempoMapFollower {
+    pub fn new(map: TempoMap) -> TempoMapFollower {
+        TempoMapFollower { map, started: None }
+    }
+
+    pub fn start(&mut self) {
+        self.started = Some(Instant::now());
+    }
+
+    pub fn stop(&mut self) {
+        self.started = None;
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// The section to play right now, if following
+    pub fn current(&self) -> Option<&Section> {
+        self.started
+            .map(|started| self.map.section_at(started.elapsed().as_secs_f64()))
+    }
+}
+
+enum MetaKind {
+    Tempo(u32),
+    TimeSignature(usize, usize),
+}
+
+struct MetaEvent {
+    tick: u64,
+    kind: MetaKind,
+}
+
+fn read_track_meta_events(track: &[u8], events: &mut Vec<MetaEvent>) -> anyhow::Result<()> {
+    let mut reader = Reader {
+        data: track,
+        pos: 0,
+    };
+    let mut tick = 0u64;
+    let mut running_status = 0u8;
+
+    while !reader.is_empty() {
+        tick += reader.varint()? as u64;
+
+        let mut status = reader.u8()?;
+        if status < 0x80 {
+            // Running status: the byte we just read is already the first data byte
+            reader.pos -= 1;
+            status = running_status;
+        }
+
+        match status {
+            0xFF => {
+                let kind = reader.u8()?;
+                let len = reader.varint()? as usize;
+                let data = reader.take(len)?;
+                match (kind, data) {
+                    (0x51, &[a, b, c]) => events.push(MetaEvent {
+                        tick,
+                        kind: MetaKind::Tempo(u32::from_be_bytes([0, a, b, c])),
+                    }),
+                    (0x58, &[numerator, denominator_pow, ..]) if denominator_pow < 8 => events
+                        .push(MetaEvent {
+                            tick,
+                            kind: MetaKind::TimeSignature(numerator as usize, 1 << denominator_pow),
+                        }),
+                    (0x2F, _) => break,
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                let len = reader.varint()? as usize;
+                reader.skip(len)?;
+            }
+            0x80..=0xEF => {
+                running_status = status;
+                let data_len = match status & 0xF0 {
+                    0xC0 | 0xD0 => 1,
+                    _ => 2,
+                };
+                reader.skip(data_len)?;
+            }
+            _ => anyhow::bail!("Unexpected MIDI status byte {:#x}", status),
+        }
+    }
+
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self.pos.checked_add(len);
+        let bytes = end
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or(anyhow::anyhow!("Unexpected end of MIDI data"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> anyhow::Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn chunk_header(&mut self) -> anyhow::Result<([u8; 4], usize)> {
+        let b = self.take(8)?;
+        let id = [b[0], b[1], b[2], b[3]];
+        let len = u32::from_be_bytes([b[4], b[5], b[6], b[7]]) as usize;
+        Ok((id, len))
+    }
+
+    fn varint(&mut self) -> anyhow::Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            value = (value << 7) | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        anyhow::bail!("Invalid variable length quantity in MIDI data")
+    }
+}
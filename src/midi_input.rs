@@ -0,0 +1,131 @@
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::midi_clock::{ClockEvent, MidiClock};
+
+/// Repeats of the same trigger within this time are ignored, footswitches tend to bounce
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// An incoming MIDI message that can be mapped to an action
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Trigger {
+    Note { channel: u8, note: u8 },
+    ControlChange { channel: u8, controller: u8 },
+}
+
+impl Trigger {
+    /// The trigger a message fires: note-ons, and controller values from 64 up
+    /// (a footswitch being pressed). Releases and everything else are ignored.
+    fn from_message(message: &[u8]) -> Option<Trigger> {
+        let &[status, data1, data2] = message else {
+            return None;
+        };
+        let channel = status & 0x0F;
+        match status & 0xF0 {
+            0x90 if data2 > 0 => Some(Trigger::Note {
+                channel,
+                note: data1,
+            }),
+            0xB0 if data2 >= 64 => Some(Trigger::ControlChange {
+                channel,
+                controller: data1,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Trigger::Note { channel, note } => format!("note {} (ch {})", note, channel + 1),
+            Trigger::ControlChange {
+                channel,
+                controller,
+            } => format!("CC {} (ch {})", controller, channel + 1),
+        }
+    }
+}
+
+/// A connection to a MIDI input port, collecting triggers for the UI to poll
+pub struct MidiInput {
+    pub port_name: String,
+    /// Follows the clock the port sends, if any
+    pub clock: MidiClock,
+    triggers: Receiver<(Trigger, Instant)>,
+    clock_events: Receiver<ClockEvent>,
+    last_fired: HashMap<Trigger, Instant>,
+    _connection: midir::MidiInputConnection<()>,
+}
+
+impl MidiInput {
+    /// Names of the available input ports
+    pub fn ports() -> Vec<String> {
+        let Ok(input) = midir::MidiInput::new("metronome") else {
+            return Vec::new();
+        };
+        input
+            .ports()
+            .iter()
+            .filter_map(|port| input.port_name(port).ok())
+            .collect()
+    }
+
+    pub fn connect(port_name: &str) -> anyhow::Result<MidiInput> {
+        let input = midir::MidiInput::new("metronome")?;
+        let port = input
+            .ports()
+            .into_iter()
+            .find(|port| input.port_name(port).is_ok_and(|name| name == port_name))
+            .ok_or(anyhow::anyhow!("No MIDI input called '{}'", port_name))?;
+
+        let (send, triggers) = mpsc::channel();
+        let (send_clock, clock_events) = mpsc::channel();
+        let connection = input
+            .connect(
+                &port,
+                "metronome-input",
+                move |_timestamp, message, _| {
+                    // The UI is gone if sending fails
+                    if let Some(trigger) = Trigger::from_message(message) {
+                        let _ = send.send((trigger, Instant::now()));
+                    } else if let Some(event) = ClockEvent::from_message(message, Instant::now()) {
+                        let _ = send_clock.send(event);
+                    }
+                },
+                (),
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to connect to '{}': {}", port_name, e))?;
+
+        Ok(MidiInput {
+            port_name: port_name.to_string(),
+            clock: MidiClock::default(),
+            triggers,
+            clock_events,
+            last_fired: HashMap::new(),
+            _connection: connection,
+        })
+    }
+
+    /// The triggers received since the last call, debounced. Also updates `clock`.
+    pub fn poll(&mut self) -> Vec<Trigger> {
+        for event in self.clock_events.try_iter() {
+            self.clock.receive(event);
+        }
+        let mut fired = Vec::new();
+        for (trigger, at) in self.triggers.try_iter() {
+            let bounced = self
+                .last_fired
+                .get(&trigger)
+                .is_some_and(|&last| at.duration_since(last) < DEBOUNCE);
+            if !bounced {
+                self.last_fired.insert(trigger, at);
+                fired.push(trigger);
+            }
+        }
+        fired
+    }
+}
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+/// The messages tempo and position are sent as, for controllers that display them
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeedbackMapping {
+    /// 0-based MIDI channel
+    pub channel: u8,
+    /// NRPN parameter carrying the tempo in tenths of a BPM, e.g. 1200 for 120 BPM
+    pub tempo_nrpn: u16,
+    /// Controller set to the 1-based beat of the bar on every beat
+    pub beat_cc: u8,
+    /// Controller set to the bar number, wrapping around after 127
+    pub bar_cc: u8,
+}
+
+impl Default for FeedbackMapping {
+    fn default() -> Self {
+        FeedbackMapping {
+            channel: 0,
+            tempo_nrpn: 0,
+            // General purpose controllers 5 and 6, unused by most instruments
+            beat_cc: 80,
+            bar_cc: 81,
+        }
+    }
+}
+
+/// A connection to a MIDI output port, reporting tempo and beat position to a controller
+pub struct MidiFeedback {
+    pub port_name: String,
+    connection: midir::MidiOutputConnection,
+    /// The last tempo and beat sent, to only send changes
+    sent_bpm: Option<u16>,
+    sent_beat: Option<i64>,
+}
+
+impl MidiFeedback {
+    /// Names of the available output ports
+    pub fn ports() -> Vec<String> {
+        let Ok(output) = midir::MidiOutput::new("metronome") else {
+            return Vec::new();
+        };
+        output
+            .ports()
+            .iter()
+            .filter_map(|port| output.port_name(port).ok())
+            .collect()
+    }
+
+    pub fn connect(port_name: &str) -> anyhow::Result<MidiFeedback> {
+        let output = midir::MidiOutput::new("metronome")?;
+        let port = output
+            .ports()
+            .into_iter()
+            .find(|port| output.port_name(port).is_ok_and(|name| name == port_name))
+            .ok_or(anyhow::anyhow!("No MIDI output called '{}'", port_name))?;
+        let connection = output
+            .connect(&port, "metronome-feedback")
+            .map_err(|e| anyhow::anyhow!("Failed to connect to '{}': {}", port_name, e))?;
+
+        Ok(MidiFeedback {
+            port_name: port_name.to_string(),
+            connection,
+            sent_bpm: None,
+            sent_beat: None,
+        })
+    }
+
+    fn control_change(&mut self, channel: u8, controller: u8, value: u8) {
+        let message = [0xB0 | (channel & 0x0F), controller & 0x7F, value & 0x7F];
+        if let Err(e) = self.connection.send(&message) {
+            eprintln!("Failed to send MIDI feedback: {}", e);
+        }
+    }
+
+    /// Sends the tempo if it changed, and the position whenever a new beat starts.
+    /// `beats` counts the beats since the pattern started, None while stopped.
+    pub fn update(
+        &mut self,
+        mapping: &FeedbackMapping,
+        bpm: f32,
+        beats: Option<f64>,
+        numerator: usize,
+    ) {
+        let tenths = (bpm * 10.0).round().clamp(0.0, 16383.0) as u16;
+        if self.sent_bpm != Some(tenths) {
+            self.sent_bpm = Some(tenths);
+            let channel = mapping.channel;
+            let [parameter, value] = [mapping.tempo_nrpn, tenths].map(|v| (v >> 7, v & 0x7F));
+            self.control_change(channel, 99, parameter.0 as u8);
+            self.control_change(channel, 98, parameter.1 as u8);
+            self.control_change(channel, 6, value.0 as u8);
+            self.control_change(channel, 38, value.1 as u8);
+        }
+
+        let beat = beats.map(|b| b.floor() as i64).filter(|&b| b >= 0);
+        if beat != self.sent_beat {
+            self.sent_beat = beat;
+            if let Some(beat) = beat {
+                let numerator = numerator.max(1) as i64;
+                let beat_in_bar = (beat % numerator + 1).min(127) as u8;
+                let bar = ((beat / numerator) % 128) as u8;
+                self.control_change(mapping.channel, mapping.beat_cc, beat_in_bar);
+                self.control_change(mapping.channel, mapping.bar_cc, bar);
+            }
+        }
+    }
+}
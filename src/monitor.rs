@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A bounded single-producer single-consumer ring of samples. The audio thread never waits
+/// on it: whatever doesn't fit because the consumer fell behind is dropped.
+pub struct MonitorRing {
+    /// `f32` bits, atomics so both sides can share the buffer without locking
+    buffer: Box<[AtomicU32]>,
+    /// Total samples written and read, the difference is what's waiting to be read
+    written: AtomicUsize,
+    read: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl MonitorRing {
+    /// A ring holding up to `capacity` samples
+    pub fn new(capacity: usize) -> MonitorRing {
+        MonitorRing {
+            buffer: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends as many of `samples` as fit, only called by the producer
+    pub(crate) fn push(&self, samples: impl ExactSizeIterator<Item = f32>) {
+        let capacity = self.buffer.len();
+        let written = self.written.load(Ordering::Relaxed);
+        let free = capacity - (written - self.read.load(Ordering::Acquire));
+        let total = samples.len();
+        let mut count = 0;
+        for s in samples.take(free) {
+            self.buffer[(written + count) % capacity].store(s.to_bits(), Ordering::Relaxed);
+            count += 1;
+        }
+        self.written.store(written + count, Ordering::Release);
+        if total > count {
+            self.dropped.fetch_add(total - count, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The reading end of a `MonitorRing` set with `Player::set_monitor`
+pub struct MonitorConsumer {
+    ring: Arc<MonitorRing>,
+}
+
+impl MonitorConsumer {
+    pub(crate) fn new(ring: Arc<MonitorRing>) -> MonitorConsumer {
+        MonitorConsumer { ring }
+    }
+
+    /// Moves the samples mixed since the last call into `out`, oldest first
+    pub fn read_into(&mut self, out: &mut Vec<f32>) {
+        let ring = &self.ring;
+        let capacity = ring.buffer.len();
+        let read = ring.read.load(Ordering::Relaxed);
+        let written = ring.written.load(Ordering::Acquire);
+        out.extend(
+            (read..written)
+                .map(|i| f32::from_bits(ring.buffer[i % capacity].load(Ordering::Relaxed))),
+        );
+        ring.read.store(written, Ordering::Release);
+    }
+
+    /// Samples dropped so far because they weren't read in time
+    pub fn dropped(&self) -> usize {
+        self.ring.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,431 @@
+use serde::{Deserialize, Serialize};
+
+use crate::click::{scale_degree_freq, tags, Sounds};
+use crate::echo::{echo_playbacks, EchoDelay};
+use crate::player::Playback;
+
+/// How strongly a beat is accented, picks the click it plays
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum AccentLevel {
+    Strong,
+    Medium,
+    Weak,
+}
+
+impl AccentLevel {
+    pub fn next(self) -> AccentLevel {
+        match self {
+            AccentLevel::Strong => AccentLevel::Medium,
+            AccentLevel::Medium => AccentLevel::Weak,
+            AccentLevel::Weak => AccentLevel::Strong,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            AccentLevel::Strong => "X",
+            AccentLevel::Medium => "x",
+            AccentLevel::Weak => "·",
+        }
+    }
+
+    /// Accented downbeat, then alternating weak and medium beats
+    pub fn default_for(beat: usize) -> AccentLevel {
+        if beat == 0 {
+            AccentLevel::Strong
+        } else if beat % 2 == 1 {
+            AccentLevel::Weak
+        } else {
+            AccentLevel::Medium
+        }
+    }
+
+    pub fn default_pattern(numerator: usize) -> Vec<AccentLevel> {
+        (0..numerator).map(AccentLevel::default_for).collect()
+    }
+
+    /// Accents for beats grouped like 3+3+2: the downbeat strong, the first beat of every
+    /// other group medium and the rest weak
+    pub fn grouped(groups: &[usize]) -> Vec<AccentLevel> {
+        groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, &len)| {
+                (0..len).map(move |i| match (group, i) {
+                    (0, 0) => AccentLevel::Strong,
+                    (_, 0) => AccentLevel::Medium,
+                    _ => AccentLevel::Weak,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Most beats a bar can have
+pub const MAX_BEATS: usize = 32;
+/// Note values a beat can be, from quarter to 32nd notes
+pub const SUBDIVISIONS: [usize; 4] = [4, 8, 16, 32];
+
+/// Parses a grouping of the beats of a bar like "3+3+2"
+pub fn parse_grouping(text: &str) -> anyhow::Result<Vec<usize>> {
+    let groups = text
+        .split('+')
+        .map(|group| match group.trim().parse() {
+            Ok(len) if len > 0 => Ok(len),
+            _ => Err(anyhow::anyhow!(
+                "'{}' is not a group of beats",
+                group.trim()
+            )),
+        })
+        .collect::<anyhow::Result<Vec<usize>>>()?;
+    let beats: usize = groups.iter().sum();
+    if beats > MAX_BEATS {
+        anyhow::bail!("{} beats don't fit in a bar", beats);
+    }
+    Ok(groups)
+}
+
+/// Level of the pre-beat tick relative to the clicks, soft enough not to be taken for a beat
+pub const PRE_BEAT_GAIN: f32 = 0.25;
+
+/// Everything about what the metronome plays, except for the tempo
+#[derive(Clone, PartialEq, Debug)]
+pub struct Pattern {
+    pub numerator: usize,
+    pub subdivision: usize,
+    /// Clicks per beat: the beat itself followed by evenly spaced unaccented ones,
+    /// e.g. 5 for quintuplets. 1 plays just the beats.
+    pub tuplet: usize,
+    /// How far the tuplet clicks alternate to the left and right of the beats, 0 to 1
+    pub tuplet_spread: f32,
+    /// Note value clicked between the beats, e.g. 8 for the off-eighths in 4/4.
+    /// Nothing is added unless it is shorter than the beat.
+    pub click_note: usize,
+    /// How far every second click between the beats is delayed, 0 for straight to 0.75.
+    /// Around 2/3 the pairs sound like triplets.
+    pub swing: f32,
+    pub pre_beat: bool,
+    /// Accent per beat of the bar, the default one for any beat missing
+    pub accents: Vec<AccentLevel>,
+    /// Beats left silent, keeping their accent for when they are unmuted. Beats missing play.
+    pub muted: Vec<bool>,
+    /// Cue a fill on the last beat of every Nth bar, 0 to disable
+    pub fill_every: usize,
+    /// Accent every Nth beat counted across the bar lines, e.g. 3 in 4/4 for a hemiola.
+    /// 0 to disable.
+    pub cross_accent_every: usize,
+    pub melodic: bool,
+    /// Pan the beats of a bar from left to right
+    pub spatial: bool,
+    /// 1-based major scale degree per beat, used in melodic mode
+    pub beat_degrees: Vec<usize>,
+    pub echo_delay: EchoDelay,
+    pub echo_feedback: f32,
+    /// Only play this many beats of the bar, spread as evenly as possible
+    pub euclidean_hits: Option<usize>,
+    /// Chance of each beat being left out, from 0 to 1
+    pub drop_probability: f32,
+    /// Whether the first beat of the bar can be left out too
+    pub drop_downbeats: bool,
+    /// Picks which beats are left out, see `RandomDrop`
+    pub drop_seed: u64,
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Pattern {
+            numerator: 4,
+            subdivision: 4,
+            tuplet: 1,
+            tuplet_spread: 0.0,
+            click_note: 4,
+            swing: 0.0,
+            pre_beat: false,
+            accents: AccentLevel::default_pattern(4),
+            muted: Vec::new(),
+            fill_every: 0,
+            cross_accent_every: 0,
+            melodic: false,
+            spatial: false,
+            beat_degrees: vec![1; 4],
+            echo_delay: EchoDelay::DottedEighth,
+            echo_feedback: 0.0,
+            euclidean_hits: None,
+            drop_probability: 0.0,
+            drop_downbeats: false,
+            drop_seed: 0,
+        }
+    }
+}
+
+/// Looping playbacks ready to be sent to the player
+pub struct Schedule {
+    pub playbacks: Vec<Playback>,
+    /// Exact samples in one bar, or in the whole loop if it alternates between patterns
+    pub bar_duration: f64,
+    /// Exact samples in each bar of the loop, a single bar unless it alternates
+    pub bar_lengths: Vec<f64>,
+}
+
+impl Pattern {
+    /// Sets the time signature, clamped to 1 to `MAX_BEATS` beats of one of the `SUBDIVISIONS`.
+    /// The beat degrees grow and shrink with the bar.
+    pub fn set_meter(&mut self, numerator: usize, subdivision: usize) {
+        self.numerator = numerator.clamp(1, MAX_BEATS);
+        self.subdivision = subdivision
+            .clamp(SUBDIVISIONS[0], SUBDIVISIONS[SUBDIVISIONS.len() - 1])
+            .next_power_of_two();
+        self.beat_degrees.resize(self.numerator, 1);
+    }
+
+    /// Samples between two beats, rounded down
+    pub fn subdiv_duration(&self, bpm: f32, sample_rate: usize) -> usize {
+        self.beat_duration(bpm, sample_rate) as usize
+    }
+
+    /// Clicks per beat from `click_note`, 1 for just the beats
+    pub fn clicks_per_beat(&self) -> usize {
+        (self.click_note / self.subdivision.max(1)).max(1)
+    }
+
+    /// Exact samples between two beats
+    pub fn beat_duration(&self, bpm: f32, sample_rate: usize) -> f64 {
+        sample_rate as f64 * 60.0 * 4.0 / bpm as f64 / self.subdivision as f64
+    }
+
+    pub fn schedule(&self, bpm: f32, sample_rate: usize, sounds: &mut Sounds) -> Schedule {
+        let numerator = self.numerator;
+        let subdiv_duration = self.subdiv_duration(bpm, sample_rate);
+        // Onsets are placed at exact positions and rounded individually,
+        // so loops of many bars keep the tempo instead of drifting
+        let beat = self.beat_duration(bpm, sample_rate);
+        let at_beat = |beats: f64| (beats * beat).round() as usize;
+        let bar_exact = beat * numerator as f64;
+        if subdiv_duration * numerator == 0 {
+            // Degenerate bar, there is nothing sensible to play
+            return Schedule {
+                playbacks: Vec::new(),
+                bar_duration: 0.0,
+                bar_lengths: vec![0.0],
+            };
+        }
+
+        // With a fill cue the pattern loops over the whole phrase instead of a single bar
+        let phrase_bars = usize::max(self.fill_every, 1);
+        let phrase_duration = bar_exact * phrase_bars as f64;
+
+        let hits = match self.euclidean_hits {
+            Some(k) => euclidean_rhythm(k, numerator),
+            None => vec![true; numerator],
+        };
+
+        let mut playbacks: Vec<Playback> = (0..phrase_bars)
+            .flat_map(|bar| (0..numerator).map(move |i| (bar, i)))
+            .filter_map(|(bar, i)| {
+                let fill_cue = self.fill_every > 0 && bar == phrase_bars - 1 && i == numerator - 1;
+                let muted = self.muted.get(i).copied().unwrap_or(false);
+                if !fill_cue && (!hits[i] || muted) {
+                    return None;
+                }
+
+                let playback = if fill_cue {
+                    Playback::new(sounds.fill.clone()).tag(tags::FILL)
+                } else if self.melodic {
+                    // Beats added since the degrees were last resized play the root
+                    let degree = self.beat_degrees.get(i).copied().unwrap_or(1).max(1);
+                    Playback::new(sounds.cache.get(scale_degree_freq(440.0, degree - 1)))
+                } else {
+                    let accent = self.accents.get(i).copied();
+                    match accent.unwrap_or_else(|| AccentLevel::default_for(i)) {
+                        AccentLevel::Strong => Playback::new(sounds.hi.clone()).tag(tags::HI),
+                        AccentLevel::Medium => Playback::new(sounds.mid.clone()).tag(tags::MID),
+                        AccentLevel::Weak => Playback::new(sounds.lo.clone()).tag(tags::LO),
+                    }
+                };
+
+                let pan = if self.spatial && numerator > 1 {
+                    -1.0 + 2.0 * i as f32 / (numerator - 1) as f32
+                } else {
+                    0.0
+                };
+
+                let droppable = !fill_cue && (i > 0 || self.drop_downbeats);
+                let playback = if droppable && self.drop_probability > 0.0 {
+                    playback.drop_randomly(self.drop_probability, self.drop_seed)
+                } else {
+                    playback
+                };
+
+                Some(
+                    playback
+                        .pan(pan)
+                        .offset(at_beat((bar * numerator + i) as f64))
+                        .repeat_exact(phrase_duration, None),
+                )
+            })
+            .collect();
+        if self.tuplet > 1 {
+            for i in
+                (0..numerator).filter(|&i| hits[i] && !self.muted.get(i).copied().unwrap_or(false))
+            {
+                for part in 1..self.tuplet {
+                    let beats = i as f64 + part as f64 / self.tuplet as f64;
+                    // Alternating across the bar: left, right, left...
+                    let pan = if (i * (self.tuplet - 1) + part) % 2 == 1 {
+                        -self.tuplet_spread
+                    } else {
+                        self.tuplet_spread
+                    };
+                    playbacks.extend((0..phrase_bars).map(|bar| {
+                        Playback::new(sounds.tuplet.clone())
+                            .tag(tags::TUPLET)
+                            .pan(pan)
+                            .offset(at_beat((bar * numerator) as f64 + beats))
+                            .repeat_exact(phrase_duration, None)
+                    }));
+                }
+            }
+        }
+        let steps = self.clicks_per_beat();
+        if steps > 1 {
+            for i in
+                (0..numerator).filter(|&i| hits[i] && !self.muted.get(i).copied().unwrap_or(false))
+            {
+                for step in 1..steps {
+                    // The "and" halfway through the beat is louder than the notes around it
+                    let gain = if step * 2 == steps { 1.0 } else { 0.6 };
+                    // Delayed by a share of half a click, so the clicks on the beats keep their place
+                    let swing = if step % 2 == 1 {
+                        self.swing.clamp(0.0, 0.75) as f64 / 2.0
+                    } else {
+                        0.0
+                    };
+                    let beats = i as f64 + (step as f64 + swing) / steps as f64;
+                    playbacks.extend((0..phrase_bars).map(|bar| {
+                        Playback::new(sounds.subdivision.clone())
+                            .tag(tags::SUBDIVISION)
+                            .gain(gain)
+                            .offset(at_beat((bar * numerator) as f64 + beats))
+                            .repeat_exact(phrase_duration, None)
+                    }));
+                }
+            }
+        }
+        if self.pre_beat && numerator > 0 {
+            // One subdivision ahead of the next downbeat, looping with the bar. Without
+            // subdivisions that would be the last beat, so it takes the off-beat instead.
+            let lead = 1.0 / steps.max(2) as f64;
+            playbacks.push(
+                Playback::new(sounds.pre_beat.clone())
+                    .tag(tags::PRE_BEAT)
+                    .gain(PRE_BEAT_GAIN)
+                    .offset(at_beat(numerator as f64 - lead))
+                    .repeat_exact(bar_exact, None),
+            );
+        }
+        if self.cross_accent_every > 0 {
+            // Loops on its own instead of with the bar, so it keeps counting beats across bar lines
+            playbacks.push(
+                Playback::new(sounds.cross_accent.clone())
+                    .tag(tags::CROSS_ACCENT)
+                    .repeat_exact(beat * self.cross_accent_every as f64, None),
+            );
+        }
+        if self.echo_feedback > 0.0 {
+            let quarter_duration = sample_rate as f32 * 60.0 / bpm;
+            let delay = (quarter_duration * self.echo_delay.quarters()) as usize;
+            let echoes = echo_playbacks(&playbacks, delay, self.echo_feedback);
+            playbacks.extend(echoes);
+        }
+
+        Schedule {
+            playbacks,
+            bar_duration: bar_exact,
+            bar_lengths: vec![bar_exact],
+        }
+    }
+}
+
+/// Spreads `hits` onsets over `steps` steps as evenly as possible (Bjorklund's algorithm),
+/// e.g. 3 over 8 gives `x..x..x.`. The first step always sounds.
+pub fn euclidean_rhythm(hits: usize, steps: usize) -> Vec<bool> {
+    if steps == 0 {
+        return Vec::new();
+    }
+    let hits = hits.clamp(1, steps);
+
+    // Repeatedly pair up the remainder groups with the leading groups until at most one is left.
+    // A single rest is paired up too, so 3 over 4 is `x.xx` instead of `xxx.`.
+    let mut groups: Vec<Vec<bool>> = vec![vec![true]; hits];
+    let mut remainder: Vec<Vec<bool>> = vec![vec![false]; steps - hits];
+    while !remainder.is_empty() {
+        let paired = groups.len().min(remainder.len());
+        let rest = if groups.len() > paired {
+            groups.split_off(paired)
+        } else {
+            remainder.split_off(paired)
+        };
+        for (group, r) in groups.iter_mut().zip(remainder) {
+            group.extend(r);
+        }
+        remainder = rest;
+        if remainder.len() <= 1 {
+            break;
+        }
+    }
+
+    let mut rhythm: Vec<bool> = groups.into_iter().chain(remainder).flatten().collect();
+    rhythm[0] = true;
+    rhythm
+}
+
+impl Schedule {
+    /// Plays `bars` bars of `a`, then `bars` bars of `b`, and loops
+    pub fn alternate(a: &Schedule, b: &Schedule, bars: usize) -> Schedule {
+        let span_a = a.bar_duration * bars as f64;
+        let span_b = b.bar_duration * bars as f64;
+        let cycle = span_a + span_b;
+
+        let mut playbacks = Vec::new();
+        let offset_b = span_a.round() as usize;
+        for (schedule, offset, span) in [(a, 0, span_a), (b, offset_b, span_b)] {
+            // Turn the pattern's own loop into explicit onsets looping with the whole cycle
+            for p in &schedule.playbacks {
+                playbacks.extend(p.onsets(0..span.round() as usize).map(|start| {
+                    Playback {
+                        start: start + offset,
+                        ..p.clone()
+                    }
+                    .repeat_exact(cycle, None)
+                }));
+            }
+        }
+
+        let bar_lengths = [a, b]
+            .iter()
+            .flat_map(|s| (0..bars).flat_map(|_| s.bar_lengths.iter().copied()))
+            .collect();
+        Schedule {
+            playbacks,
+            bar_duration: cycle,
+            bar_lengths,
+        }
+    }
+
+    /// The onsets of the first `bars` bars as playbacks that play only once
+    pub fn unroll(&self, bars: usize) -> Vec<Playback> {
+        let span = (self.bar_duration * bars as f64).round() as usize;
+        self.playbacks
+            .iter()
+            .flat_map(|p| {
+                p.onsets(0..span).map(|start| Playback {
+                    start,
+                    repetition_period: span,
+                    repetition_fraction: 0.0,
+                    repetition_count: Some(0),
+                    ..p.clone()
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,32 @@
+use std::f64::consts::PI;
+
+/// Largest deflection of the arm from vertical, in radians
+const MAX_ANGLE: f32 = 0.5;
+
+/// Draws a pendulum swinging once per beat, reaching an extreme whenever a click sounds.
+/// `beats` is the (fractional) number of beats since the pattern started, None while stopped.
+pub fn pendulum(ui: &mut egui::Ui, beats: Option<f64>, size: f32) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(size, size * 0.6), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let color = ui.visuals().strong_text_color();
+
+    let pivot = rect.center_bottom() - egui::vec2(0.0, rect.height() * 0.05);
+    let length = rect.height() * 0.9;
+    let direction = |angle: f32| egui::vec2(angle.sin(), -angle.cos());
+
+    // Scale marks at the extremes
+    for side in [-1.0, 1.0] {
+        let d = direction(side * MAX_ANGLE);
+        painter.line_segment(
+            [pivot + d * length * 0.9, pivot + d * length],
+            egui::Stroke::new(1.0, color),
+        );
+    }
+
+    // A sinusoidal swing, cos(pi * beats) is +-1 on every whole beat
+    let angle = beats.map_or(0.0, |b| MAX_ANGLE * (PI * b).cos() as f32);
+    let d = direction(angle);
+    painter.line_segment([pivot, pivot + d * length], egui::Stroke::new(3.0, color));
+    painter.circle_filled(pivot + d * length * 0.6, size * 0.04, color);
+    painter.circle_filled(pivot, size * 0.02, color);
+}
@@ -1,7 +1,118 @@
-use cpal::traits::DeviceTrait;
-use cpal::traits::HostTrait;
+use crate::audio_sink::{AudioSink, CpalSink, NullSink, RenderCallback};
+use crate::backing_track::BackingTrack;
+use crate::monitor::{MonitorConsumer, MonitorRing};
 
+use std::collections::HashMap;
+use std::ops::{Range, RangeInclusive};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Length of the fade to silence when the player stops
+const FADE_OUT_MS: usize = 10;
+
+/// Length of the fade when pausing or resuming, short enough to keep the beat but without a click
+const PAUSE_FADE_FRAMES: usize = 256;
+
+/// Length of the fade of the clicks still sounding when the playbacks are cleared
+const CLEAR_FADE_FRAMES: usize = 256;
+
+/// Volume changes glide over this long, so dragging the volume doesn't step audibly
+const VOLUME_SMOOTHING_MS: usize = 10;
+
+/// Time between attempts to reopen a failed output
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Beat events held for the UI before new ones are dropped
+const BEAT_EVENTS_CAPACITY: usize = 256;
+
+/// Onsets closer than this count as simultaneous for the overlap protection
+const OVERLAP_WINDOW_MS: usize = 5;
+
+/// Playbacks sounding at once unless configured otherwise. Far more than any pattern needs,
+/// it only kicks in when e.g. echo feedback layers up clicks faster than they die out.
+pub const DEFAULT_MAX_VOICES: usize = 64;
+
+/// The layer of playbacks that don't ask for another one, the one `replace_playbacks` works on
+pub const MAIN_LAYER: u64 = 0;
+
+/// Volumes the player accepts unless configured otherwise. Far above 0 dB the limiter
+/// turns everything into loud square waves.
+pub const DEFAULT_VOLUME_RANGE_DB: RangeInclusive<f32> = -60.0..=12.0;
+
+/// A scheduled click, e.g. for drawing upcoming beats
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Onset {
+    pub time: usize,
+    pub tag: Option<u64>,
+    /// Peak amplitude of the click, louder ones are accented
+    pub level: f32,
+}
+
+/// All onsets of `playbacks` within `window`, sorted by time
+pub fn onsets(playbacks: &[Playback], window: Range<usize>) -> Vec<Onset> {
+    let mut onsets: Vec<Onset> = playbacks
+        .iter()
+        .flat_map(|p| {
+            let level = p.samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            p.onsets(window.clone()).map(move |time| Onset {
+                time,
+                tag: p.tag,
+                level,
+            })
+        })
+        .collect();
+    onsets.sort_by_key(|onset| onset.time);
+    onsets
+}
+
+/// Sent by the audio thread whenever it mixes the start of a repetition
+#[derive(Clone, Copy, Debug)]
+pub struct BeatEvent {
+    /// Index of the playback among the ones playing at the time
+    pub playback_index: usize,
+    /// Mixer time the repetition starts at, see `Player::instant_of`
+    pub sample_time: usize,
+}
+
+/// Alternates bars with clicks and silent bars, to practice keeping time alone
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BarGate {
+    pub play_bars: usize,
+    pub mute_bars: usize,
+}
+
+impl BarGate {
+    /// Whether the `bar`th bar of the pattern, counting from 0, is silent
+    pub fn is_muted(&self, bar: usize) -> bool {
+        let cycle = self.play_bars + self.mute_bars;
+        cycle > 0 && bar % cycle >= self.play_bars
+    }
+}
+
+/// Leaves out repetitions at random, to practice holding the pulse through the gaps
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RandomDrop {
+    /// Chance of each repetition staying silent, from 0 to 1
+    pub probability: f32,
+    /// Decides along with the mixer time of each repetition whether it is dropped,
+    /// so rendering from the same start with the same seed drops the same ones
+    pub seed: u64,
+}
+
+impl RandomDrop {
+    /// Whether the repetition starting at `time` is silent
+    pub fn drops(&self, time: usize) -> bool {
+        // SplitMix64 of the seed and the onset, so no generator state has to be kept
+        let mut x = self.seed ^ (time as u64).wrapping_mul(0x9E3779B97F4A7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+        x ^= x >> 31;
+        let uniform = (x >> 40) as f32 / (1u64 << 24) as f32;
+        uniform < self.probability
+    }
+}
 
 pub enum ReadResult {
     Ok,
@@ -13,8 +124,26 @@ pub enum ReadResult {
 pub struct Playback {
     pub start: usize,
     pub repetition_period: usize,
+    /// Fraction of a sample added to `repetition_period` per repetition. Each repetition
+    /// is rounded on its own, so a loop of a non-integer length doesn't drift.
+    pub repetition_fraction: f64,
     pub repetition_count: Option<usize>,
     pub samples: Arc<Vec<f32>>,
+    /// Whether `samples` holds interleaved left and right frames instead of mono ones
+    pub stereo: bool,
+    /// Identifies playbacks for targeted updates, e.g. swapping their samples
+    pub tag: Option<u64>,
+    /// Stereo position from -1.0 (left) to 1.0 (right)
+    pub pan: f32,
+    /// Linear gain of the samples, applied before the volume
+    pub gain: f32,
+    /// Play the samples as one continuous loop from `start` instead of retriggering them
+    /// every period. With a repetition count it ends after `repetition_count + 1` periods.
+    pub loop_sustain: bool,
+    /// Independent metronome this belongs to, with its own volume and mute
+    pub layer: u64,
+    /// Repetitions left out at random
+    pub random_drop: Option<RandomDrop>,
 }
 
 impl Playback {
@@ -22,8 +151,16 @@ impl Playback {
         Playback {
             start: 0,
             repetition_period: 0,
+            repetition_fraction: 0.0,
             repetition_count: None,
             samples,
+            stereo: false,
+            tag: None,
+            pan: 0.0,
+            gain: 1.0,
+            loop_sustain: false,
+            layer: MAIN_LAYER,
+            random_drop: None,
         }
     }
     pub fn offset(self, offset: usize) -> Self {
@@ -32,22 +169,283 @@ impl Playback {
             ..self
         }
     }
+    /// Plays the sample every `period` samples, `count` more times after the first or forever
     pub fn repeat(self, period: usize, count: Option<usize>) -> Self {
         Playback {
             repetition_period: period,
+            repetition_fraction: 0.0,
             repetition_count: count,
             ..self
         }
     }
 
+    /// Like `repeat`, with a period that isn't a whole number of samples
+    pub fn repeat_exact(self, period: f64, count: Option<usize>) -> Self {
+        let period = period.max(0.0);
+        Playback {
+            repetition_period: period as usize,
+            repetition_fraction: period.fract(),
+            repetition_count: count,
+            ..self
+        }
+    }
+
+    fn exact_period(&self) -> f64 {
+        self.repetition_period as f64 + self.repetition_fraction
+    }
+
+    /// Start time of the `rep`th repetition
+    fn rep_start(&self, rep: usize) -> usize {
+        self.start
+            + rep * self.repetition_period
+            + (rep as f64 * self.repetition_fraction).round() as usize
+    }
+
+    /// The last repetition starting at or before `time` (the first one if none does)
+    fn last_rep_at(&self, time: usize) -> usize {
+        let period = self.exact_period();
+        if period <= 0.0 {
+            return 0;
+        }
+        // Estimated in floating point, then corrected for the rounding of each start
+        let mut rep = (time.saturating_sub(self.start) as f64 / period) as usize;
+        while rep > 0 && self.rep_start(rep) > time {
+            rep -= 1;
+        }
+        while self.rep_start(rep + 1) <= time {
+            rep += 1;
+        }
+        rep
+    }
+
+    pub fn tag(self, tag: u64) -> Self {
+        Playback {
+            tag: Some(tag),
+            ..self
+        }
+    }
+
+    pub fn pan(self, pan: f32) -> Self {
+        Playback {
+            pan: pan.clamp(-1.0, 1.0),
+            ..self
+        }
+    }
+
+    /// Scales the samples, e.g. to make accented clicks louder
+    pub fn gain(self, gain: f32) -> Self {
+        Playback { gain, ..self }
+    }
+
+    pub fn layer(self, layer: u64) -> Self {
+        Playback { layer, ..self }
+    }
+
+    /// Leaves out each repetition with the chance `probability`, see `RandomDrop`
+    pub fn drop_randomly(self, probability: f32, seed: u64) -> Self {
+        Playback {
+            random_drop: Some(RandomDrop { probability, seed }),
+            ..self
+        }
+    }
+
+    fn is_dropped(&self, onset: usize) -> bool {
+        self.random_drop.is_some_and(|drop| drop.drops(onset))
+    }
+
+    /// Loops the samples seamlessly, e.g. for a sustained tone
+    pub fn loop_sustain(self) -> Self {
+        Playback {
+            loop_sustain: true,
+            ..self
+        }
+    }
+
+    /// Plays the samples as interleaved stereo frames, keeping their stereo image.
+    /// `pan` then works as a balance control.
+    pub fn stereo(self) -> Self {
+        Playback {
+            stereo: true,
+            ..self
+        }
+    }
+
+    /// Length of the samples in frames
+    pub fn frames(&self) -> usize {
+        if self.stereo {
+            self.samples.len() / 2
+        } else {
+            self.samples.len()
+        }
+    }
+
+    /// Left and right sample of a frame, the same for mono samples
+    fn frame(&self, frame: usize) -> (f32, f32) {
+        if self.stereo {
+            (self.samples[2 * frame], self.samples[2 * frame + 1])
+        } else {
+            (self.samples[frame], self.samples[frame])
+        }
+    }
+
     pub fn end(&self) -> Option<usize> {
+        if self.exact_period() <= 0.0 && !self.loop_sustain {
+            // Without a period there is nothing to repeat, whatever the count says
+            return Some(self.start + self.frames());
+        }
         self.repetition_count.map(|repetition_count| {
-            self.start + self.samples.len() + self.repetition_period * repetition_count
+            if self.loop_sustain {
+                self.rep_start(repetition_count + 1)
+            } else {
+                self.rep_start(repetition_count) + self.frames()
+            }
         })
     }
 
+    /// Start times of the repetitions within `window`. A sustained loop only starts once.
+    pub fn onsets(&self, window: Range<usize>) -> impl Iterator<Item = usize> + '_ {
+        let count = if self.exact_period() <= 0.0 || self.loop_sustain {
+            1
+        } else {
+            self.repetition_count
+                .map_or(usize::MAX, |c| c.saturating_add(1))
+        };
+        let mut first = self.last_rep_at(window.start);
+        if self.rep_start(first) < window.start {
+            first += 1;
+        }
+        (first..count)
+            .map(move |rep| self.rep_start(rep))
+            .take_while(move |&time| time < window.end)
+    }
+
+    /// Start of the latest repetition before `time`, None for sustained loops or if
+    /// none has started yet
+    fn last_onset(&self, time: usize) -> Option<usize> {
+        if self.loop_sustain || time <= self.start {
+            return None;
+        }
+        let mut rep = self.last_rep_at(time - 1);
+        if let Some(count) = self.repetition_count {
+            rep = rep.min(count);
+        }
+        Some(self.rep_start(rep))
+    }
+
+    /// Limits repetitions to those starting before `time`.
+    /// Returns false if no repetition is left at all.
+    /// Endless sustained loops aren't tied to the bars and are left running.
+    pub fn stop_before(&mut self, time: usize) -> bool {
+        if self.loop_sustain && self.repetition_count.is_none() {
+            return true;
+        }
+        if time <= self.start {
+            return false;
+        }
+        if self.exact_period() > 0.0 {
+            let last_rep = self.last_rep_at(time - 1);
+            self.repetition_count = Some(
+                self.repetition_count
+                    .map_or(last_rep, |count| count.min(last_rep)),
+            );
+        }
+        true
+    }
+
     pub fn read(&self, time: usize, buffer: &mut [f32]) -> ReadResult {
-        let time_end = time + buffer.len();
+        self.read_scaled(time, buffer, 1.0)
+    }
+
+    /// Like `read`, scaling the samples by `gain`
+    pub fn read_scaled(&self, time: usize, buffer: &mut [f32], gain: f32) -> ReadResult {
+        self.read_scaled_by(time, buffer, |_| gain)
+    }
+
+    /// Like `read_scaled`, with the gain of each repetition given by its start time
+    fn read_scaled_by(
+        &self,
+        time: usize,
+        buffer: &mut [f32],
+        rep_gain: impl Fn(usize) -> f32,
+    ) -> ReadResult {
+        if self.loop_sustain {
+            let gain = rep_gain(self.start) * self.gain;
+            return self.read_loop(time, buffer.len(), |i, l, r| {
+                buffer[i] += (l + r) * 0.5 * gain
+            });
+        }
+        self.read_with(time, buffer.len(), |offset, rep_time| {
+            let gain = rep_gain(rep_time) * self.gain;
+            if self.stereo {
+                // Downmixed, the stereo image only survives on the panned path
+                self.read_channel(offset, buffer, gain * 0.5, 0);
+                self.read_channel(offset, buffer, gain * 0.5, 1);
+            } else {
+                self.read_sample(offset, buffer, gain)
+            }
+        })
+    }
+
+    /// Like `read`, but mixes into a stereo pair according to `pan`
+    pub fn read_panned(&self, time: usize, left: &mut [f32], right: &mut [f32]) -> ReadResult {
+        self.read_panned_scaled(time, left, right, 1.0)
+    }
+
+    /// Like `read_panned`, scaling the samples by `gain`
+    pub fn read_panned_scaled(
+        &self,
+        time: usize,
+        left: &mut [f32],
+        right: &mut [f32],
+        gain: f32,
+    ) -> ReadResult {
+        self.read_panned_scaled_by(time, left, right, |_| gain)
+    }
+
+    /// Like `read_panned_scaled`, with the gain of each repetition given by its start time
+    fn read_panned_scaled_by(
+        &self,
+        time: usize,
+        left: &mut [f32],
+        right: &mut [f32],
+        rep_gain: impl Fn(usize) -> f32,
+    ) -> ReadResult {
+        // Balance law: the near side stays at unity, the far side fades out
+        let pan_gains = |rep_time: usize| {
+            let gain = rep_gain(rep_time) * self.gain;
+            (
+                gain * f32::min(1.0 - self.pan, 1.0),
+                gain * f32::min(1.0 + self.pan, 1.0),
+            )
+        };
+        if self.loop_sustain {
+            let (left_gain, right_gain) = pan_gains(self.start);
+            return self.read_loop(time, left.len(), |i, l, r| {
+                left[i] += l * left_gain;
+                right[i] += r * right_gain;
+            });
+        }
+        self.read_with(time, left.len(), |offset, rep_time| {
+            let (left_gain, right_gain) = pan_gains(rep_time);
+            if self.stereo {
+                self.read_channel(offset, left, left_gain, 0);
+                self.read_channel(offset, right, right_gain, 1);
+            } else {
+                self.read_sample(offset, left, left_gain);
+                self.read_sample(offset, right, right_gain);
+            }
+        })
+    }
+
+    /// Calls `read_onset` with the offset of each repetition audible in the `len` samples from
+    /// `time`, along with the time it starts at
+    fn read_with(
+        &self,
+        time: usize,
+        len: usize,
+        mut read_onset: impl FnMut(isize, usize),
+    ) -> ReadResult {
+        let time_end = time + len;
 
         if time_end < self.start {
             return ReadResult::NotYetStarted;
@@ -56,119 +454,1028 @@ impl Playback {
             return ReadResult::Ended;
         }
 
-        // Play last repetition
-        let mut rep = (time.saturating_sub(self.start)) / self.repetition_period;
+        // Start at the first repetition still sounding, the ones whose samples ended before
+        // `time` are skipped without being looked at, however long ago the playback started
+        let mut rep = self.last_rep_at((time + 1).saturating_sub(self.frames()));
+        if self.exact_period() > 0.0 && self.rep_start(rep) + self.frames() <= time {
+            rep += 1;
+        }
         loop {
-            let rep_time = self.start + rep * self.repetition_period;
-            if rep_time >= time_end {
+            let rep_time = self.rep_start(rep);
+            if rep_time >= time_end || matches!(self.repetition_count, Some(count) if rep > count) {
                 break;
             }
 
-            self.read_sample(rep_time as isize - time as isize, buffer);
+            if !self.is_dropped(rep_time) {
+                read_onset(rep_time as isize - time as isize, rep_time);
+            }
 
+            if self.exact_period() <= 0.0 {
+                break;
+            }
             rep += 1;
         }
 
         ReadResult::Ok
     }
 
-    pub fn read_sample(&self, time_offset: isize, output: &mut [f32]) {
+    /// Calls `write` with the left and right sample of each looped frame audible in the
+    /// `len` samples from `time`, along with its offset from `time`
+    fn read_loop(
+        &self,
+        time: usize,
+        len: usize,
+        mut write: impl FnMut(usize, f32, f32),
+    ) -> ReadResult {
+        let time_end = time + len;
+        if time_end < self.start {
+            return ReadResult::NotYetStarted;
+        }
+        let end = self.end();
+        if matches!(end, Some(end) if time >= end) {
+            return ReadResult::Ended;
+        }
+
+        let n = self.frames();
+        if n > 0 {
+            let to = end.map_or(time_end, |end| end.min(time_end));
+            for t in self.start.max(time)..to {
+                let (l, r) = self.frame((t - self.start) % n);
+                write(t - time, l, r);
+            }
+        }
+        ReadResult::Ok
+    }
+
+    pub fn read_sample(&self, time_offset: isize, output: &mut [f32], gain: f32) {
         let read_offset = (-time_offset).clamp(0, self.samples.len() as isize) as usize;
         let write_offset = (time_offset).clamp(0, output.len() as isize) as usize;
 
         let src = &self.samples[read_offset..];
         let dst = &mut output[write_offset..];
 
-        dst.iter_mut().zip(src.iter()).for_each(|(d, s)| *d += *s);
+        dst.iter_mut()
+            .zip(src.iter())
+            .for_each(|(d, s)| *d += *s * gain);
+    }
+
+    /// Like `read_sample`, for the left (0) or right (1) channel of stereo samples
+    fn read_channel(&self, time_offset: isize, output: &mut [f32], gain: f32, channel: usize) {
+        let read_offset = (-time_offset).clamp(0, self.frames() as isize) as usize;
+        let write_offset = (time_offset).clamp(0, output.len() as isize) as usize;
+
+        let src = self
+            .samples
+            .iter()
+            .skip(2 * read_offset + channel)
+            .step_by(2);
+        let dst = &mut output[write_offset..];
+
+        dst.iter_mut().zip(src).for_each(|(d, s)| *d += *s * gain);
+    }
+}
+
+struct LayerGain {
+    gain: f32,
+    muted: bool,
+    /// While any layer is soloed, only the soloed ones play
+    solo: bool,
+}
+impl Default for LayerGain {
+    fn default() -> Self {
+        LayerGain {
+            gain: 1.0,
+            muted: false,
+            solo: false,
+        }
+    }
+}
+impl LayerGain {
+    fn effective(&self, any_solo: bool) -> f32 {
+        if self.muted || (any_solo && !self.solo) {
+            0.0
+        } else {
+            self.gain
+        }
+    }
+}
+
+/// Gain of the playbacks on `layer`, layers that were never set play at unity unless another
+/// one is soloed
+fn layer_gain(layers: &HashMap<u64, LayerGain>, layer: u64) -> f32 {
+    let any_solo = layers.values().any(|l| l.solo);
+    match layers.get(&layer) {
+        Some(gain) => gain.effective(any_solo),
+        None if any_solo => 0.0,
+        None => 1.0,
     }
 }
 
 enum PlayerCommand {
     AddPlaybacks(Vec<Playback>),
     ClearPlaybacks,
-    SetVolume(f32),
+    RemovePlaybacks(u64),
+    /// Replaces the playbacks of a layer right away, leaving the others alone
+    ReplaceLayer {
+        layer: u64,
+        playbacks: Vec<Playback>,
+    },
+    ClearLayer(u64),
+    SetLayerGain {
+        layer: u64,
+        gain: f32,
+    },
+    SetLayerMuted {
+        layer: u64,
+        muted: bool,
+    },
+    SetLayerSolo {
+        layer: u64,
+        solo: bool,
+    },
+    ReplacePlaybacks {
+        playbacks: Vec<Playback>,
+        /// Exact length of each bar in one loop of the playbacks
+        bar_lengths: Vec<f64>,
+        at_next_bar: bool,
+        /// Mixer time to switch at instead, if it hasn't passed yet
+        at_time: Option<usize>,
+        /// Frames of count-in the playbacks begin with, which the bar gate leaves alone
+        count_in: usize,
+    },
+    SetSamples {
+        tag: u64,
+        samples: Arc<Vec<f32>>,
+    },
+    NudgePhase(isize),
+    /// Ramps the volume linearly to `target` over `frames` frames
+    RampVolume {
+        target: f32,
+        frames: usize,
+    },
+    /// Fades the output to silence over the given number of frames, for good
+    FadeOut(usize),
+    /// Pauses or resumes with a short fade. The mixer time stands still while paused.
+    SetPlaying(bool),
+    SetChannelGains(Vec<f32>),
+    /// Plays the same mixdown on every channel, ignoring panning
+    SetMono(bool),
+    /// Attenuates onsets less than this many frames apart so they don't pile up, None to disable
+    SetOverlapWindow(Option<usize>),
+    /// Cuts the oldest sounding playbacks beyond this many
+    SetMaxVoices(usize),
+    /// Passes the mix through linearly instead of soft clipping it, only clamping to [-1, 1]
+    BypassLimiter(bool),
+    /// Copies the mono mixdown into the ring, None to stop
+    SetMonitor(Option<Arc<MonitorRing>>),
+    SetBarGate(Option<BarGate>),
+    /// Starts a backing track `delay` frames after the current pattern started,
+    /// counting from now if the pattern started earlier
+    PlayTrack {
+        track: BackingTrack,
+        delay: usize,
+    },
+    StopTrack,
+    SetTrackVolume(f32),
 }
 
-pub struct Player {
-    config: cpal::SupportedStreamConfig,
-    send: std::sync::mpsc::Sender<PlayerCommand>,
-    _stream: cpal::Stream,
+/// State published by the audio thread for the `Player` handle
+#[derive(Default)]
+struct SharedState {
+    clock: StreamClock,
+    active_playbacks: AtomicUsize,
+    /// Mixer time at the start of the last callback
+    time: AtomicUsize,
+    /// Bar start and length at the end of the last callback
+    bar_start: AtomicUsize,
+    bar_duration: AtomicUsize,
+    finished: AtomicBool,
+    faded_out: AtomicBool,
+    track_playing: AtomicBool,
 }
-impl Player {
-    pub fn start() -> anyhow::Result<Player> {
-        let device = cpal::default_host()
-            .default_output_device()
-            .ok_or(anyhow::anyhow!("No output device available"))?;
-        println!("Using output device: {}", device.name()?);
-
-        let config = device.default_output_config()?;
-        println!("Using output config: {:?}", config);
-        let num_channels = config.channels() as usize;
-
-        let (send, recv) = std::sync::mpsc::channel::<PlayerCommand>();
-
-        let mut playbacks = Vec::<Playback>::new();
-        let mut time = 0usize;
-        let mut volume = 1f32;
-
-        let mut tmp_buffer = vec![0.0f32; 2 << 14];
-        let stream = device.build_output_stream(
-            &config.config(),
-            move |data: &mut [f32], _info| {
-                // Handle commands
-                for cmd in recv.try_iter() {
-                    match cmd {
-                        PlayerCommand::AddPlaybacks(new_playbacks) => {
-                            playbacks.extend(new_playbacks.into_iter().map(|p| Playback {
-                                start: p.start + time,
-                                ..p
-                            }));
-                        }
-                        PlayerCommand::ClearPlaybacks => {
-                            playbacks.clear();
-                        }
-                        PlayerCommand::SetVolume(new_volume) => {
-                            volume = new_volume;
-                        }
+
+/// When the last callback ran in wall-clock time, to relate `Instant`s to mixer time
+struct StreamClock {
+    /// Reference for `callback_nanos`
+    epoch: Instant,
+    /// Time of the last callback, relative to `epoch`
+    callback_nanos: AtomicU64,
+    /// Time from the last callback until its first frame is played
+    latency_nanos: AtomicU64,
+}
+impl Default for StreamClock {
+    fn default() -> Self {
+        StreamClock {
+            epoch: Instant::now(),
+            callback_nanos: AtomicU64::new(0),
+            latency_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+/// The mixing state of the audio thread, independent of any audio device
+struct Mixer {
+    num_channels: usize,
+    playbacks: Vec<Playback>,
+    /// Whether any of the current playbacks will end by itself
+    has_finite_playbacks: bool,
+    /// Set once all finite playbacks have ended, until taken
+    finished: bool,
+    /// Output gain ramped down by `fade_step` per frame when shutting down
+    fade_gain: f32,
+    fade_step: f32,
+    /// Faded towards 1 while playing and towards 0 when paused, the time stops at 0
+    playing: bool,
+    pause_gain: f32,
+    time: usize,
+    /// Start and exact length of the loop of the current pattern, if set via `ReplacePlaybacks`
+    bar_start: usize,
+    bar_duration: f64,
+    /// Exact length of each bar in the loop, which differ when patterns in different
+    /// meters alternate
+    bar_lengths: Vec<f64>,
+    /// Start of the first bar the bar gate counts, after the count-in
+    gate_start: usize,
+    /// Linear output volume, moved towards `volume_target` by `volume_step` per frame.
+    /// In f64 because the step of a ramp over several minutes is below f32 precision.
+    volume: f64,
+    volume_target: f64,
+    volume_step: f64,
+    channel_gains: Vec<f32>,
+    /// Play the same mixdown on every channel, e.g. for a mono PA feed
+    mono: bool,
+    /// Onsets closer than this share the headroom instead of summing at full gain
+    overlap_window: Option<usize>,
+    /// Playbacks allowed to sound at once, the oldest ones beyond it are cut off
+    max_voices: usize,
+    /// Skip the soft clipping, e.g. for measurements
+    bypass_limiter: bool,
+    /// Silences the clicks during some of the bars of the pattern, time runs on
+    bar_gate: Option<BarGate>,
+    /// Receives a copy of the mono mixdown, e.g. for a scope
+    monitor: Option<Arc<MonitorRing>>,
+    /// Told about each repetition that starts, dropping events while it is full
+    beat_events: Option<SyncSender<BeatEvent>>,
+    /// Mixed in after the click volume, so both can be set independently
+    track: Option<BackingTrack>,
+    track_volume: f32,
+    /// Gain and mute of the layers that have been set, the others play at unity
+    layers: HashMap<u64, LayerGain>,
+    tmp_buffer: Vec<f32>,
+    tmp_left: Vec<f32>,
+    tmp_right: Vec<f32>,
+    /// Playbacks cleared while sounding, finishing their last repetition as they fade out
+    /// over `CLEAR_FADE_FRAMES` instead of being cut off with a pop
+    fading: Vec<Playback>,
+    /// Frames of the fade of `fading` that have been played
+    fading_frames: usize,
+    /// The cleared playbacks, mixed before the fade is applied. Left and right halves when
+    /// the output is stereo.
+    tmp_fading: Vec<f32>,
+    /// Latest onset per playback, for `overlap_window` and `max_voices`
+    tmp_onsets: Vec<Option<usize>>,
+    /// Onsets of the playbacks still sounding, for `max_voices`
+    tmp_voices: Vec<usize>,
+    /// Sorted start times of the repetitions around the current block, for `overlap_window`
+    tmp_nearby: Vec<usize>,
+}
+impl Mixer {
+    fn new(num_channels: usize) -> Mixer {
+        Mixer {
+            num_channels,
+            playbacks: Vec::new(),
+            has_finite_playbacks: false,
+            finished: false,
+            fade_gain: 1.0,
+            fade_step: 0.0,
+            playing: true,
+            pause_gain: 1.0,
+            time: 0,
+            bar_start: 0,
+            bar_duration: 0.0,
+            bar_lengths: Vec::new(),
+            gate_start: 0,
+            volume: 1.0,
+            volume_target: 1.0,
+            volume_step: 0.0,
+            channel_gains: vec![1.0; num_channels],
+            mono: false,
+            overlap_window: None,
+            max_voices: DEFAULT_MAX_VOICES,
+            bypass_limiter: false,
+            bar_gate: None,
+            monitor: None,
+            beat_events: None,
+            track: None,
+            track_volume: 1.0,
+            layers: HashMap::new(),
+            tmp_buffer: vec![0.0; 2 << 14],
+            tmp_left: vec![0.0; 2 << 14],
+            tmp_right: vec![0.0; 2 << 14],
+            fading: Vec::new(),
+            fading_frames: 0,
+            tmp_fading: vec![0.0; 2 << 15],
+            tmp_onsets: Vec::new(),
+            tmp_voices: Vec::new(),
+            tmp_nearby: Vec::new(),
+        }
+    }
+
+    /// Exact offset of the `k`th bar line from the start of the pattern, counting the bars
+    /// of every loop
+    fn bar_line(&self, k: usize) -> f64 {
+        let bars = self.bar_lengths.len().max(1);
+        let loops = (k / bars) as f64 * self.bar_duration;
+        loops + self.bar_lengths.iter().take(k % bars).sum::<f64>()
+    }
+
+    /// Average length of a bar, to guess at which bar a time falls into
+    fn mean_bar(&self) -> f64 {
+        self.bar_duration / self.bar_lengths.len().max(1) as f64
+    }
+
+    /// Start of the first bar at or after the current time. Each bar start is rounded on its
+    /// own like the onsets in it, so switching after many bars still lands on a downbeat.
+    fn next_bar_start(&mut self) -> usize {
+        let elapsed = self.time.saturating_sub(self.bar_start);
+        let bar_at = |k: usize| self.bar_line(k).round() as usize;
+        let mut k = (elapsed as f64 / self.mean_bar()).ceil() as usize;
+        while k > 0 && bar_at(k - 1) >= elapsed {
+            k -= 1;
+        }
+        while bar_at(k) < elapsed {
+            k += 1;
+        }
+        self.bar_start + bar_at(k)
+    }
+
+    /// Index of the bar the bar gate is in at `time` and when it ends, None before the
+    /// gate starts along with when it does
+    fn gate_bar_at(&self, time: usize) -> (Option<usize>, usize) {
+        if time < self.gate_start {
+            return (None, self.gate_start);
+        }
+        let elapsed = time - self.gate_start;
+        let bar_at = |k: usize| self.bar_line(k).round() as usize;
+        let mut k = (elapsed as f64 / self.mean_bar()) as usize;
+        while k > 0 && bar_at(k) > elapsed {
+            k -= 1;
+        }
+        while bar_at(k + 1) <= elapsed {
+            k += 1;
+        }
+        (Some(k), self.gate_start + bar_at(k + 1))
+    }
+
+    /// Silences the mixed clicks of the `frames` from `time` that fall into muted bars
+    fn apply_bar_gate(&mut self, time: usize, frames: usize, stereo: bool) {
+        let Some(gate) = self.bar_gate.filter(|_| self.bar_duration >= 1.0) else {
+            return;
+        };
+        let mut from = time;
+        while from < time + frames {
+            let (bar, end) = self.gate_bar_at(from);
+            let to = end.min(time + frames);
+            if bar.is_some_and(|bar| gate.is_muted(bar)) {
+                let range = from - time..to - time;
+                self.tmp_buffer[range.clone()].fill(0.0);
+                if stereo {
+                    self.tmp_left[range.clone()].fill(0.0);
+                    self.tmp_right[range].fill(0.0);
+                }
+            }
+            from = to;
+        }
+    }
+
+    fn handle(&mut self, cmd: PlayerCommand) {
+        match cmd {
+            PlayerCommand::AddPlaybacks(new_playbacks) => {
+                self.has_finite_playbacks |= new_playbacks.iter().any(|p| p.end().is_some());
+                let time = self.time;
+                self.playbacks
+                    .extend(new_playbacks.into_iter().map(|p| Playback {
+                        start: p.start + time,
+                        ..p
+                    }));
+            }
+            PlayerCommand::ClearPlaybacks => {
+                // Clearing is intentional, so it never counts as finishing.
+                // What is sounding right now fades out, nothing new starts.
+                let time = self.time;
+                if self.fading.is_empty() {
+                    self.fading_frames = 0;
+                }
+                self.fading.extend(
+                    self.playbacks
+                        .drain(..)
+                        .filter_map(|mut p| p.stop_before(time).then_some(p)),
+                );
+                self.has_finite_playbacks = false;
+                self.bar_duration = 0.0;
+            }
+            PlayerCommand::RemovePlaybacks(tag) => {
+                self.playbacks.retain(|p| p.tag != Some(tag));
+            }
+            PlayerCommand::ReplaceLayer { layer, playbacks } => {
+                let time = self.time;
+                self.playbacks
+                    .retain_mut(|p| p.layer != layer || p.stop_before(time));
+                self.has_finite_playbacks |= playbacks.iter().any(|p| p.end().is_some());
+                self.playbacks
+                    .extend(playbacks.into_iter().map(|p| Playback {
+                        start: p.start + time,
+                        layer,
+                        ..p
+                    }));
+            }
+            PlayerCommand::ClearLayer(layer) => {
+                self.playbacks.retain(|p| p.layer != layer);
+            }
+            PlayerCommand::SetLayerGain { layer, gain } => {
+                self.layers.entry(layer).or_default().gain = gain;
+            }
+            PlayerCommand::SetLayerMuted { layer, muted } => {
+                self.layers.entry(layer).or_default().muted = muted;
+            }
+            PlayerCommand::SetLayerSolo { layer, solo } => {
+                self.layers.entry(layer).or_default().solo = solo;
+            }
+            PlayerCommand::ReplacePlaybacks {
+                playbacks,
+                bar_lengths,
+                at_next_bar,
+                at_time,
+                count_in,
+            } => {
+                let switch_time = if let Some(at_time) = at_time {
+                    at_time.max(self.time)
+                } else if at_next_bar && self.bar_duration >= 1.0 {
+                    self.next_bar_start()
+                } else {
+                    self.time
+                };
+
+                // Old playbacks keep sounding up to the switch, so there is no gap or overlap.
+                // Other layers keep their own schedule.
+                self.playbacks
+                    .retain_mut(|p| p.layer != MAIN_LAYER || p.stop_before(switch_time));
+                self.has_finite_playbacks = playbacks.iter().any(|p| p.end().is_some());
+                self.playbacks
+                    .extend(playbacks.into_iter().map(|p| Playback {
+                        start: p.start + switch_time,
+                        ..p
+                    }));
+                self.bar_start = switch_time;
+                self.bar_duration = bar_lengths.iter().sum();
+                self.bar_lengths = bar_lengths;
+                self.gate_start = switch_time + count_in;
+            }
+            PlayerCommand::SetSamples { tag, samples } => {
+                // Timing is untouched, a repetition that is currently sounding continues
+                // with the new samples from the same position.
+                for p in self.playbacks.iter_mut().filter(|p| p.tag == Some(tag)) {
+                    p.samples = samples.clone();
+                }
+            }
+            PlayerCommand::NudgePhase(offset) => {
+                // A sustained tone has no phase to speak of, shifting it would only leave a gap
+                for p in self.playbacks.iter_mut().filter(|p| !p.loop_sustain) {
+                    p.start = shift_time(p.start, offset, p.repetition_period);
+                }
+                let bar = self.bar_duration.round() as usize;
+                self.bar_start = shift_time(self.bar_start, offset, bar);
+                self.gate_start = shift_time(self.gate_start, offset, bar);
+            }
+            PlayerCommand::RampVolume { target, frames } => {
+                self.volume_target = target as f64;
+                self.volume_step = (self.volume_target - self.volume).abs() / frames.max(1) as f64;
+            }
+            PlayerCommand::FadeOut(frames) => {
+                self.fade_step = 1.0 / frames.max(1) as f32;
+            }
+            PlayerCommand::SetPlaying(playing) => {
+                self.playing = playing;
+            }
+            PlayerCommand::SetChannelGains(mut new_gains) => {
+                // Missing entries default to unity gain, extra entries are ignored
+                new_gains.resize(self.num_channels, 1.0);
+                self.channel_gains = new_gains;
+            }
+            PlayerCommand::SetMono(mono) => {
+                self.mono = mono;
+            }
+            PlayerCommand::SetOverlapWindow(window) => {
+                self.overlap_window = window;
+            }
+            PlayerCommand::SetMaxVoices(max_voices) => {
+                self.max_voices = max_voices.max(1);
+            }
+            PlayerCommand::BypassLimiter(bypass) => {
+                self.bypass_limiter = bypass;
+            }
+            PlayerCommand::SetMonitor(monitor) => {
+                self.monitor = monitor;
+            }
+            PlayerCommand::SetBarGate(gate) => {
+                self.bar_gate = gate;
+            }
+            PlayerCommand::PlayTrack { mut track, delay } => {
+                track.start = self.bar_start.max(self.time) + delay;
+                self.track = Some(track);
+            }
+            PlayerCommand::StopTrack => {
+                self.track = None;
+            }
+            PlayerCommand::SetTrackVolume(volume) => {
+                self.track_volume = volume;
+            }
+        }
+    }
+
+    /// Mixes the cleared playbacks into the temporary buffers along the fade,
+    /// forgetting them once it is over
+    fn mix_fading(&mut self, time: usize, frames: usize, stereo: bool) {
+        let half = self.tmp_fading.len() / 2;
+        let (fade_left, fade_right) = self.tmp_fading.split_at_mut(half);
+        let (fade_left, fade_right) = (&mut fade_left[..frames], &mut fade_right[..frames]);
+        fade_left.fill(0.0);
+        fade_right.fill(0.0);
+        for p in &self.fading {
+            let gain = layer_gain(&self.layers, p.layer);
+            if stereo {
+                // Unpanned playbacks land on both sides at full gain, like the mono mix does
+                p.read_panned_scaled(time, fade_left, fade_right, gain);
+            } else {
+                p.read_scaled(time, fade_left, gain);
+            }
+        }
+
+        let ramp = fade_out_gains(CLEAR_FADE_FRAMES, self.fading_frames, frames);
+        if stereo {
+            let left = self.tmp_left[..frames].iter_mut().zip(fade_left.iter());
+            let right = self.tmp_right[..frames].iter_mut().zip(fade_right.iter());
+            for (((l, fl), (r, fr)), gain) in left.zip(right).zip(ramp) {
+                *l += fl * gain;
+                *r += fr * gain;
+            }
+        } else {
+            let mono = self.tmp_buffer[..frames].iter_mut().zip(fade_left.iter());
+            for ((m, f), gain) in mono.zip(ramp) {
+                *m += f * gain;
+            }
+        }
+
+        self.fading_frames += frames;
+        if self.fading_frames >= CLEAR_FADE_FRAMES {
+            self.fading.clear();
+        }
+    }
+
+    /// Renders the next interleaved frames into `data`
+    fn process(&mut self, data: &mut [f32]) {
+        let max_chunk = self.tmp_buffer.len() * self.num_channels;
+        for data in data.chunks_mut(max_chunk) {
+            let frames = data.len() / self.num_channels;
+            if !self.playing && self.pause_gain <= 0.0 {
+                // Nothing is read, so resuming continues exactly where the fade ended
+                data.fill(0.0);
+                if self.fade_step > 0.0 {
+                    // Already silent, a fade-out has nothing left to do
+                    self.fade_gain = 0.0;
+                }
+                continue;
+            }
+
+            // Read unpanned playbacks into temporary buffer in mono format,
+            // panned and stereo ones into a separate stereo pair if there is more than one channel
+            let mono = &mut self.tmp_buffer[..frames];
+            let left = &mut self.tmp_left[..frames];
+            let right = &mut self.tmp_right[..frames];
+            let stereo = !self.mono
+                && self.num_channels > 1
+                && (self.playbacks.iter().any(|p| p.pan != 0.0 || p.stereo)
+                    || self.track.as_ref().is_some_and(|t| t.stereo));
+            mono.fill(0.0);
+            if stereo {
+                left.fill(0.0);
+                right.fill(0.0);
+            }
+
+            let time = self.time;
+            let layers = &self.layers;
+            let beat_events = &self.beat_events;
+            let overlap_window = self.overlap_window;
+            let onsets = &mut self.tmp_onsets;
+            onsets.clear();
+            onsets.extend(self.playbacks.iter().map(|p| p.last_onset(time + frames)));
+
+            // Voice stealing: of the playbacks still sounding, only the newest ones are read
+            let voices = &mut self.tmp_voices;
+            voices.clear();
+            voices.extend(
+                self.playbacks
+                    .iter()
+                    .zip(onsets.iter())
+                    .filter_map(|(p, onset)| onset.filter(|&onset| onset + p.frames() > time)),
+            );
+            let oldest_kept = (voices.len() > self.max_voices).then(|| {
+                *voices
+                    .select_nth_unstable_by(self.max_voices - 1, |a, b| b.cmp(a))
+                    .1
+            });
+
+            // Every repetition that could overlap one sounding in this block, sustained loops
+            // are left alone
+            let nearby = &mut self.tmp_nearby;
+            nearby.clear();
+            if let Some(window) = overlap_window {
+                let clicks = || self.playbacks.iter().filter(|p| !p.loop_sustain);
+                let longest = clicks().map(|p| p.frames()).max().unwrap_or(0);
+                let from = (time + 1).saturating_sub(longest + window);
+                for p in clicks() {
+                    let starts = p.onsets(from..time + frames + window);
+                    nearby.extend(starts.filter(|&start| !p.is_dropped(start)));
+                }
+                nearby.sort_unstable();
+            }
+            let nearby = &*nearby;
+
+            let mut index = 0;
+            self.playbacks.retain(|p| {
+                let onset = onsets[index];
+                index += 1;
+                if matches!((oldest_kept, onset), (Some(kept), Some(onset)) if onset < kept) {
+                    return true;
+                }
+
+                if let Some(events) = beat_events.as_ref().filter(|_| !p.loop_sustain) {
+                    for sample_time in p
+                        .onsets(time..time + frames)
+                        .filter(|&onset| !p.is_dropped(onset))
+                    {
+                        // Never blocks, the UI missing a flash beats a dropout
+                        let _ = events.try_send(BeatEvent {
+                            playback_index: index - 1,
+                            sample_time,
+                        });
                     }
                 }
 
-                // Read playbacks into temporary buffer in mono format
-                let mono = &mut tmp_buffer[..(data.len() / num_channels)];
-                mono.fill(0.0);
-                playbacks.retain(|p| match p.read(time, mono) {
+                let layer_gain = layer_gain(layers, p.layer);
+                // Equal power split between the clicks starting together, before the limiter.
+                // Taken from when each repetition starts, so it holds while the click sounds.
+                let gain = |start: usize| match overlap_window.filter(|_| !p.loop_sustain) {
+                    Some(window) => {
+                        let first = nearby.partition_point(|&other| other + window <= start);
+                        let last = nearby.partition_point(|&other| other < start + window);
+                        layer_gain / ((last - first).max(1) as f32).sqrt()
+                    }
+                    None => layer_gain,
+                };
+                let result = if stereo && (p.pan != 0.0 || p.stereo) {
+                    p.read_panned_scaled_by(time, left, right, gain)
+                } else {
+                    p.read_scaled_by(time, mono, gain)
+                };
+                match result {
                     ReadResult::Ok => true,
                     ReadResult::NotYetStarted => true,
                     ReadResult::Ended => false,
-                });
+                }
+            });
+            if self.has_finite_playbacks && self.playbacks.is_empty() {
+                self.has_finite_playbacks = false;
+                self.finished = true;
+            }
+            if !self.fading.is_empty() {
+                self.mix_fading(time, frames, stereo);
+            }
+            self.apply_bar_gate(time, frames, stereo);
+            let mono = &mut self.tmp_buffer[..frames];
+            let left = &mut self.tmp_left[..frames];
+            let right = &mut self.tmp_right[..frames];
+
+            // Volume, backing track, clipping and the fade-out before shutting down
+            let (volume_target, volume_step) = (self.volume_target, self.volume_step);
+            let fade_step = self.fade_step;
+            let pause_step = if self.playing { 1.0 } else { -1.0 } / PAUSE_FADE_FRAMES as f32;
+            let mut volume = self.volume;
+            let mut fade_gain = self.fade_gain;
+            let mut pause_gain = self.pause_gain;
+            let track_volume = self.track_volume;
+            let mut track = self.track.take();
+            let mut frame_time = time;
+            let limit = if self.bypass_limiter { clamp } else { limit };
+            let mut next_track_frame = || {
+                let frame = track.as_mut().and_then(|t| t.next_frame(frame_time));
+                if frame.is_none() {
+                    track = None;
+                }
+                frame_time += 1;
+                let (l, r) = frame.unwrap_or_default();
+                (l * track_volume, r * track_volume)
+            };
+            let mut next_gains = || {
+                volume = if volume < volume_target {
+                    f64::min(volume + volume_step, volume_target)
+                } else {
+                    f64::max(volume - volume_step, volume_target)
+                };
+                fade_gain = f32::max(fade_gain - fade_step, 0.0);
+                pause_gain = (pause_gain + pause_step).clamp(0.0, 1.0);
+                (volume as f32, fade_gain * pause_gain)
+            };
+            if stereo {
+                for ((l, r), m) in left.iter_mut().zip(right.iter_mut()).zip(mono.iter()) {
+                    let (volume, fade) = next_gains();
+                    let (track_l, track_r) = next_track_frame();
+                    *l = fade * limit(volume * (*l + *m) + track_l);
+                    *r = fade * limit(volume * (*r + *m) + track_r);
+                }
+            } else {
                 for f in mono.iter_mut() {
-                    // Volume and clipping
-                    *f = (volume * *f).tanh();
+                    let (volume, fade) = next_gains();
+                    let (track_l, track_r) = next_track_frame();
+                    *f = fade * limit(volume * *f + (track_l + track_r) * 0.5);
                 }
-                time += mono.len();
-
-                // Convert mono to as many channels as needed
-                for ch in 0..num_channels {
-                    data.iter_mut()
-                        .skip(ch)
-                        .step_by(num_channels)
-                        .zip(mono.iter())
-                        .for_each(|(d, s)| *d = *s);
+            }
+            self.volume = volume;
+            self.fade_gain = fade_gain;
+            self.pause_gain = pause_gain;
+            self.track = track;
+            self.time += frames;
+
+            if let Some(monitor) = &self.monitor {
+                if stereo {
+                    monitor.push(left.iter().zip(right.iter()).map(|(l, r)| (l + r) * 0.5));
+                } else {
+                    monitor.push(mono.iter().copied());
                 }
-            },
-            |e| eprintln!("an error occurred on the output audio stream: {}", e),
-            None,
-        )?;
+            }
 
+            if self.num_channels == 1 {
+                // Already in the output format, nothing to spread over channels
+                let gain = self.channel_gains[0];
+                data.iter_mut()
+                    .zip(mono.iter())
+                    .for_each(|(d, s)| *d = *s * gain);
+                continue;
+            }
+
+            // Convert to as many channels as needed, channels past the stereo pair get the center
+            for (ch, &gain) in self.channel_gains.iter().enumerate() {
+                let samples = data.iter_mut().skip(ch).step_by(self.num_channels);
+                match (stereo, ch) {
+                    (false, _) => samples.zip(mono.iter()).for_each(|(d, s)| *d = *s * gain),
+                    (true, 0) => samples.zip(left.iter()).for_each(|(d, s)| *d = *s * gain),
+                    (true, 1) => samples.zip(right.iter()).for_each(|(d, s)| *d = *s * gain),
+                    (true, _) => samples
+                        .zip(left.iter().zip(right.iter()))
+                        .for_each(|(d, (l, r))| *d = (*l + *r) * 0.5 * gain),
+                }
+            }
+        }
+    }
+}
+
+/// Soft-clips a mixed sample into [-1, 1]. Overflowing sums can cancel out to NaN
+/// (inf - inf), those are silenced instead of being sent to the speakers.
+fn limit(sample: f32) -> f32 {
+    if sample.is_nan() {
+        0.0
+    } else {
+        sample.tanh()
+    }
+}
+
+/// Like `limit`, but linear within [-1, 1]
+fn clamp(sample: f32) -> f32 {
+    if sample.is_nan() {
+        0.0
+    } else {
+        sample.clamp(-1.0, 1.0)
+    }
+}
+
+/// Shifts `time` by `offset` samples. Where that would go below zero, whole periods
+/// are added instead, which keeps looping playbacks in the same phase.
+fn shift_time(time: usize, offset: isize, period: usize) -> usize {
+    let mut shifted = time as isize + offset;
+    if shifted < 0 && period > 0 {
+        shifted = shifted.rem_euclid(period as isize);
+    }
+    shifted.max(0) as usize
+}
+
+/// Gains of a linear fade from 1 to 0 over `fade_frames`, for the `len` frames starting
+/// `from` frames into it. Stays at 0 past its end.
+pub fn fade_out_gains(fade_frames: usize, from: usize, len: usize) -> impl Iterator<Item = f32> {
+    let fade_frames = fade_frames.max(1) as f32;
+    (from..from + len).map(move |frame| f32::max(1.0 - frame as f32 / fade_frames, 0.0))
+}
+
+/// Renders `frames` interleaved frames of the given playbacks without touching any audio device,
+/// using the same mixing path as the output stream.
+pub fn render_offline(
+    playbacks: Vec<Playback>,
+    sample_rate: usize,
+    channels: usize,
+    frames: usize,
+) -> Vec<f32> {
+    let mut mixer = Mixer::new(channels);
+    mixer.handle(PlayerCommand::AddPlaybacks(playbacks));
+
+    // Feed the mixer in blocks of a typical callback size (10ms)
+    let block_len = usize::max(sample_rate / 100, 1) * channels;
+    let mut output = vec![0.0; frames * channels];
+    for block in output.chunks_mut(block_len) {
+        mixer.process(block);
+    }
+    output
+}
+
+/// A click layer mixed over the main pattern of an exported track
+pub struct TrackLayer {
+    pub id: u64,
+    pub playbacks: Vec<Playback>,
+    pub volume_db: f32,
+    pub muted: bool,
+    pub solo: bool,
+}
+
+/// An exported click track, the pattern with everything that changes how it sounds
+pub struct ClickTrack {
+    pub playbacks: Vec<Playback>,
+    /// Exact samples in each bar of the loop, as in `Schedule::bar_lengths`
+    pub bar_lengths: Vec<f64>,
+    /// Bars to render, counting each bar of an alternating loop
+    pub bars: usize,
+    pub volume_db: f32,
+    pub bar_gate: Option<BarGate>,
+    pub main_solo: bool,
+    pub layers: Vec<TrackLayer>,
+}
+
+/// Renders `track` on `mix` with its volume, bar gate and layers, so it sounds like what
+/// plays. `mix` is a fresh player with the output settings applied. Returns the
+/// interleaved frames.
+pub fn export_click_track(mix: &mut Player<NullSink>, track: ClickTrack) -> Vec<f32> {
+    let bars = track.bar_lengths.iter().cycle().take(track.bars);
+    let frames = bars.sum::<f64>().round() as usize;
+    mix.ramp_volume_db(track.volume_db, Duration::ZERO);
+    mix.set_bar_gate(track.bar_gate);
+    mix.set_layer_solo(MAIN_LAYER, track.main_solo);
+    for layer in track.layers {
+        mix.replace_layer(layer.id, layer.playbacks);
+        mix.set_layer_volume_db(layer.id, layer.volume_db);
+        mix.set_layer_muted(layer.id, layer.muted);
+        mix.set_layer_solo(layer.id, layer.solo);
+    }
+    mix.replace_playbacks_with_count_in(track.playbacks, track.bar_lengths, false, None, 0);
+
+    // In blocks of a typical callback size (10ms)
+    let channels = mix.num_channels();
+    let block = (mix.sample_rate() / 100).max(1);
+    let mut output = Vec::with_capacity(frames * channels);
+    while output.len() < frames * channels {
+        let len = block.min(frames - output.len() / channels);
+        output.extend(mix.sink_mut().render(len));
+    }
+    output
+}
+
+/// Plays the mixed playbacks on an `AudioSink`, the default output device via cpal by default
+pub struct Player<S: AudioSink = CpalSink> {
+    sink: S,
+    send: Sender<PlayerCommand>,
+    shared: Arc<SharedState>,
+    volume_range_db: RangeInclusive<f32>,
+    beat_events: Receiver<BeatEvent>,
+    last_reconnect: Option<Instant>,
+}
+impl Player {
+    pub fn start() -> anyhow::Result<Player> {
+        Self::start_with_buffer(None)
+    }
+
+    /// Starts playback requesting a fixed buffer size in frames.
+    /// Falls back to the device's default buffer size if the requested one is not supported.
+    pub fn start_with_buffer(buffer_size: Option<u32>) -> anyhow::Result<Player> {
+        Self::start_with(buffer_size, None)
+    }
+
+    /// Like `start_with_buffer`, additionally opening the device with `channels` output
+    /// channels. Falls back to the device's default channel count if it can't do that.
+    pub fn start_with(buffer_size: Option<u32>, channels: Option<u16>) -> anyhow::Result<Player> {
+        Player::with_sink(CpalSink::open(None, buffer_size, channels)?)
+    }
+
+    /// Starts playback on the output device called `name`, or the default one if it is gone
+    pub fn start_with_device(name: &str) -> anyhow::Result<Player> {
+        Player::with_sink(CpalSink::open(Some(name), None, None)?)
+    }
+
+    /// Names of the output devices `start_with_device` can use
+    pub fn list_output_devices() -> anyhow::Result<Vec<String>> {
+        CpalSink::output_devices()
+    }
+}
+impl<S: AudioSink> Player<S> {
+    /// Starts playback on any sink
+    pub fn with_sink(mut sink: S) -> anyhow::Result<Player<S>> {
+        let shared = Arc::new(SharedState::default());
+        let (send, recv) = std::sync::mpsc::channel();
+        let (send_beats, beat_events) = std::sync::mpsc::sync_channel(BEAT_EVENTS_CAPACITY);
+        let mut mixer = Mixer::new(sink.num_channels());
+        mixer.beat_events = Some(send_beats);
+        sink.play(render_callback(mixer, recv, shared.clone()))?;
         Ok(Player {
-            config,
-            _stream: stream,
+            sink,
             send,
+            shared,
+            volume_range_db: DEFAULT_VOLUME_RANGE_DB,
+            beat_events,
+            last_reconnect: None,
         })
     }
 
+    /// Whether the output is running, false from a device failure until `keep_connected`
+    /// could reopen it
+    pub fn is_connected(&self) -> bool {
+        self.sink.is_connected()
+    }
+
+    /// Reopens the output if it failed, trying at most every `RECONNECT_INTERVAL`. To call
+    /// regularly, e.g. every frame. The mixer keeps its playbacks and volume meanwhile, so
+    /// they carry on where the failed output stopped.
+    pub fn keep_connected(&mut self) {
+        if self.sink.is_connected()
+            || self
+                .last_reconnect
+                .is_some_and(|at| at.elapsed() < RECONNECT_INTERVAL)
+        {
+            return;
+        }
+        self.last_reconnect = Some(Instant::now());
+        if let Err(e) = self.sink.reconnect() {
+            eprintln!("Failed to reconnect the audio output: {}", e);
+        }
+    }
+
+    /// The sink, e.g. to pull audio from a `NullSink`
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
     pub fn sample_rate(&self) -> usize {
-        self.config.sample_rate().0 as usize
+        self.sink.sample_rate()
+    }
+
+    /// The fixed buffer size in frames, or None if the device default is used
+    pub fn buffer_size(&self) -> Option<u32> {
+        self.sink.buffer_size()
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.sink.num_channels()
+    }
+
+    /// Number of playbacks held by the audio thread as of its last callback
+    pub fn active_playbacks(&self) -> usize {
+        self.shared.active_playbacks.load(Ordering::Relaxed)
+    }
+
+    /// Returns true once after all finite playbacks (e.g. with a repetition count) have ended.
+    /// Clearing the playbacks does not count as finishing.
+    pub fn take_finished(&self) -> bool {
+        self.shared.finished.swap(false, Ordering::Relaxed)
+    }
+
+    /// Output frames rendered before the last callback. Published by the audio thread without
+    /// synchronization, so it may lag a callback behind what was just mixed.
+    pub fn position_samples(&self) -> usize {
+        self.shared.time.load(Ordering::Relaxed)
+    }
+
+    /// `position_samples` in seconds
+    pub fn position_seconds(&self) -> f64 {
+        self.position_samples() as f64 / self.sample_rate() as f64
+    }
+
+    /// Frames since the current pattern started, if one was set via `replace_playbacks`.
+    /// Negative while the pattern is waiting for the previous one's bar to end.
+    pub fn pattern_position(&self) -> Option<isize> {
+        self.pattern_position_at(self.position_samples())
+    }
+
+    /// Frames from the start of the current pattern to mixer time `time`, e.g. of a `BeatEvent`
+    pub fn pattern_position_at(&self, time: usize) -> Option<isize> {
+        if self.shared.bar_duration.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let bar_start = self.shared.bar_start.load(Ordering::Relaxed);
+        Some(time as isize - bar_start as isize)
     }
 
     pub fn add_playbacks(&self, playbacks: Vec<Playback>) {
@@ -181,9 +1488,359 @@ impl Player {
         self.send.send(PlayerCommand::ClearPlaybacks).unwrap();
     }
 
+    /// Removes all playbacks tagged with `tag`, leaving the others untouched
+    pub fn remove_playbacks_by_tag(&self, tag: u64) {
+        self.send.send(PlayerCommand::RemovePlaybacks(tag)).unwrap();
+    }
+
+    /// Starts the playbacks on `layer` right away, stopping the layer's previous ones.
+    /// Layers other than `MAIN_LAYER` run on their own, unaffected by `replace_playbacks`.
+    pub fn replace_layer(&self, layer: u64, playbacks: Vec<Playback>) {
+        self.send
+            .send(PlayerCommand::ReplaceLayer { layer, playbacks })
+            .unwrap();
+    }
+
+    pub fn clear_layer(&self, layer: u64) {
+        self.send.send(PlayerCommand::ClearLayer(layer)).unwrap();
+    }
+
+    /// Volume of a layer, applied before the output volume and the limiter
+    pub fn set_layer_volume_db(&self, layer: u64, volume_db: f32) {
+        self.send
+            .send(PlayerCommand::SetLayerGain {
+                layer,
+                gain: 10.0f32.powf(volume_db / 20.0),
+            })
+            .unwrap();
+    }
+
+    /// Silences a layer while it keeps running, so it comes back in time
+    pub fn set_layer_muted(&self, layer: u64, muted: bool) {
+        self.send
+            .send(PlayerCommand::SetLayerMuted { layer, muted })
+            .unwrap();
+    }
+
+    /// Plays only the soloed layers while any is soloed, `MAIN_LAYER` included.
+    /// The others keep running silently, like muted ones.
+    pub fn set_layer_solo(&self, layer: u64, solo: bool) {
+        self.send
+            .send(PlayerCommand::SetLayerSolo { layer, solo })
+            .unwrap();
+    }
+
+    /// Replaces all playbacks with a pattern of bars of `bar_duration` samples, which needn't
+    /// be a whole number.
+    /// With `at_next_bar` the switch waits for the end of the current pattern's bar,
+    /// keeping the downbeats in phase. Otherwise the new pattern starts right away.
+    pub fn replace_playbacks(
+        &self,
+        playbacks: Vec<Playback>,
+        bar_duration: f64,
+        at_next_bar: bool,
+    ) {
+        self.send
+            .send(PlayerCommand::ReplacePlaybacks {
+                playbacks,
+                bar_lengths: vec![bar_duration],
+                at_next_bar,
+                at_time: None,
+                count_in: 0,
+            })
+            .unwrap();
+    }
+
+    /// Like `replace_playbacks`, but the new pattern's first frame is heard at `instant`
+    /// (or right away if that has passed). The current pattern plays on until then.
+    pub fn start_at(&self, playbacks: Vec<Playback>, bar_duration: f64, instant: Instant) {
+        self.send
+            .send(PlayerCommand::ReplacePlaybacks {
+                playbacks,
+                bar_lengths: vec![bar_duration],
+                at_next_bar: false,
+                at_time: Some(self.time_at(instant)),
+                count_in: 0,
+            })
+            .unwrap();
+    }
+
+    /// Like `replace_playbacks`, or `start_at` with an `instant`, for playbacks that begin
+    /// with `count_in` frames of count-in and loop over bars of `bar_lengths`, which needn't
+    /// all be the same. The bar gate counts its bars from the end of the count-in, so the
+    /// count-in always plays.
+    pub fn replace_playbacks_with_count_in(
+        &self,
+        playbacks: Vec<Playback>,
+        bar_lengths: Vec<f64>,
+        at_next_bar: bool,
+        instant: Option<Instant>,
+        count_in: usize,
+    ) {
+        self.send
+            .send(PlayerCommand::ReplacePlaybacks {
+                playbacks,
+                bar_lengths,
+                at_next_bar,
+                at_time: instant.map(|instant| self.time_at(instant)),
+                count_in,
+            })
+            .unwrap();
+    }
+
+    /// The mixer time at the start of the last callback and when it is heard,
+    /// from the wall-clock time and output latency of that callback
+    fn heard_at(&self) -> (usize, Instant) {
+        // Re-read if a callback updated the clock in between, so time and instant match
+        let clock = &self.shared.clock;
+        let (time, callback) = loop {
+            let time = self.shared.time.load(Ordering::Acquire);
+            let callback = clock.callback_nanos.load(Ordering::Acquire);
+            if self.shared.time.load(Ordering::Acquire) == time {
+                break (time, clock.epoch + Duration::from_nanos(callback));
+            }
+        };
+        let heard = callback + Duration::from_nanos(clock.latency_nanos.load(Ordering::Relaxed));
+        (time, heard)
+    }
+
+    /// Estimates the mixer time that will be heard at `instant`
+    fn time_at(&self, instant: Instant) -> usize {
+        let (time, heard) = self.heard_at();
+        let frames = |d: Duration| (d.as_secs_f64() * self.sample_rate() as f64).round() as usize;
+        match instant.checked_duration_since(heard) {
+            Some(ahead) => time + frames(ahead),
+            None => time.saturating_sub(frames(heard - instant)),
+        }
+    }
+
+    /// Estimates when the mixer time `time` is heard, e.g. of a `BeatEvent`
+    pub fn instant_of(&self, time: usize) -> Instant {
+        let (now, heard) = self.heard_at();
+        let secs =
+            |frames: usize| Duration::from_secs_f64(frames as f64 / self.sample_rate() as f64);
+        if time >= now {
+            heard + secs(time - now)
+        } else {
+            heard.checked_sub(secs(now - time)).unwrap_or(heard)
+        }
+    }
+
+    /// Repetitions the audio thread started mixing, to drain every frame.
+    /// Events are dropped while nobody reads them and the channel is full.
+    pub fn beat_events(&self) -> &Receiver<BeatEvent> {
+        &self.beat_events
+    }
+
+    /// Fades the output to silence, blocking until the audio thread has played the fade.
+    /// Called on drop, so the stream never stops in the middle of a click.
+    pub fn stop(&self) {
+        let fade_frames = self.sample_rate() * FADE_OUT_MS / 1000;
+        if self.send.send(PlayerCommand::FadeOut(fade_frames)).is_err() {
+            return;
+        }
+
+        // The stream may already be dead, so don't wait forever
+        let deadline = Instant::now() + Duration::from_millis(FADE_OUT_MS as u64 * 4 + 100);
+        while !self.shared.faded_out.load(Ordering::Relaxed) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Moves all playbacks `offset` samples later (or earlier if negative) without changing tempo
+    pub fn nudge_phase(&self, offset: isize) {
+        self.send.send(PlayerCommand::NudgePhase(offset)).unwrap();
+    }
+
+    /// Swaps the samples of all playbacks tagged with `tag`, keeping their timing
+    pub fn set_samples(&self, tag: u64, samples: Arc<Vec<f32>>) {
+        self.send
+            .send(PlayerCommand::SetSamples { tag, samples })
+            .unwrap();
+    }
+
+    pub fn volume_range_db(&self) -> RangeInclusive<f32> {
+        self.volume_range_db.clone()
+    }
+
+    /// Limits the volumes accepted by `set_volume_db` and `ramp_volume_db`
+    pub fn set_volume_range_db(&mut self, range: RangeInclusive<f32>) {
+        self.volume_range_db = range;
+    }
+
+    /// Clamps into the volume range, except for `f32::NEG_INFINITY` (silence)
+    fn clamp_volume_db(&self, volume_db: f32) -> f32 {
+        if volume_db == f32::NEG_INFINITY {
+            volume_db
+        } else {
+            volume_db.clamp(*self.volume_range_db.start(), *self.volume_range_db.end())
+        }
+    }
+
+    /// Sets the volume, clamped into `volume_range_db`. Gets there within a few milliseconds.
     pub fn set_volume_db(&self, volume_db: f32) {
+        let smoothing = Duration::from_millis(VOLUME_SMOOTHING_MS as u64);
+        self.ramp_volume_db(volume_db, smoothing);
+    }
+
+    /// Ramps the volume to `volume_db` over `duration` without audible steps.
+    /// `f32::NEG_INFINITY` fades to silence. Setting the volume directly replaces the ramp
+    /// with a short one.
+    pub fn ramp_volume_db(&self, volume_db: f32, duration: Duration) {
+        let volume_db = self.clamp_volume_db(volume_db);
+        let frames = (duration.as_secs_f64() * self.sample_rate() as f64) as usize;
+        self.send
+            .send(PlayerCommand::RampVolume {
+                target: 10.0f32.powf(volume_db / 20.0),
+                frames,
+            })
+            .unwrap();
+    }
+
+    /// Plays a backing track under the click, starting `delay` frames from now.
+    /// Replacing the playbacks right before starts both in sync, with `delay` counted
+    /// from the start of the new pattern.
+    pub fn play_track(&self, track: BackingTrack, delay: usize) {
+        self.send
+            .send(PlayerCommand::PlayTrack { track, delay })
+            .unwrap();
+    }
+
+    pub fn stop_track(&self) {
+        self.send.send(PlayerCommand::StopTrack).unwrap();
+    }
+
+    /// Sets the backing track volume, independent of the click volume
+    pub fn set_track_volume_db(&self, volume_db: f32) {
+        self.send
+            .send(PlayerCommand::SetTrackVolume(
+                10.0f32.powf(volume_db / 20.0),
+            ))
+            .unwrap();
+    }
+
+    /// Whether a backing track is playing or waiting to start, as of the last callback
+    pub fn track_playing(&self) -> bool {
+        self.shared.track_playing.load(Ordering::Relaxed)
+    }
+
+    /// Sets a linear gain trim per output channel.
+    /// Entries beyond `num_channels()` are ignored, missing entries default to 1.0.
+    pub fn set_channel_gains(&self, gains: Vec<f32>) {
+        if gains.len() != self.num_channels() {
+            eprintln!(
+                "Got {} channel gains for {} output channels",
+                gains.len(),
+                self.num_channels()
+            );
+        }
+        self.send
+            .send(PlayerCommand::SetChannelGains(gains))
+            .unwrap();
+    }
+
+    /// Plays the same signal on all channels, leaving out any panning
+    pub fn set_mono(&self, mono: bool) {
+        self.send.send(PlayerCommand::SetMono(mono)).unwrap();
+    }
+
+    /// Sends a copy of the mixed output, downmixed to mono and before the channel trims,
+    /// to `ring` from now on. Read it with the returned consumer, samples that don't fit
+    /// because it isn't read often enough are dropped.
+    pub fn set_monitor(&self, ring: MonitorRing) -> MonitorConsumer {
+        let ring = Arc::new(ring);
+        self.send
+            .send(PlayerCommand::SetMonitor(Some(ring.clone())))
+            .unwrap();
+        MonitorConsumer::new(ring)
+    }
+
+    /// Stops copying the output to the monitor
+    pub fn clear_monitor(&self) {
+        self.send.send(PlayerCommand::SetMonitor(None)).unwrap();
+    }
+
+    /// Mutes the output during some of the bars of the pattern set with `replace_playbacks`,
+    /// counted from its start. The pattern keeps running, so the clicks come back in time.
+    pub fn set_bar_gate(&self, gate: Option<BarGate>) {
+        self.send.send(PlayerCommand::SetBarGate(gate)).unwrap();
+    }
+
+    /// Pauses or resumes the output. The position stops while paused, so resuming
+    /// continues in the same place of the bar. Both fade briefly to avoid a click.
+    pub fn set_playing(&self, playing: bool) {
+        self.send.send(PlayerCommand::SetPlaying(playing)).unwrap();
+    }
+
+    /// Turns off the soft clipping of the output for a linear signal, e.g. to measure it.
+    /// Samples beyond [-1, 1] are still clamped.
+    pub fn bypass_limiter(&self, bypass: bool) {
+        self.send
+            .send(PlayerCommand::BypassLimiter(bypass))
+            .unwrap();
+    }
+
+    /// Limits the playbacks sounding at once, cutting off the oldest ones beyond `max_voices`.
+    /// `DEFAULT_MAX_VOICES` until set.
+    pub fn set_max_voices(&self, max_voices: usize) {
         self.send
-            .send(PlayerCommand::SetVolume(10.0f32.powf(volume_db / 20.0)))
+            .send(PlayerCommand::SetMaxVoices(max_voices))
             .unwrap();
     }
+
+    /// Attenuates clicks starting within a few milliseconds of each other, so dense
+    /// patterns don't sum up into the limiter
+    pub fn set_overlap_protection(&self, enabled: bool) {
+        let window = enabled.then_some(self.sample_rate() * OVERLAP_WINDOW_MS / 1000);
+        self.send
+            .send(PlayerCommand::SetOverlapWindow(window))
+            .unwrap();
+    }
+}
+
+impl<S: AudioSink> Drop for Player<S> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The audio thread's side of the player: applies the commands and mixes the playbacks
+fn render_callback(
+    mut mixer: Mixer,
+    recv: Receiver<PlayerCommand>,
+    shared: Arc<SharedState>,
+) -> RenderCallback {
+    Box::new(move |data: &mut [f32], latency: Duration| {
+        shared
+            .clock
+            .latency_nanos
+            .store(latency.as_nanos() as u64, Ordering::Relaxed);
+
+        for cmd in recv.try_iter() {
+            mixer.handle(cmd);
+        }
+        // The clock refers to the start of this buffer, published before the time advances
+        shared.clock.callback_nanos.store(
+            shared.clock.epoch.elapsed().as_nanos() as u64,
+            Ordering::Release,
+        );
+        shared.time.store(mixer.time, Ordering::Release);
+        mixer.process(data);
+        shared
+            .active_playbacks
+            .store(mixer.playbacks.len(), Ordering::Relaxed);
+        shared.bar_start.store(mixer.bar_start, Ordering::Relaxed);
+        shared
+            .bar_duration
+            .store(mixer.bar_duration.round() as usize, Ordering::Relaxed);
+        if std::mem::take(&mut mixer.finished) {
+            shared.finished.store(true, Ordering::Relaxed);
+        }
+        shared
+            .track_playing
+            .store(mixer.track.is_some(), Ordering::Relaxed);
+        if mixer.fade_gain == 0.0 {
+            shared.faded_out.store(true, Ordering::Relaxed);
+        }
+    })
 }
@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+/// How long the metronome has been playing since it was last stopped, measured on the audio clock
+pub struct PracticeStreak {
+    sample_rate: usize,
+    /// Player position the current streak started at
+    started: Option<usize>,
+    /// Longest streak this session in frames, including the current one
+    longest: usize,
+}
+
+impl PracticeStreak {
+    pub fn new(sample_rate: usize) -> PracticeStreak {
+        PracticeStreak {
+            sample_rate,
+            started: None,
+            longest: 0,
+        }
+    }
+
+    /// Follows the transport, `position` is the player's output position in frames
+    pub fn update(&mut self, playing: bool, position: usize) {
+        self.longest = self.longest.max(self.frames(position));
+        match (playing, self.started) {
+            (true, None) => self.started = Some(position),
+            (false, Some(_)) => self.started = None,
+            _ => {}
+        }
+    }
+
+    fn frames(&self, position: usize) -> usize {
+        self.started
+            .map_or(0, |started| position.saturating_sub(started))
+    }
+
+    fn duration(&self, frames: usize) -> Duration {
+        Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    /// Time played since the last start, zero while stopped
+    pub fn current(&self, position: usize) -> Duration {
+        self.duration(self.frames(position))
+    }
+
+    /// The longest streak this session
+    pub fn longest(&self) -> Duration {
+        self.duration(self.longest)
+    }
+}
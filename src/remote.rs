@@ -0,0 +1,285 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    str::FromStr,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a client may take to send its request or accept a response
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest a beat stream stays quiet, so clients that left are noticed while stopped
+const HEARTBEAT: Duration = Duration::from_secs(10);
+/// Beats kept for the streams to pick up, far more than play between two of their polls
+const RECENT_BEATS: usize = 64;
+
+/// What the remote clients see, kept up to date by the UI
+#[derive(Clone, Debug)]
+pub struct RemoteState {
+    pub transport: Transport,
+    pub bpm: f32,
+    pub numerator: usize,
+    pub subdivision: usize,
+    pub volume_db: f32,
+    /// The latest beats from the audio thread, oldest first
+    beats: VecDeque<Beat>,
+}
+
+impl RemoteState {
+    pub fn new(
+        transport: Transport,
+        bpm: f32,
+        numerator: usize,
+        subdivision: usize,
+        volume_db: f32,
+    ) -> RemoteState {
+        RemoteState {
+            transport,
+            bpm,
+            numerator,
+            subdivision,
+            volume_db,
+            beats: VecDeque::new(),
+        }
+    }
+}
+
+/// A beat for the event streams
+#[derive(Clone, Copy, Debug)]
+struct Beat {
+    /// Counts up with every beat, so each stream can tell which ones it sent
+    number: u64,
+    /// When the click is heard, the event is sent then
+    heard: Instant,
+    beat_in_bar: usize,
+}
+
+/// Whether the clicks are playing, as reported in the state
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transport {
+    Stopped,
+    Playing,
+    /// Playing, but held where it was paused
+    Paused,
+}
+
+impl Transport {
+    fn name(self) -> &'static str {
+        match self {
+            Transport::Stopped => "stopped",
+            Transport::Playing => "playing",
+            Transport::Paused => "paused",
+        }
+    }
+}
+
+/// Settings changed by a remote client
+pub enum RemoteCommand {
+    /// Start or resume playing, or stop
+    Play(bool),
+    Bpm(f32),
+    Meter(usize, usize),
+    VolumeDb(f32),
+}
+
+/// A small HTTP control server.
+///
+/// - `GET /state` returns the transport and the current settings as JSON
+/// - `POST /play` starts or resumes playing, `POST /stop` stops
+/// - `POST /bpm?value=120`, `POST /meter?numerator=3&subdivision=4`, `POST /volume?db=-6`
+///   change the settings
+/// - `GET /events` is a server-sent event stream with one `beat` event per beat
+pub struct RemoteServer {
+    pub state: Arc<Mutex<RemoteState>>,
+    pub commands: Receiver<RemoteCommand>,
+}
+
+impl RemoteServer {
+    pub fn start(address: &str, initial: RemoteState) -> std::io::Result<RemoteServer> {
+        let listener = TcpListener::bind(address)?;
+        println!(
+            "Remote control listening on http://{}",
+            listener.local_addr()?
+        );
+
+        let state = Arc::new(Mutex::new(initial));
+        let (send, commands) = mpsc::channel();
+
+        let server_state = state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = server_state.clone();
+                let send = send.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &state, &send) {
+                        eprintln!("Remote control connection failed: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(RemoteServer { state, commands })
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut RemoteState)) {
+        f(&mut self.state.lock().unwrap());
+    }
+
+    /// Streams a beat of the bar to the clients once it is heard
+    pub fn beat(&self, heard: Instant, beat_in_bar: usize) {
+        let mut state = self.state.lock().unwrap();
+        let number = state.beats.back().map_or(0, |b| b.number + 1);
+        if state.beats.len() == RECENT_BEATS {
+            state.beats.pop_front();
+        }
+        state.beats.push_back(Beat {
+            number,
+            heard,
+            beat_in_bar,
+        });
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    state: &Mutex<RemoteState>,
+    send: &Sender<RemoteCommand>,
+) -> std::io::Result<()> {
+    // Without them an idle client would hold on to its thread forever
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, none of the endpoints need them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query: HashMap<&str, &str> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect();
+
+    let command = match (method, path) {
+        ("GET", "/state") => {
+            let state = state.lock().unwrap().clone();
+            return respond(
+                stream,
+                "200 OK",
+                "application/json",
+                &format!(
+                    "{{\"transport\":\"{}\",\"bpm\":{},\"numerator\":{},\"subdivision\":{},\"volume_db\":{}}}",
+                    state.transport.name(),
+                    state.bpm,
+                    state.numerator,
+                    state.subdivision,
+                    state.volume_db
+                ),
+            );
+        }
+        ("GET", "/events") => return stream_beats(stream, state),
+        ("POST", "/play") => Some(RemoteCommand::Play(true)),
+        ("POST", "/stop") => Some(RemoteCommand::Play(false)),
+        ("POST", "/bpm") => finite_param(&query, "value").map(RemoteCommand::Bpm),
+        ("POST", "/meter") => param(&query, "numerator")
+            .zip(param(&query, "subdivision"))
+            .map(|(n, d)| RemoteCommand::Meter(n, d)),
+        ("POST", "/volume") => finite_param(&query, "db").map(RemoteCommand::VolumeDb),
+        _ => return respond(stream, "404 Not Found", "text/plain", "Not found"),
+    };
+
+    match command {
+        Some(command) => {
+            // The UI is gone if this fails, nothing left to control
+            let _ = send.send(command);
+            respond(stream, "204 No Content", "text/plain", "")
+        }
+        None => respond(
+            stream,
+            "400 Bad Request",
+            "text/plain",
+            "Missing or invalid parameter",
+        ),
+    }
+}
+
+fn param<T: FromStr>(query: &HashMap<&str, &str>, name: &str) -> Option<T> {
+    query.get(name).and_then(|v| v.parse().ok())
+}
+
+/// A number parameter, rejecting the "NaN" and "inf" that parse as floats too
+fn finite_param(query: &HashMap<&str, &str>, name: &str) -> Option<f32> {
+    param(query, name).filter(|v: &f32| v.is_finite())
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn stream_beats(mut stream: TcpStream, state: &Mutex<RemoteState>) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nAccess-Control-Allow-Origin: *\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    // Only beats from after connecting are sent
+    let mut sent = state.lock().unwrap().beats.back().map(|b| b.number);
+    let mut last_write = Instant::now();
+    loop {
+        let now = Instant::now();
+        let (due, next): (Vec<Beat>, Option<Instant>) = {
+            let state = state.lock().unwrap();
+            let pending = state
+                .beats
+                .iter()
+                .filter(|b| sent.is_none_or(|n| b.number > n));
+            let (due, later): (Vec<Beat>, Vec<Beat>) = pending.partition(|b| b.heard <= now);
+            (due, later.first().map(|b| b.heard))
+        };
+
+        for beat in &due {
+            write!(
+                stream,
+                "event: beat\ndata: {{\"beat\":{}}}\n\n",
+                beat.beat_in_bar
+            )?;
+            sent = Some(beat.number);
+        }
+        if due.is_empty() && last_write.elapsed() >= HEARTBEAT {
+            // A comment, ignored by the clients. Fails once the client is gone.
+            write!(stream, ": heartbeat\n\n")?;
+        }
+        if !due.is_empty() || last_write.elapsed() >= HEARTBEAT {
+            stream.flush()?;
+            last_write = Instant::now();
+        }
+
+        // Wake up when the next beat is heard, checking for new ones regularly
+        let poll = now + Duration::from_millis(50);
+        let wake = next.map_or(poll, |next| next.min(poll));
+        std::thread::sleep(wake.saturating_duration_since(Instant::now()));
+    }
+}
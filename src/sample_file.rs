@@ -0,0 +1,66 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use metronome::samples::load_wav;
+
+/// How often a watched file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A click sample loaded from a WAV file, reloaded when the file changes on disk
+pub struct SampleFile {
+    pub path: PathBuf,
+    /// The last version that loaded, kept while reloading fails
+    pub samples: Option<Arc<Vec<f32>>>,
+    pub error: Option<String>,
+    /// Modification time of the version last read
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl SampleFile {
+    pub fn open(path: PathBuf, sample_rate: usize) -> SampleFile {
+        let mut file = SampleFile {
+            path,
+            samples: None,
+            error: None,
+            modified: None,
+            last_check: Instant::now(),
+        };
+        file.reload(sample_rate);
+        file
+    }
+
+    fn modified_on_disk(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    fn reload(&mut self, sample_rate: usize) {
+        self.modified = self.modified_on_disk();
+        match load_wav(&self.path, sample_rate) {
+            Ok(samples) => {
+                self.samples = Some(Arc::new(samples));
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Reloads the file if it changed since it was last read.
+    /// Returns true if that replaced the samples.
+    pub fn poll(&mut self, sample_rate: usize) -> bool {
+        if self.last_check.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+        if self.modified_on_disk() == self.modified {
+            return false;
+        }
+        self.reload(sample_rate);
+        self.error.is_none()
+    }
+}
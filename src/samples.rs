@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use crate::backing_track::WavReader;
+
+/// Decodes a whole WAV file into mono samples at `target_sample_rate`, e.g. for a custom click.
+/// All channels are mixed down, and the file is linearly resampled if its rate differs.
+pub fn load_wav(path: &Path, target_sample_rate: usize) -> anyhow::Result<Vec<f32>> {
+    let is_wav = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+    if !is_wav {
+        anyhow::bail!("Only WAV samples are supported");
+    }
+
+    let mut wav = WavReader::open(path)?;
+    let mut frames = Vec::new();
+    while let Some(frame) = wav.next_mono_frame()? {
+        frames.push(frame);
+    }
+    if wav.sample_rate == target_sample_rate {
+        return Ok(frames);
+    }
+
+    let step = wav.sample_rate as f64 / target_sample_rate.max(1) as f64;
+    let len = (frames.len() as f64 / step) as usize;
+    Ok((0..len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let a = frames[pos as usize];
+            let b = frames.get(pos as usize + 1).copied().unwrap_or(a);
+            a + (b - a) * pos.fract() as f32
+        })
+        .collect())
+}
@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+/// Counts down while the volume fades to silence, after which playback stops
+pub struct SleepTimer {
+    /// Length of the fade
+    pub minutes: f32,
+    started: Option<Instant>,
+}
+
+impl SleepTimer {
+    pub fn new() -> SleepTimer {
+        SleepTimer {
+            minutes: 20.0,
+            started: None,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f32(self.minutes.max(0.0) * 60.0)
+    }
+
+    pub fn start(&mut self) {
+        self.started = Some(Instant::now());
+    }
+
+    pub fn cancel(&mut self) {
+        self.started = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// Time left until the fade has reached silence, if running
+    pub fn remaining(&self) -> Option<Duration> {
+        self.started
+            .map(|started| self.duration().saturating_sub(started.elapsed()))
+    }
+
+    /// Returns true once when the timer has run out, stopping it
+    pub fn take_expired(&mut self) -> bool {
+        let expired = self.remaining().is_some_and(|r| r.is_zero());
+        if expired {
+            self.started = None;
+        }
+        expired
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+/// A pause this long starts a new measurement
+const TIMEOUT: Duration = Duration::from_secs(3);
+/// Complete bars needed before the meter is trusted
+const MIN_BARS: usize = 2;
+/// How far a single beat may stray from the typical one, relative to it
+const MAX_BEAT_DEVIATION: f64 = 0.2;
+
+/// Infers meter and tempo from tapped beats, some of which are marked as downbeats
+pub struct TapMeter {
+    /// Time of each tap and whether it was a downbeat
+    taps: Vec<(Instant, bool)>,
+}
+
+pub enum Detection {
+    /// Not enough bars tapped yet
+    Incomplete,
+    /// The taps don't agree with each other, along with what was off
+    Inconsistent(&'static str),
+    /// `bpm` counts the tapped beats
+    Meter { numerator: usize, bpm: f32 },
+}
+
+impl TapMeter {
+    pub fn new() -> TapMeter {
+        TapMeter { taps: Vec::new() }
+    }
+
+    pub fn tap(&mut self, at: Instant, downbeat: bool) {
+        if self
+            .taps
+            .last()
+            .is_some_and(|&(last, _)| at.saturating_duration_since(last) > TIMEOUT)
+        {
+            self.taps.clear();
+        }
+        // Beats before the first downbeat can't be told apart from those of an upbeat
+        if downbeat || !self.taps.is_empty() {
+            self.taps.push((at, downbeat));
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.taps.clear();
+    }
+
+    /// Number of complete bars tapped so far
+    pub fn bars(&self) -> usize {
+        self.downbeats().len().saturating_sub(1)
+    }
+
+    fn downbeats(&self) -> Vec<usize> {
+        (self.taps.iter().enumerate())
+            .filter(|(_, &(_, downbeat))| downbeat)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn detect(&self) -> Detection {
+        let downbeats = self.downbeats();
+        if downbeats.len() < MIN_BARS + 1 {
+            return Detection::Incomplete;
+        }
+
+        let numerator = downbeats[1] - downbeats[0];
+        if downbeats.windows(2).any(|w| w[1] - w[0] != numerator) {
+            return Detection::Inconsistent("the bars have different numbers of beats");
+        }
+
+        // Only whole bars count, free beats after the last downbeat are left out
+        let taps = &self.taps[..=downbeats[downbeats.len() - 1]];
+        let mut beats: Vec<f64> = taps
+            .windows(2)
+            .map(|w| w[1].0.duration_since(w[0].0).as_secs_f64())
+            .collect();
+        let total: f64 = beats.iter().sum();
+        beats.sort_by(f64::total_cmp);
+        let typical = beats[beats.len() / 2];
+        if beats
+            .iter()
+            .any(|b| (b / typical - 1.0).abs() > MAX_BEAT_DEVIATION)
+        {
+            return Detection::Inconsistent("the beats are too uneven");
+        }
+
+        Detection::Meter {
+            numerator,
+            bpm: (60.0 * beats.len() as f64 / total) as f32,
+        }
+    }
+}
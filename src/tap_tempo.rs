@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+
+/// How long after the last tap the measured tempo is applied by itself
+const SETTLE_AFTER: Duration = Duration::from_millis(1500);
+/// Taps the tempo is fitted over, older ones are forgotten so the estimate follows tempo changes
+const MAX_TAPS: usize = 6;
+/// A gap this long between taps starts a new measurement instead of blending into the last one
+const RESET_AFTER: Duration = Duration::from_secs(2);
+
+/// The note value a tap stands for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TapDivision {
+    Quarter,
+    Eighth,
+    DottedQuarter,
+}
+impl TapDivision {
+    pub const ALL: [TapDivision; 3] = [
+        TapDivision::Quarter,
+        TapDivision::Eighth,
+        TapDivision::DottedQuarter,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TapDivision::Quarter => "quarter",
+            TapDivision::Eighth => "eighth",
+            TapDivision::DottedQuarter => "dotted-quarter",
+        }
+    }
+
+    /// Length of the tapped note in quarter notes
+    pub fn quarters(self) -> f32 {
+        match self {
+            TapDivision::Quarter => 1.0,
+            TapDivision::Eighth => 0.5,
+            TapDivision::DottedQuarter => 1.5,
+        }
+    }
+}
+
+/// Rounding of the tapped tempo to a round value
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TapSnap {
+    Off,
+    Integer,
+    Five,
+}
+impl TapSnap {
+    pub const ALL: [TapSnap; 3] = [TapSnap::Off, TapSnap::Integer, TapSnap::Five];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TapSnap::Off => "off",
+            TapSnap::Integer => "nearest integer",
+            TapSnap::Five => "nearest 5",
+        }
+    }
+
+    pub fn apply(self, bpm: f32) -> f32 {
+        match self {
+            TapSnap::Off => bpm,
+            TapSnap::Integer => bpm.round(),
+            TapSnap::Five => (bpm / 5.0).round() * 5.0,
+        }
+    }
+}
+
+pub struct TapTempo {
+    pub division: TapDivision,
+    pub snap: TapSnap,
+    /// Intervals between the last few taps in seconds
+    taps: Vec<f32>,
+    /// When the previous tap happened, None before the first one
+    last: Option<Instant>,
+    /// Measured tempo waiting to be applied
+    staged: Option<f32>,
+}
+impl Default for TapTempo {
+    fn default() -> Self {
+        TapTempo::new()
+    }
+}
+
+impl TapTempo {
+    pub fn new() -> TapTempo {
+        TapTempo {
+            division: TapDivision::Quarter,
+            snap: TapSnap::Off,
+            taps: Vec::new(),
+            last: None,
+            staged: None,
+        }
+    }
+
+    pub fn tap(&mut self) -> Option<f32> {
+        self.tap_at(Instant::now())
+    }
+
+    /// Registers a tap that happened at `now`, returning the tempo measured so far.
+    /// The first tap only starts the measurement.
+    pub fn tap_at(&mut self, now: Instant) -> Option<f32> {
+        let last = self.last.replace(now)?;
+        let gap = now.saturating_duration_since(last);
+        let duration = gap.as_secs_f32();
+
+        if gap > RESET_AFTER
+            || self
+                .taps
+                .last()
+                .is_some_and(|&v| v < duration * 0.5 || v > duration * 2.0)
+        {
+            self.taps.clear();
+            None
+        } else {
+            self.taps.push(duration);
+            if self.taps.len() > MAX_TAPS - 1 {
+                self.taps.remove(0);
+            }
+
+            // Outlier detection above works on the raw intervals, the division only scales the result
+            let interval = fitted_interval(&self.taps);
+            let bpm = self.snap.apply(60.0 * self.division.quarters() / interval);
+            self.staged = Some(bpm);
+            Some(bpm)
+        }
+    }
+
+    /// The tempo of the last tap interval alone, for live feedback while tapping
+    pub fn latest(&self) -> Option<f32> {
+        self.taps
+            .last()
+            .map(|interval| 60.0 * self.division.quarters() / interval)
+    }
+
+    /// The tempo measured so far that hasn't been applied yet
+    pub fn staged(&self) -> Option<f32> {
+        self.staged
+    }
+
+    /// Takes the staged tempo to apply it right away
+    pub fn apply(&mut self) -> Option<f32> {
+        self.staged.take()
+    }
+
+    /// Takes the staged tempo once tapping has stopped for a moment
+    pub fn take_settled(&mut self) -> Option<f32> {
+        if self.last.is_some_and(|last| last.elapsed() >= SETTLE_AFTER) {
+            self.staged.take()
+        } else {
+            None
+        }
+    }
+}
+
+/// The tap period as the least-squares slope of the tap times over the tap index, so a
+/// single early or late tap shifts the estimate less than it would the plain intervals
+fn fitted_interval(intervals: &[f32]) -> f32 {
+    let times: Vec<f64> = std::iter::once(0.0)
+        .chain(intervals.iter().scan(0.0, |t, &interval| {
+            *t += interval as f64;
+            Some(*t)
+        }))
+        .collect();
+    let n = times.len() as f64;
+    let mean_index = (n - 1.0) / 2.0;
+    let mean_time = times.iter().sum::<f64>() / n;
+    let (covariance, variance) = times.iter().enumerate().fold((0.0, 0.0), |(c, v), (i, t)| {
+        let di = i as f64 - mean_index;
+        (c + di * (t - mean_time), v + di * di)
+    });
+    (covariance / variance) as f32
+}
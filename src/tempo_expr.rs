@@ -0,0 +1,110 @@
+/// Parses a typed tempo in quarter notes per minute. Accepts arithmetic like "120/2" or
+/// "(60+20)*1.5", and a note value the tempo is given for like "dotted-quarter = 80".
+pub fn parse_tempo(text: &str) -> anyhow::Result<f32> {
+    let (quarters, expr) = match text.split_once('=') {
+        Some((note, expr)) => (note_quarters(note.trim())?, expr),
+        None => (1.0, text),
+    };
+
+    let mut parser = Parser {
+        text: expr.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.sum()?;
+    parser.skip_spaces();
+    if parser.pos < parser.text.len() {
+        anyhow::bail!("Unexpected '{}'", &expr[parser.pos..].trim());
+    }
+
+    let bpm = value * quarters;
+    if !bpm.is_finite() || bpm <= 0.0 {
+        anyhow::bail!("{} is not a tempo", bpm);
+    }
+    Ok(bpm as f32)
+}
+
+/// Length of a note value like "quarter" or "dotted-eighth" in quarter notes
+fn note_quarters(name: &str) -> anyhow::Result<f64> {
+    let (dotted, base) = match name.strip_prefix("dotted") {
+        Some(base) => (true, base.trim_start_matches(['-', ' '])),
+        None => (false, name),
+    };
+    let quarters = match base {
+        "whole" => 4.0,
+        "half" => 2.0,
+        "quarter" => 1.0,
+        "eighth" => 0.5,
+        "sixteenth" => 0.25,
+        _ => anyhow::bail!("Unknown note value '{}'", name),
+    };
+    Ok(if dotted { quarters * 1.5 } else { quarters })
+}
+
+/// Recursive descent over `+ - * /`, parentheses and decimal numbers
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_spaces(&mut self) {
+        while self.text.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// The next non-space character if it is one of `ops`, consuming it
+    fn take_op(&mut self, ops: &[u8]) -> Option<u8> {
+        self.skip_spaces();
+        let op = *self.text.get(self.pos).filter(|c| ops.contains(c))?;
+        self.pos += 1;
+        Some(op)
+    }
+
+    fn sum(&mut self) -> anyhow::Result<f64> {
+        let mut value = self.product()?;
+        while let Some(op) = self.take_op(b"+-") {
+            let rhs = self.product()?;
+            value = if op == b'+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> anyhow::Result<f64> {
+        let mut value = self.atom()?;
+        while let Some(op) = self.take_op(b"*/") {
+            let rhs = self.atom()?;
+            value = if op == b'*' { value * rhs } else { value / rhs };
+        }
+        Ok(value)
+    }
+
+    fn atom(&mut self) -> anyhow::Result<f64> {
+        if self.take_op(b"(").is_some() {
+            let value = self.sum()?;
+            if self.take_op(b")").is_none() {
+                anyhow::bail!("Missing ')'");
+            }
+            return Ok(value);
+        }
+        if self.take_op(b"-").is_some() {
+            return Ok(-self.atom()?);
+        }
+
+        let start = self.pos;
+        while self
+            .text
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_digit() || *c == b'.')
+        {
+            self.pos += 1;
+        }
+        let number = std::str::from_utf8(&self.text[start..self.pos])?;
+        if number.is_empty() {
+            anyhow::bail!("Expected a number");
+        }
+        number
+            .parse()
+            .map_err(|_| anyhow::anyhow!("'{}' is not a number", number))
+    }
+}
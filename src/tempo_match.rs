@@ -0,0 +1,74 @@
+use metronome::tap_tempo::TapTempo;
+
+/// Deviation shown at the ends of the needle's scale, in percent
+const SCALE_PERCENT: f32 = 10.0;
+/// Deviation still counted as in time, in percent
+const TOLERANCE_PERCENT: f32 = 1.0;
+
+/// A tuner for tempo: compares tapped beats against a target tempo
+pub struct TempoMatch {
+    pub target_bpm: f32,
+    pub taps: TapTempo,
+}
+
+impl TempoMatch {
+    pub fn new(target_bpm: f32) -> TempoMatch {
+        TempoMatch {
+            target_bpm,
+            taps: TapTempo::new(),
+        }
+    }
+
+    /// How much faster than the target the last tap interval was, in percent
+    pub fn deviation_percent(&self) -> Option<f32> {
+        self.taps
+            .latest()
+            .map(|bpm| (bpm / self.target_bpm - 1.0) * 100.0)
+    }
+
+    pub fn feedback(&self) -> &'static str {
+        match self.deviation_percent() {
+            None => "tap along",
+            Some(d) if d > TOLERANCE_PERCENT => "rushing",
+            Some(d) if d < -TOLERANCE_PERCENT => "dragging",
+            Some(_) => "in time",
+        }
+    }
+
+    /// Draws a needle that leans right when rushing and left when dragging
+    pub fn needle(&self, ui: &mut egui::Ui, width: f32) {
+        let (rect, _) =
+            ui.allocate_exact_size(egui::vec2(width, width * 0.3), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        let visuals = ui.visuals();
+
+        let pivot = rect.center_bottom();
+        let length = rect.height() * 0.95;
+        let max_angle = 1.0f32;
+        let direction = |angle: f32| egui::vec2(angle.sin(), -angle.cos());
+
+        // The in-time zone around the center
+        let tolerance_angle = max_angle * TOLERANCE_PERCENT / SCALE_PERCENT;
+        for angle in [-tolerance_angle, tolerance_angle] {
+            painter.line_segment(
+                [
+                    pivot + direction(angle) * length * 0.8,
+                    pivot + direction(angle) * length,
+                ],
+                egui::Stroke::new(1.0, visuals.weak_text_color()),
+            );
+        }
+
+        let deviation = self.deviation_percent();
+        let color = match deviation {
+            Some(d) if d.abs() <= TOLERANCE_PERCENT => egui::Color32::GREEN,
+            Some(_) => egui::Color32::YELLOW,
+            None => visuals.weak_text_color(),
+        };
+        let angle = deviation.map_or(0.0, |d| (d / SCALE_PERCENT).clamp(-1.0, 1.0) * max_angle);
+        painter.line_segment(
+            [pivot, pivot + direction(angle) * length],
+            egui::Stroke::new(3.0, color),
+        );
+    }
+}
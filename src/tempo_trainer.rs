@@ -0,0 +1,49 @@
+/// Speeds the tempo up by a step every few bars, up to a ceiling
+pub struct TempoTrainer {
+    pub enabled: bool,
+    /// Tempo every run starts at
+    pub base_bpm: f32,
+    pub step_bpm: f32,
+    pub bars_per_step: usize,
+    pub max_bpm: f32,
+    /// Bar of the pattern the last step was asked for in, until the new tempo takes over
+    stepped_in: Option<f64>,
+}
+
+impl TempoTrainer {
+    pub fn new() -> TempoTrainer {
+        TempoTrainer {
+            enabled: false,
+            base_bpm: 80.0,
+            step_bpm: 5.0,
+            bars_per_step: 4,
+            max_bpm: 160.0,
+            stepped_in: None,
+        }
+    }
+
+    /// Starts a run over, returning the tempo it starts at
+    pub fn restart(&mut self) -> f32 {
+        self.stepped_in = None;
+        self.base_bpm
+    }
+
+    /// Follows the pattern, `bars` counts the bars since it started at the current tempo.
+    /// Returns the next tempo once the last bar before a step has begun, so the change can
+    /// wait for the bar line.
+    pub fn update(&mut self, bars: Option<f64>, bpm: f32) -> Option<f32> {
+        let bars = bars.filter(|_| self.enabled)?;
+        if let Some(stepped_in) = self.stepped_in {
+            // The pattern starts over at the new tempo, counting from there
+            if bars >= stepped_in {
+                return None;
+            }
+            self.stepped_in = None;
+        }
+        if bpm >= self.max_bpm || bars < self.bars_per_step.max(1) as f64 - 1.0 {
+            return None;
+        }
+        self.stepped_in = Some(bars);
+        Some((bpm + self.step_bpm).min(self.max_bpm))
+    }
+}
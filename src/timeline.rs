@@ -0,0 +1,32 @@
+use std::ops::Range;
+
+use metronome::player::Onset;
+
+/// Draws the onsets within `window` on a strip, the current time at the left edge,
+/// so upcoming clicks scroll in from the right. Louder clicks get bigger dots.
+pub fn timeline(ui: &mut egui::Ui, onsets: &[Onset], window: Range<usize>, height: f32) {
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(ui.available_width(), height),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    painter.line_segment(
+        [rect.left_center(), rect.right_center()],
+        egui::Stroke::new(1.0, ui.visuals().weak_text_color()),
+    );
+
+    let loudest = onsets.iter().fold(0.0f32, |peak, o| peak.max(o.level));
+    if window.is_empty() || loudest <= 0.0 {
+        return;
+    }
+    let color = ui.visuals().strong_text_color();
+    for onset in onsets {
+        let fraction = (onset.time - window.start) as f32 / window.len() as f32;
+        let radius = height * 0.4 * (onset.level / loudest).sqrt();
+        painter.circle_filled(
+            egui::pos2(rect.left() + rect.width() * fraction, rect.center().y),
+            radius,
+            color,
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use std::{io::Write, path::Path};
+
+/// Encodes interleaved samples as a 16-bit PCM WAV file, clamping them to [-1, 1]
+pub fn encode_wav(samples: &[f32], sample_rate: usize, channels: usize) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let block_align = channels as u16 * 2;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&(channels as u16).to_le_bytes());
+    wav.extend_from_slice(&(sample_rate as u32).to_le_bytes());
+    wav.extend_from_slice(&(sample_rate as u32 * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for s in samples {
+        let value = (s.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+    wav
+}
+
+/// Writes interleaved samples to `path` as a 16-bit PCM WAV file
+pub fn write_wav(
+    path: &Path,
+    samples: &[f32],
+    sample_rate: usize,
+    channels: usize,
+) -> std::io::Result<()> {
+    std::fs::File::create(path)?.write_all(&encode_wav(samples, sample_rate, channels))
+}
@@ -0,0 +1,36 @@
+use egui::plot::{Line, Plot, PlotPoints};
+
+/// Points drawn at most, longer buffers are reduced to the extremes of each stretch
+const MAX_POINTS: usize = 600;
+
+/// Plots a click buffer against time in milliseconds
+pub fn waveform(ui: &mut egui::Ui, samples: &[f32], sample_rate: usize, height: f32) {
+    let ms = |i: usize| i as f64 * 1000.0 / sample_rate as f64;
+    let points: PlotPoints = if samples.len() <= MAX_POINTS {
+        (samples.iter().enumerate())
+            .map(|(i, &s)| [ms(i), s as f64])
+            .collect()
+    } else {
+        // A minimum and a maximum per stretch keeps the envelope visible
+        let stretch = samples.len().div_ceil(MAX_POINTS / 2);
+        (samples.chunks(stretch).enumerate())
+            .flat_map(|(i, chunk)| {
+                let min = chunk.iter().copied().fold(f32::INFINITY, f32::min);
+                let max = chunk.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let t = ms(i * stretch);
+                [[t, min as f64], [t, max as f64]]
+            })
+            .collect()
+    };
+
+    Plot::new("click waveform")
+        .height(height)
+        .include_y(-1.0)
+        .include_y(1.0)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .allow_boxed_zoom(false)
+        .x_axis_formatter(|x, _| format!("{} ms", x))
+        .show(ui, |plot| plot.line(Line::new(points)));
+}
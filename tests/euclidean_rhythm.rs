@@ -0,0 +1,50 @@
+//! Compares the Euclidean rhythms against the ones listed by Toussaint for Bjorklund's algorithm.
+
+use metronome::pattern::euclidean_rhythm;
+
+fn rhythm(hits: usize, steps: usize) -> String {
+    euclidean_rhythm(hits, steps)
+        .iter()
+        .map(|&hit| if hit { 'x' } else { '.' })
+        .collect()
+}
+
+#[test]
+fn hits_match_the_known_rhythms() {
+    let known = [
+        (2, 5, "x.x.."),
+        (3, 4, "x.xx"),
+        (3, 7, "x.x.x.."),
+        (3, 8, "x..x..x."),
+        (4, 7, "x.x.x.x"),
+        (4, 12, "x..x..x..x.."),
+        (5, 8, "x.xx.xx."),
+        (5, 9, "x.x.x.x.x"),
+        (5, 12, "x..x.x..x.x."),
+        (5, 13, "x..x.x..x.x.."),
+        (7, 12, "x.xx.x.xx.x."),
+        (7, 16, "x..x.x.x..x.x.x."),
+        (9, 16, "x.xx.x.x.xx.x.x."),
+        (13, 24, "x.xx.x.x.x.x.xx.x.x.x.x."),
+    ];
+    for (hits, steps, expected) in known {
+        assert_eq!(rhythm(hits, steps), expected, "E({},{})", hits, steps);
+    }
+}
+
+#[test]
+fn the_downbeat_always_sounds() {
+    assert_eq!(rhythm(0, 4), "x...");
+    assert_eq!(rhythm(1, 1), "x");
+}
+
+#[test]
+fn as_many_hits_as_steps_or_more_fill_the_bar() {
+    assert_eq!(rhythm(4, 4), "xxxx");
+    assert_eq!(rhythm(6, 4), "xxxx");
+}
+
+#[test]
+fn no_steps_give_an_empty_rhythm() {
+    assert_eq!(rhythm(3, 0), "");
+}
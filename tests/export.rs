@@ -0,0 +1,138 @@
+//! Exports click tracks with their volume, bar gate and layers.
+
+use std::sync::Arc;
+
+use metronome::audio_sink::NullSink;
+use metronome::pattern::Schedule;
+use metronome::player::{export_click_track, BarGate, ClickTrack, Playback, Player, TrackLayer};
+
+fn mix() -> Player<NullSink> {
+    let player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    // Linear, so levels can be compared exactly
+    player.bypass_limiter(true);
+    player
+}
+
+/// A single sample click at the start of every `period` samples
+fn clicks(level: f32, period: usize) -> Vec<Playback> {
+    vec![Playback::new(Arc::new(vec![level])).repeat(period, None)]
+}
+
+/// A track of one bar of `bar` samples clicking on its downbeat
+fn track(bar: usize, bars: usize) -> ClickTrack {
+    ClickTrack {
+        playbacks: clicks(0.5, bar),
+        bar_lengths: vec![bar as f64],
+        bars,
+        volume_db: 0.0,
+        bar_gate: None,
+        main_solo: false,
+        layers: Vec::new(),
+    }
+}
+
+fn onsets(output: &[f32]) -> Vec<usize> {
+    (0..output.len()).filter(|&t| output[t] != 0.0).collect()
+}
+
+#[test]
+fn renders_the_bars_of_the_pattern() {
+    let output = export_click_track(&mut mix(), track(100, 4));
+    assert_eq!(output.len(), 400);
+    assert_eq!(onsets(&output), [0, 100, 200, 300]);
+    assert!(output.iter().all(|&s| s == 0.0 || s == 0.5));
+}
+
+#[test]
+fn plays_at_the_volume() {
+    let mut track = track(100, 2);
+    track.volume_db = -6.0;
+    let output = export_click_track(&mut mix(), track);
+    let expected = 0.5 * 10f32.powf(-6.0 / 20.0);
+    assert_eq!(onsets(&output), [0, 100]);
+    assert!((output[0] - expected).abs() < 1e-4);
+    assert!((output[100] - expected).abs() < 1e-4);
+}
+
+#[test]
+fn gates_the_bars() {
+    let mut track = track(100, 6);
+    track.bar_gate = Some(BarGate {
+        play_bars: 2,
+        mute_bars: 1,
+    });
+    let output = export_click_track(&mut mix(), track);
+    assert_eq!(onsets(&output), [0, 100, 300, 400]);
+}
+
+#[test]
+fn counts_the_bars_of_alternating_patterns() {
+    let bar = |len: usize| Schedule {
+        playbacks: clicks(0.5, len),
+        bar_duration: len as f64,
+        bar_lengths: vec![len as f64],
+    };
+    let schedule = Schedule::alternate(&bar(100), &bar(60), 1);
+    let mut track = track(100, 3);
+    track.playbacks = schedule.playbacks;
+    track.bar_lengths = schedule.bar_lengths;
+    track.bar_gate = Some(BarGate {
+        play_bars: 1,
+        mute_bars: 1,
+    });
+    let output = export_click_track(&mut mix(), track);
+
+    // Three real bars, not three A/B loops, with the gate muting the B bar between the A ones
+    assert_eq!(output.len(), 260);
+    assert_eq!(onsets(&output), [0, 160]);
+}
+
+#[test]
+fn mixes_in_the_layers() {
+    let mut track = track(100, 2);
+    track.layers = vec![
+        TrackLayer {
+            id: 1,
+            playbacks: vec![Playback::new(Arc::new(vec![0.2])).offset(50)],
+            volume_db: 0.0,
+            muted: false,
+            solo: false,
+        },
+        TrackLayer {
+            id: 2,
+            playbacks: vec![Playback::new(Arc::new(vec![0.2])).offset(70)],
+            volume_db: 0.0,
+            muted: true,
+            solo: false,
+        },
+        TrackLayer {
+            id: 3,
+            playbacks: vec![Playback::new(Arc::new(vec![0.2])).offset(120)],
+            volume_db: -6.0,
+            muted: false,
+            solo: false,
+        },
+    ];
+    let output = export_click_track(&mut mix(), track);
+    assert_eq!(onsets(&output), [0, 50, 100, 120]);
+    assert!((output[50] - 0.2).abs() < 1e-4);
+    assert!((output[120] - 0.2 * 10f32.powf(-6.0 / 20.0)).abs() < 1e-4);
+}
+
+#[test]
+fn soloing_a_layer_silences_the_main_pattern_unless_it_is_soloed_too() {
+    let soloed = |main_solo: bool| {
+        let mut track = track(100, 2);
+        track.main_solo = main_solo;
+        track.layers = vec![TrackLayer {
+            id: 1,
+            playbacks: vec![Playback::new(Arc::new(vec![0.2])).offset(50)],
+            volume_db: 0.0,
+            muted: false,
+            solo: true,
+        }];
+        onsets(&export_click_track(&mut mix(), track))
+    };
+    assert_eq!(soloed(false), [50]);
+    assert_eq!(soloed(true), [0, 50, 100]);
+}
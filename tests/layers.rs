@@ -0,0 +1,46 @@
+//! Mixes several layers with their own gain, mute and solo state.
+
+use std::sync::Arc;
+
+use metronome::audio_sink::NullSink;
+use metronome::player::{Playback, Player, MAIN_LAYER};
+
+/// A player with a constant on the main layer and on layers 1 and 2, each with its own level
+fn layered_player() -> Player<NullSink> {
+    let player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    // Linear, so the output is exactly the sum of the layers
+    player.bypass_limiter(true);
+    let constant = |level: f32| Playback::new(Arc::new(vec![level; 100])).repeat(100, None);
+    player.add_playbacks(vec![constant(0.01)]);
+    player.replace_layer(1, vec![constant(0.02)]);
+    player.replace_layer(2, vec![constant(0.04)]);
+    player
+}
+
+fn level(player: &mut Player<NullSink>) -> f32 {
+    // Long enough for the commands to be handled before the last sample
+    *player.sink_mut().render(500).last().unwrap()
+}
+
+#[test]
+fn layers_sum_unless_muted() {
+    let mut player = layered_player();
+    assert!((level(&mut player) - 0.07).abs() < 1e-4);
+    player.set_layer_muted(1, true);
+    assert!((level(&mut player) - 0.05).abs() < 1e-4);
+}
+
+#[test]
+fn soloing_silences_the_other_layers() {
+    let mut player = layered_player();
+    player.set_layer_solo(2, true);
+    assert!((level(&mut player) - 0.04).abs() < 1e-4);
+
+    // The main layer can be soloed along with it, the rest stays silent
+    player.set_layer_solo(MAIN_LAYER, true);
+    assert!((level(&mut player) - 0.05).abs() < 1e-4);
+
+    player.set_layer_solo(2, false);
+    player.set_layer_solo(MAIN_LAYER, false);
+    assert!((level(&mut player) - 0.07).abs() < 1e-4);
+}
@@ -0,0 +1,171 @@
+//! Parses small standard MIDI files built in memory, including broken ones.
+
+use metronome::midi_file::{Section, TempoMap};
+
+const TICKS_PER_QUARTER: u16 = 96;
+
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend((data.len() as u32).to_be_bytes());
+    chunk.extend(data);
+    chunk
+}
+
+/// A format 1 file of the given track data, each track ending with end of track
+fn smf(tracks: &[Vec<u8>]) -> Vec<u8> {
+    let mut header = vec![0, 1];
+    header.extend((tracks.len() as u16).to_be_bytes());
+    header.extend(TICKS_PER_QUARTER.to_be_bytes());
+    let mut file = chunk(b"MThd", &header);
+    for track in tracks {
+        let mut data = track.clone();
+        data.extend([0x00, 0xFF, 0x2F, 0x00]);
+        file.extend(chunk(b"MTrk", &data));
+    }
+    file
+}
+
+/// `delta` is the time since the previous event as a variable length quantity
+fn tempo(delta: &[u8], bpm: u32) -> Vec<u8> {
+    let micros = (60_000_000 / bpm).to_be_bytes();
+    [delta, &[0xFF, 0x51, 0x03, micros[1], micros[2], micros[3]]].concat()
+}
+
+fn time_signature(delta: &[u8], numerator: u8, denominator_pow: u8) -> Vec<u8> {
+    [
+        delta,
+        &[0xFF, 0x58, 0x04, numerator, denominator_pow, 24, 8],
+    ]
+    .concat()
+}
+
+fn section(start: f64, bpm: f32, numerator: usize, denominator: usize) -> Section {
+    Section {
+        start,
+        bpm,
+        numerator,
+        denominator,
+    }
+}
+
+#[test]
+fn follows_tempo_and_meter_changes() {
+    let conductor = [
+        tempo(&[0], 100),
+        time_signature(&[0], 3, 2),
+        // Three quarters later, at 100 BPM that is 1.8 s
+        tempo(&[0x82, 0x20], 150),
+        // And one more at 150 BPM, 0.4 s
+        time_signature(&[0x60], 7, 3),
+    ]
+    .concat();
+    // Notes in running status, with a two byte delta time, don't disturb the meta events
+    let notes = vec![
+        0x00, 0x90, 60, 100, 0x81, 0x40, 60, 0, 0x00, 0xC0, 5, 0x00, 0xF0, 0x01, 0xF7,
+    ];
+    let map = TempoMap::parse(&smf(&[conductor, notes])).unwrap();
+
+    assert_eq!(
+        map.sections,
+        vec![
+            section(0.0, 100.0, 3, 4),
+            section(1.8, 150.0, 3, 4),
+            section(2.2, 150.0, 7, 8),
+        ]
+    );
+    assert_eq!(map.section_at(2.0).bpm, 150.0);
+}
+
+#[test]
+fn a_file_without_tempo_events_plays_at_120() {
+    let map = TempoMap::parse(&smf(&[vec![0x00, 0x90, 60, 100]])).unwrap();
+    assert_eq!(map.sections, vec![section(0.0, 120.0, 4, 4)]);
+}
+
+#[test]
+fn meters_the_metronome_cannot_play_are_mapped_to_ones_it_can() {
+    let meter = |numerator, denominator_pow| {
+        let map = TempoMap::parse(&smf(&[time_signature(&[0], numerator, denominator_pow)]));
+        let section = map.unwrap().sections[0];
+        (section.numerator, section.denominator)
+    };
+    // Half and whole notes become quarters, keeping the bar
+    assert_eq!(meter(2, 1), (4, 4));
+    assert_eq!(meter(1, 0), (4, 4));
+    assert_eq!(meter(0, 2), (1, 4));
+    assert_eq!(meter(6, 3), (6, 8));
+    assert_eq!(meter(40, 7), (32, 32));
+}
+
+#[test]
+fn every_truncated_file_is_an_error() {
+    let file = smf(&[
+        [tempo(&[0], 90), time_signature(&[0], 5, 2)].concat(),
+        vec![0x00, 0x90, 60, 100],
+    ]);
+    assert!(TempoMap::parse(&file).is_ok());
+    for len in 0..file.len() {
+        assert!(TempoMap::parse(&file[..len]).is_err(), "{} bytes", len);
+    }
+}
+
+#[test]
+fn malformed_files_are_errors() {
+    let valid = smf(&[tempo(&[0], 90)]);
+
+    let mut not_midi = valid.clone();
+    not_midi[0] = b'R';
+    let mut smpte = valid.clone();
+    smpte[12] = 0xE7;
+    let mut no_ticks = valid.clone();
+    no_ticks[12..14].copy_from_slice(&[0, 0]);
+    let malformed = [
+        not_midi,
+        smpte,
+        no_ticks,
+        // A delta time longer than four bytes
+        smf(&[vec![0xFF, 0xFF, 0xFF, 0xFF, 0x7F]]),
+        // A data byte before any status it could be running from
+        smf(&[vec![0x00, 0x40, 0x40]]),
+        // A meta event longer than its track
+        smf(&[vec![0x00, 0xFF, 0x51, 0x7F, 0x07]]),
+        // A system exclusive message running past the track
+        smf(&[vec![0x00, 0xF0, 0x81, 0x00]]),
+        // More tracks announced than there are
+        {
+            let mut file = valid.clone();
+            file[11] = 2;
+            file
+        },
+        // A track chunk claiming to be 4 GiB
+        {
+            let mut file = valid.clone();
+            file[18..22].copy_from_slice(&[0xFF; 4]);
+            file
+        },
+    ];
+    for (i, file) in malformed.iter().enumerate() {
+        assert!(TempoMap::parse(file).is_err(), "file {}", i);
+    }
+}
+
+#[test]
+fn random_bytes_never_panic() {
+    let header = &smf(&[])[..14];
+    // xorshift32, so the bytes are the same on every run
+    let mut state = 0x2545F491u32;
+    for _ in 0..2000 {
+        let len = (state % 64) as usize;
+        let mut track = Vec::with_capacity(len);
+        for _ in 0..len {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            track.push(state as u8);
+        }
+        let mut file = header.to_vec();
+        file[11] = 1;
+        file.extend(chunk(b"MTrk", &track));
+        let _ = TempoMap::parse(&file);
+    }
+}
@@ -0,0 +1,33 @@
+//! Taps the mixed output through a monitor ring while rendering without a device.
+
+use std::sync::Arc;
+
+use metronome::audio_sink::NullSink;
+use metronome::monitor::MonitorRing;
+use metronome::player::{Playback, Player};
+
+#[test]
+fn monitor_receives_the_mix_and_drops_what_does_not_fit() {
+    let mut player = Player::with_sink(NullSink::new(48000, 2)).unwrap();
+    let mut monitor = player.set_monitor(MonitorRing::new(1000));
+    player.add_playbacks(vec![
+        Playback::new(Arc::new(vec![0.5; 100])).repeat(200, None)
+    ]);
+
+    let output = player.sink_mut().render(600);
+    let mut tapped = Vec::new();
+    monitor.read_into(&mut tapped);
+    assert_eq!(tapped.len(), 600);
+    // Both channels carry the unpanned mix, so the mono tap matches either one
+    for (i, s) in tapped.iter().enumerate() {
+        assert_eq!(*s, output[2 * i]);
+    }
+
+    // Not reading for a while fills the ring, the audio keeps going regardless
+    player.sink_mut().render(800);
+    player.sink_mut().render(800);
+    tapped.clear();
+    monitor.read_into(&mut tapped);
+    assert_eq!(tapped.len(), 1000);
+    assert_eq!(monitor.dropped(), 600);
+}
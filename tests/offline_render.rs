@@ -0,0 +1,64 @@
+//! Renders the same click as `examples/render_to_wav.rs` and compares it to a golden hash,
+//! so changes to the click synthesis, scheduling or mixing don't go unnoticed. Also checks
+//! that rendered clicks start exactly where they were scheduled.
+
+use std::sync::Arc;
+
+use metronome::click::{generate_click, ClickShape};
+use metronome::player::{render_offline, Playback};
+use metronome::wav::encode_wav;
+
+/// FNV-1a of the WAV file of 8 bars of 4/4 at 120 BPM
+const GOLDEN_HASH: u64 = 0xa99db6063560d34f;
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[test]
+fn click_at_120_bpm_matches_golden_wav() {
+    let sample_rate = 44100;
+    let bars = 8;
+    let beat = sample_rate as f64 * 60.0 / 120.0;
+    let bar = beat * 4.0;
+
+    let playbacks = (0..4)
+        .map(|i| {
+            let freq = if i == 0 { 880.0 } else { 440.0 };
+            let click = generate_click(sample_rate, freq, 1.0, ClickShape::default());
+            Playback::new(Arc::new(click))
+                .offset((i as f64 * beat).round() as usize)
+                .repeat_exact(bar, Some(bars - 1))
+        })
+        .collect();
+    let output = render_offline(playbacks, sample_rate, 1, (bar * bars as f64) as usize);
+    let wav = encode_wav(&output, sample_rate, 1);
+
+    assert_eq!(wav.len(), 44 + output.len() * 2);
+    assert_eq!(
+        fnv1a(&wav),
+        GOLDEN_HASH,
+        "rendered WAV differs from the golden one"
+    );
+}
+
+#[test]
+fn clicks_start_on_the_beats() {
+    let sample_rate = 8000;
+    let beat = sample_rate as f64 * 60.0 / 135.0;
+    let bars = 2;
+    let click = Arc::new(vec![1.0; 10]);
+    let playbacks = vec![Playback::new(click).repeat_exact(beat, Some(4 * bars - 1))];
+    let frames = (beat * 4.0 * bars as f64).round() as usize;
+    let output = render_offline(playbacks, sample_rate, 1, frames);
+
+    let onsets: Vec<usize> = (0..output.len())
+        .filter(|&i| output[i] != 0.0 && (i == 0 || output[i - 1] == 0.0))
+        .collect();
+    let expected: Vec<usize> = (0..4 * bars)
+        .map(|i| (i as f64 * beat).round() as usize)
+        .collect();
+    assert_eq!(onsets, expected);
+}
@@ -0,0 +1,19 @@
+//! Checks the output device is opened in its own sample format. Passes trivially on
+//! machines without an audio device.
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use metronome::audio_sink::CpalSink;
+
+#[test]
+fn uses_the_default_sample_format() {
+    let Some(device) = cpal::default_host().default_output_device() else {
+        return;
+    };
+    let (Ok(default), Ok(sink)) = (
+        device.default_output_config(),
+        CpalSink::open(None, None, None),
+    ) else {
+        return;
+    };
+    assert_eq!(sink.sample_format(), default.sample_format());
+}
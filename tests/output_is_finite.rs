@@ -0,0 +1,136 @@
+//! Renders edge-case configurations through the mixer and checks that nothing but
+//! finite samples within [-1, 1] ever reaches the output device.
+
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+use metronome::click::stereo_snare;
+use metronome::player::{render_offline, Playback};
+
+/// A decaying sine click like the app's, scaled by `gain`
+fn click(sample_rate: usize, len: usize, freq: f32, gain: f32) -> Arc<Vec<f32>> {
+    Arc::new(
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                gain * (TAU * freq * t).sin() * (-t * 30.0).exp()
+            })
+            .collect(),
+    )
+}
+
+/// Samples per beat at `bpm`, at least one
+fn beat(sample_rate: usize, bpm: f64) -> usize {
+    ((60.0 / bpm * sample_rate as f64) as usize).max(1)
+}
+
+fn assert_safe(name: &str, output: &[f32]) {
+    for (i, sample) in output.iter().enumerate() {
+        assert!(
+            sample.is_finite() && (-1.0..=1.0).contains(sample),
+            "{}: sample {} is {}",
+            name,
+            i,
+            sample
+        );
+    }
+}
+
+fn render_all(name: &str, playbacks: Vec<Playback>, frames: usize) {
+    for sample_rate in [8000, 44100, 192000] {
+        for channels in [1, 2, 6] {
+            let output = render_offline(playbacks.clone(), sample_rate, channels, frames);
+            assert_eq!(output.len(), frames * channels);
+            assert_safe(
+                &format!("{} ({} Hz, {} channels)", name, sample_rate, channels),
+                &output,
+            );
+        }
+    }
+}
+
+#[test]
+fn nothing_to_play() {
+    // A bar with a zero numerator schedules no clicks at all
+    render_all("no playbacks", Vec::new(), 4800);
+    render_all(
+        "no frames",
+        vec![Playback::new(click(48000, 100, 1000.0, 1.0))],
+        0,
+    );
+}
+
+#[test]
+fn tiny_durations() {
+    for len in [0, 1, 2, 3] {
+        let playbacks = vec![Playback::new(click(48000, len, 1000.0, 1.0)).repeat(7, None)];
+        render_all(&format!("{} sample click", len), playbacks.clone(), 1);
+        render_all(&format!("{} sample click", len), playbacks, 4800);
+    }
+}
+
+#[test]
+fn extreme_tempos() {
+    for bpm in [1.0, 20.0, 120.0, 1000.0, 10000.0, 1e9] {
+        let period = beat(48000, bpm);
+        let playbacks = vec![
+            Playback::new(click(48000, 2400, 1500.0, 1.0)).repeat(period, None),
+            Playback::new(click(48000, 2400, 1000.0, 1.0))
+                .offset(period / 2)
+                .repeat(period, Some(3)),
+        ];
+        render_all(&format!("{} bpm", bpm), playbacks, 9600);
+    }
+}
+
+#[test]
+fn huge_gains() {
+    for gain in [10.0, 1e6, 1e30, f32::MAX] {
+        let playbacks = vec![
+            Playback::new(click(48000, 1200, 1000.0, gain)).repeat(100, None),
+            Playback::new(click(48000, 1200, 1500.0, -gain))
+                .offset(50)
+                .repeat(100, None)
+                .pan(-1.0),
+        ];
+        render_all(&format!("gain {}", gain), playbacks, 4800);
+    }
+}
+
+#[test]
+fn sustained_and_panned() {
+    let tone = Arc::new(
+        (0..48000)
+            .map(|i| (TAU * 440.0 * i as f32 / 48000.0).sin() * 4.0)
+            .collect::<Vec<f32>>(),
+    );
+    let playbacks = vec![
+        Playback::new(tone.clone()).loop_sustain(),
+        Playback::new(tone)
+            .loop_sustain()
+            .repeat(1000, Some(2))
+            .pan(1.0),
+        Playback::new(click(48000, 2400, 1000.0, 1.0))
+            .repeat(beat(48000, 240.0), None)
+            .pan(0.3),
+    ];
+    render_all("sustained and panned", playbacks, 9600);
+}
+
+#[test]
+fn stereo_samples() {
+    let snare = Arc::new(stereo_snare(48000, 4.0));
+    let playbacks = vec![
+        Playback::new(snare.clone())
+            .stereo()
+            .repeat(beat(48000, 240.0), None),
+        Playback::new(snare.clone())
+            .stereo()
+            .offset(100)
+            .repeat(beat(48000, 240.0), Some(2))
+            .pan(-0.5),
+        Playback::new(snare).stereo().loop_sustain().pan(1.0),
+        Playback::new(click(48000, 2400, 1000.0, 1.0)).repeat(beat(48000, 120.0), None),
+    ];
+    render_all("stereo samples", playbacks, 9600);
+}
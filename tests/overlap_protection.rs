@@ -0,0 +1,58 @@
+//! Shares the headroom between clicks starting together.
+
+use std::sync::Arc;
+
+use metronome::audio_sink::NullSink;
+use metronome::player::{Playback, Player};
+
+fn protected_player() -> Player<NullSink> {
+    let player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    // Linear, so the output is exactly the applied gain times the signal
+    player.bypass_limiter(true);
+    player.set_overlap_protection(true);
+    player
+}
+
+/// Renders in blocks smaller than the clicks, so they sound across several of them
+fn render(player: &mut Player<NullSink>, frames: usize) -> Vec<f32> {
+    let mut output = Vec::new();
+    while output.len() < frames {
+        output.extend(player.sink_mut().render(64));
+    }
+    output.truncate(frames);
+    output
+}
+
+#[test]
+fn clicks_starting_together_split_the_power() {
+    let mut player = protected_player();
+    let click = || Playback::new(Arc::new(vec![0.4; 100]));
+    player.add_playbacks(vec![click(), click()]);
+    let output = render(&mut player, 200);
+
+    let expected = 2.0 * 0.4 / 2f32.sqrt();
+    assert!(output[..100].iter().all(|s| (s - expected).abs() < 1e-5));
+    assert!(output[100..].iter().all(|&s| s == 0.0));
+}
+
+#[test]
+fn a_sounding_click_keeps_its_gain_when_a_later_one_starts() {
+    let mut player = protected_player();
+    // A long click starting with the first of a short one repeating every 500 samples,
+    // further apart than the 5 ms window
+    let long = Playback::new(Arc::new(vec![0.4; 1000]));
+    let short = Playback::new(Arc::new(vec![0.2; 10])).repeat(500, None);
+    player.add_playbacks(vec![long, short]);
+    let output = render(&mut player, 1000);
+
+    let held = 0.4 / 2f32.sqrt();
+    for (time, sample) in output.iter().enumerate() {
+        let expected = match time {
+            0..=9 => held + 0.2 / 2f32.sqrt(),
+            // The later short click starts alone and plays at full gain
+            500..=509 => held + 0.2,
+            _ => held,
+        };
+        assert!((sample - expected).abs() < 1e-5, "{} at {}", sample, time);
+    }
+}
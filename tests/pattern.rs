@@ -0,0 +1,110 @@
+//! Schedules patterns and checks where their clicks land over the bar.
+
+use metronome::click::{tags, ClickShape, Sounds, Timbre};
+use metronome::pattern::{Pattern, Schedule, PRE_BEAT_GAIN};
+use metronome::player::Playback;
+
+const SAMPLE_RATE: usize = 48000;
+
+fn schedule(pattern: &Pattern, bpm: f32) -> Schedule {
+    let mut sounds = Sounds::new(SAMPLE_RATE, ClickShape::default(), [Timbre::Tone; 3]);
+    pattern.schedule(bpm, SAMPLE_RATE, &mut sounds)
+}
+
+fn tagged(schedule: &Schedule, tag: u64) -> Vec<&Playback> {
+    schedule
+        .playbacks
+        .iter()
+        .filter(|p| p.tag == Some(tag))
+        .collect()
+}
+
+#[test]
+fn the_pre_beat_leads_the_downbeat_by_one_subdivision() {
+    // 120 BPM in 4/4 is 24000 samples per beat
+    let mut pattern = Pattern {
+        pre_beat: true,
+        click_note: 16,
+        ..Pattern::default()
+    };
+    let sixteenths = schedule(&pattern, 120.0);
+    let pre_beat = tagged(&sixteenths, tags::PRE_BEAT);
+    assert_eq!(pre_beat.len(), 1);
+    assert_eq!(pre_beat[0].start, 4 * 24000 - 6000);
+    assert_eq!(pre_beat[0].gain, PRE_BEAT_GAIN);
+    assert_eq!(pre_beat[0].repetition_period, 4 * 24000);
+
+    // Without subdivisions it doesn't land on the last beat
+    pattern.click_note = 4;
+    let beats = schedule(&pattern, 120.0);
+    assert_eq!(tagged(&beats, tags::PRE_BEAT)[0].start, 4 * 24000 - 12000);
+}
+
+#[test]
+fn melodic_beats_without_a_degree_play_the_root() {
+    let mut pattern = Pattern {
+        melodic: true,
+        beat_degrees: vec![3, 0],
+        ..Pattern::default()
+    };
+    pattern.numerator = 7;
+    let bar = schedule(&pattern, 120.0);
+    assert_eq!(bar.playbacks.len(), 7);
+    // The same cached sample as the root on the first beat of a plain melodic bar
+    let root = schedule(
+        &Pattern {
+            melodic: true,
+            ..Pattern::default()
+        },
+        120.0,
+    );
+    for beat in 1..7 {
+        assert_eq!(bar.playbacks[beat].samples, root.playbacks[0].samples);
+    }
+}
+
+#[test]
+fn setting_the_meter_keeps_it_playable() {
+    let mut pattern = Pattern::default();
+    pattern.set_meter(0, 2);
+    assert_eq!((pattern.numerator, pattern.subdivision), (1, 4));
+    pattern.set_meter(40, 12);
+    assert_eq!((pattern.numerator, pattern.subdivision), (32, 16));
+    assert_eq!(pattern.beat_degrees.len(), 32);
+    pattern.set_meter(7, 64);
+    assert_eq!((pattern.numerator, pattern.subdivision), (7, 32));
+    assert_eq!(pattern.beat_degrees.len(), 7);
+}
+
+#[test]
+fn tuplets_skip_muted_beats_and_keep_their_place_over_many_bars() {
+    let bars = 1000;
+    let pattern = Pattern {
+        tuplet: 5,
+        muted: vec![false, true, false],
+        ..Pattern::default()
+    };
+    let bpm = 97.0;
+    let beat = pattern.beat_duration(bpm, SAMPLE_RATE);
+    let bar = schedule(&pattern, bpm);
+    let end = (bar.bar_duration * bars as f64) as usize;
+    let mut onsets: Vec<usize> = tagged(&bar, tags::TUPLET)
+        .iter()
+        .flat_map(|p| p.onsets(0..end))
+        .collect();
+    onsets.sort_unstable();
+
+    let ideal: Vec<f64> = (0..bars * 4)
+        .filter(|i| i % 4 != 1)
+        .flat_map(|i| (1..5).map(move |j| beat * (i as f64 + j as f64 / 5.0)))
+        .collect();
+    assert_eq!(onsets.len(), ideal.len());
+    for (&onset, &ideal) in onsets.iter().zip(&ideal) {
+        assert!(
+            (onset as f64 - ideal).abs() <= 1.0,
+            "tuplet click at {} instead of {}",
+            onset,
+            ideal
+        );
+    }
+}
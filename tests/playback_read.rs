@@ -0,0 +1,144 @@
+//! Reads single playbacks buffer by buffer, checking where their samples land and when they end.
+
+use std::sync::Arc;
+
+use metronome::player::{Playback, ReadResult};
+
+/// Reads `playback` in buffers of `buffer_len` until it ends or `limit` samples were read
+fn read_until_ended(playback: &Playback, buffer_len: usize, limit: usize) -> (Vec<f32>, bool) {
+    let mut output = Vec::new();
+    while output.len() < limit {
+        let mut buffer = vec![0.0; buffer_len];
+        let result = playback.read(output.len(), &mut buffer);
+        output.extend_from_slice(&buffer);
+        if matches!(result, ReadResult::Ended) {
+            return (output, true);
+        }
+    }
+    (output, false)
+}
+
+fn sample() -> Arc<Vec<f32>> {
+    Arc::new(vec![1.0, 0.5, 0.25])
+}
+
+#[test]
+fn zero_period_plays_once() {
+    for count in [None, Some(0), Some(3)] {
+        let mut playback = Playback::new(sample()).offset(10);
+        playback.repetition_count = count;
+
+        let (output, ended) = read_until_ended(&playback, 4, 1000);
+        assert!(ended, "count {:?}: never ended", count);
+        assert!(output.len() <= 20, "count {:?}: ended late", count);
+        assert_eq!(&output[10..13], &[1.0, 0.5, 0.25]);
+        let total: f32 = output.iter().sum();
+        assert_eq!(total, 1.75, "count {:?}: played more than once", count);
+    }
+}
+
+#[test]
+fn zero_period_onsets() {
+    let playback = Playback::new(sample()).offset(10).repeat(0, None);
+    assert_eq!(playback.onsets(0..1000).collect::<Vec<_>>(), vec![10]);
+    assert_eq!(playback.end(), Some(13));
+}
+
+#[test]
+fn repeating_plays_every_period() {
+    let playback = Playback::new(sample()).offset(2).repeat(5, Some(2));
+    let (output, ended) = read_until_ended(&playback, 4, 1000);
+    assert!(ended);
+    for start in [2, 7, 12] {
+        assert_eq!(&output[start..start + 3], &[1.0, 0.5, 0.25]);
+    }
+    let total: f32 = output.iter().sum();
+    assert_eq!(total, 3.0 * 1.75);
+}
+
+#[test]
+fn gains_scale_the_mix() {
+    let loud = Playback::new(sample()).gain(1.0);
+    let quiet = Playback::new(sample()).offset(1).gain(0.5);
+    let mut buffer = vec![0.0; 5];
+    loud.read(0, &mut buffer);
+    quiet.read(0, &mut buffer);
+    assert_eq!(buffer, vec![1.0, 0.5 + 0.5, 0.25 + 0.25, 0.125, 0.0]);
+
+    let (mut left, mut right) = (vec![0.0; 3], vec![0.0; 3]);
+    Playback::new(sample())
+        .gain(0.5)
+        .read_panned(0, &mut left, &mut right);
+    assert_eq!(left, vec![0.5, 0.25, 0.125]);
+    assert_eq!(right, left);
+}
+
+/// Sum of the samples of every repetition of `playback` that has started by `time`,
+/// the slow way
+fn brute_force_sample(playback: &Playback, time: usize) -> f32 {
+    let period = playback.repetition_period;
+    (0..=time.saturating_sub(playback.start) / period)
+        .map(|rep| playback.start + rep * period)
+        .filter_map(|onset| playback.samples.get(time - onset))
+        .sum()
+}
+
+#[test]
+fn overlapping_repetitions_all_sound() {
+    // Each repetition is still playing when the next three start
+    let playback = Playback::new(Arc::new((1..=10).map(|i| i as f32).collect())).repeat(3, None);
+    let (output, _) = read_until_ended(&playback, 7, 70);
+    for (time, &sample) in output.iter().enumerate() {
+        assert_eq!(sample, brute_force_sample(&playback, time), "at {}", time);
+    }
+}
+
+#[test]
+fn reading_late_in_a_long_session_stays_cheap() {
+    let playback = Playback::new(Arc::new((1..=10).map(|i| i as f32).collect()))
+        .offset(5)
+        .repeat(3, None);
+    // Years of output at 48 kHz, a scan over the past repetitions would never finish
+    let time = 10_000_000_000_000;
+    let mut buffer = vec![0.0; 256];
+    for block in 0..1000 {
+        buffer.fill(0.0);
+        playback.read(time + block * buffer.len(), &mut buffer);
+    }
+    let last = time + 999 * buffer.len();
+    for (offset, &sample) in buffer.iter().enumerate() {
+        // The repetitions started at most 9 samples ago are still sounding
+        let newest_age = (last + offset - 5) % 3;
+        let audible: f32 = (newest_age..10)
+            .step_by(3)
+            .map(|age| (age + 1) as f32)
+            .sum();
+        assert_eq!(sample, audible, "at {}", last + offset);
+    }
+}
+
+/// Which of 1000 repetitions of a single sample sound with the chance `probability` of
+/// leaving each out
+fn sounding(probability: f32, seed: u64) -> Vec<bool> {
+    let playback = Playback::new(Arc::new(vec![1.0]))
+        .repeat(2, None)
+        .drop_randomly(probability, seed);
+    let (output, _) = read_until_ended(&playback, 64, 2000);
+    output.iter().step_by(2).map(|&s| s != 0.0).collect()
+}
+
+#[test]
+fn random_drops_follow_the_probability() {
+    assert!(sounding(0.0, 7).iter().all(|&s| s));
+    assert!(sounding(1.0, 7).iter().all(|&s| !s));
+
+    let half = sounding(0.5, 7);
+    let played = half.iter().filter(|&&s| s).count();
+    assert!((400..600).contains(&played), "{} of 1000 played", played);
+}
+
+#[test]
+fn random_drops_are_reproducible() {
+    assert_eq!(sounding(0.3, 42), sounding(0.3, 42));
+    assert_ne!(sounding(0.3, 42), sounding(0.3, 43));
+}
@@ -0,0 +1,84 @@
+//! Drops and restores the output of a player, checking the mix continues where it stopped.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use metronome::audio_sink::{AudioSink, RenderCallback};
+use metronome::player::{Playback, Player};
+
+/// A sink that can be unplugged, counting the attempts to reconnect
+struct FlakySink {
+    render: Option<RenderCallback>,
+    connected: Arc<AtomicBool>,
+    reconnects: Arc<AtomicUsize>,
+}
+
+impl FlakySink {
+    fn render(&mut self, frames: usize) -> Vec<f32> {
+        let mut data = vec![0.0; frames];
+        if self.connected.load(Ordering::Relaxed) {
+            if let Some(render) = &mut self.render {
+                render(&mut data, Duration::ZERO);
+            }
+        }
+        data
+    }
+}
+
+impl AudioSink for FlakySink {
+    fn sample_rate(&self) -> usize {
+        48000
+    }
+
+    fn num_channels(&self) -> usize {
+        1
+    }
+
+    fn play(&mut self, render: RenderCallback) -> anyhow::Result<()> {
+        self.render = Some(render);
+        self.connected.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+        self.connected.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[test]
+fn reconnecting_keeps_the_mix() {
+    let connected = Arc::new(AtomicBool::new(false));
+    let reconnects = Arc::new(AtomicUsize::new(0));
+    let mut player = Player::with_sink(FlakySink {
+        render: None,
+        connected: connected.clone(),
+        reconnects: reconnects.clone(),
+    })
+    .unwrap();
+    player.add_playbacks(vec![
+        Playback::new(Arc::new(vec![0.5; 10])).repeat(100, None)
+    ]);
+    player.set_volume_db(-6.0);
+    // Past the volume change gliding in
+    let before = player.sink_mut().render(1050);
+    assert!(player.is_connected());
+
+    connected.store(false, Ordering::Relaxed);
+    assert!(!player.is_connected());
+    player.keep_connected();
+    assert!(player.is_connected());
+    // Backing off, nothing to do while connected anyway
+    player.keep_connected();
+    assert_eq!(reconnects.load(Ordering::Relaxed), 1);
+
+    // The same clicks at the same volume, continuing from frame 1050
+    let after = player.sink_mut().render(100);
+    assert_eq!(&after[50..60], &before[1000..1010]);
+}
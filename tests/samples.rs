@@ -0,0 +1,145 @@
+//! Loads WAV files written by the crate's own encoder, and broken ones built by hand,
+//! as click samples.
+
+use std::path::PathBuf;
+
+use metronome::samples::load_wav;
+use metronome::wav::write_wav;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("metronome-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn loads_mono_at_the_same_rate() {
+    let path = temp_path("mono.wav");
+    write_wav(&path, &[0.0, 0.5, -0.5, 0.25], 48000, 1).unwrap();
+    let samples = load_wav(&path, 48000).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let expected = [0.0, 0.5, -0.5, 0.25];
+    assert_eq!(samples.len(), expected.len());
+    for (s, e) in samples.iter().zip(expected) {
+        assert!((s - e).abs() < 1e-3, "{} instead of {}", s, e);
+    }
+}
+
+#[test]
+fn downmixes_and_resamples() {
+    // Three channels at 24 kHz, averaging to a slow ramp
+    let path = temp_path("multi.wav");
+    let frames = 100;
+    let interleaved: Vec<f32> = (0..frames)
+        .flat_map(|i| {
+            let x = i as f32 / frames as f32;
+            [x, 0.0, 2.0 * x].map(|s| s * 0.5)
+        })
+        .collect();
+    write_wav(&path, &interleaved, 24000, 3).unwrap();
+    let samples = load_wav(&path, 48000).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(samples.len(), 2 * frames);
+    for (i, s) in samples.iter().enumerate().take(2 * frames - 2) {
+        let expected = i as f32 / (2 * frames) as f32 * 0.5;
+        assert!((s - expected).abs() < 1e-3, "sample {}: {}", i, s);
+    }
+}
+
+#[test]
+fn rejects_other_files() {
+    let path = temp_path("not-audio.wav");
+    std::fs::write(&path, b"definitely not RIFF").unwrap();
+    let result = load_wav(&path, 48000);
+    std::fs::remove_file(&path).unwrap();
+    assert!(result.is_err());
+
+    assert!(load_wav(&temp_path("click.mp3"), 48000).is_err());
+}
+
+/// A RIFF chunk with its declared length, padded to an even length
+fn chunk(id: &[u8; 4], declared_len: u32, data: &[u8]) -> Vec<u8> {
+    let mut chunk = [id.as_slice(), &declared_len.to_le_bytes(), data].concat();
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+/// A mono 16-bit `fmt ` chunk at 48 kHz, with `extra` bytes after the standard fields
+fn fmt_chunk(extra: &[u8]) -> Vec<u8> {
+    let mut data = vec![1, 0, 1, 0];
+    data.extend(48000u32.to_le_bytes());
+    data.extend((48000u32 * 2).to_le_bytes());
+    data.extend([2, 0, 16, 0]);
+    data.extend(extra);
+    chunk(b"fmt ", data.len() as u32, &data)
+}
+
+/// 16-bit samples at a quarter of full scale
+fn quarter_samples(count: usize) -> Vec<u8> {
+    (0..count).flat_map(|_| 8192i16.to_le_bytes()).collect()
+}
+
+fn load_bytes(name: &str, chunks: &[Vec<u8>]) -> anyhow::Result<Vec<f32>> {
+    let body = chunks.concat();
+    let riff = [
+        b"RIFF".as_slice(),
+        &(body.len() as u32 + 4).to_le_bytes(),
+        b"WAVE",
+        &body,
+    ]
+    .concat();
+    let path = temp_path(name);
+    std::fs::write(&path, riff).unwrap();
+    let result = load_wav(&path, 48000);
+    std::fs::remove_file(&path).unwrap();
+    result
+}
+
+#[test]
+fn skips_odd_sized_and_extended_chunks() {
+    let samples = load_bytes(
+        "odd-chunks.wav",
+        &[
+            // cbSize and a byte more, an odd length with a pad byte
+            fmt_chunk(&[1, 0, 7]),
+            chunk(b"LIST", 3, b"abc"),
+            chunk(b"data", 8, &quarter_samples(4)),
+        ],
+    )
+    .unwrap();
+    assert_eq!(samples, [0.25; 4]);
+}
+
+#[test]
+fn plays_what_a_truncated_data_chunk_has() {
+    // Declares 1000 samples, has 3 and a half and no pad byte
+    let data = [
+        b"data".as_slice(),
+        &2000u32.to_le_bytes(),
+        &quarter_samples(3),
+        &[0],
+    ]
+    .concat();
+    let samples = load_bytes("truncated-data.wav", &[fmt_chunk(&[]), data]).unwrap();
+    assert_eq!(samples, [0.25; 3]);
+}
+
+#[test]
+fn rejects_truncated_headers_without_trusting_their_lengths() {
+    // A format chunk claiming 4 GB
+    let huge_fmt = chunk(b"fmt ", u32::MAX, &[1, 0, 1, 0]);
+    assert!(load_bytes("huge-fmt.wav", &[huge_fmt]).is_err());
+
+    let mut cut_fmt = fmt_chunk(&[]);
+    cut_fmt.truncate(14);
+    assert!(load_bytes("cut-fmt.wav", &[cut_fmt]).is_err());
+
+    // A chunk that runs past the end and no data chunk
+    let runaway = chunk(b"LIST", 1 << 30, b"ab");
+    assert!(load_bytes("no-data.wav", &[fmt_chunk(&[]), runaway]).is_err());
+
+    let data_only = chunk(b"data", 8, &quarter_samples(4));
+    assert!(load_bytes("no-fmt.wav", &[data_only]).is_err());
+}
@@ -0,0 +1,98 @@
+//! Tempo measured from synthetic taps
+
+use metronome::tap_tempo::TapTempo;
+use std::time::{Duration, Instant};
+
+/// Taps at `start` and then after each of `intervals` seconds, returning the last measurement
+fn tap_intervals(taps: &mut TapTempo, start: Instant, intervals: &[f64]) -> (Instant, Option<f32>) {
+    let mut now = start;
+    let mut bpm = taps.tap_at(now);
+    for &interval in intervals {
+        now += Duration::from_secs_f64(interval);
+        bpm = taps.tap_at(now);
+    }
+    (now, bpm)
+}
+
+#[test]
+fn steady_taps_with_jitter_measure_the_tempo() {
+    let mut taps = TapTempo::new();
+    // Alternately early and late by up to 15 ms around 120 BPM
+    let jitter = [0.012, -0.015, 0.004, -0.009, 0.015, -0.007, 0.010, -0.010];
+    let intervals: Vec<f64> = jitter.iter().map(|j| 0.5 + j).collect();
+    let start = Instant::now();
+    let (_, bpm) = tap_intervals(&mut taps, start, &intervals);
+    let bpm = bpm.unwrap();
+    assert!((bpm - 120.0).abs() < 1.0, "{}", bpm);
+}
+
+#[test]
+fn the_estimate_follows_a_speed_up() {
+    let mut taps = TapTempo::new();
+    let start = Instant::now();
+    let (now, _) = tap_intervals(&mut taps, start, &[0.6; 8]);
+    let (_, bpm) = tap_intervals(
+        &mut taps,
+        now + Duration::from_secs_f64(60.0 / 110.0),
+        &[60.0 / 110.0; 7],
+    );
+    let bpm = bpm.unwrap();
+    assert!((bpm - 110.0).abs() < 1.0, "{}", bpm);
+}
+
+#[test]
+fn a_jump_in_tempo_starts_over() {
+    let mut taps = TapTempo::new();
+    let start = Instant::now();
+    let (now, bpm) = tap_intervals(&mut taps, start, &[0.5; 4]);
+    assert!((bpm.unwrap() - 120.0).abs() < 1.0);
+
+    // Twice as slow as before is taken as a new tempo, not averaged in
+    let now = now + Duration::from_secs_f64(1.2);
+    assert_eq!(taps.tap_at(now), None);
+    let (_, bpm) = tap_intervals(&mut taps, now + Duration::from_secs_f64(1.2), &[1.2; 2]);
+    let bpm = bpm.unwrap();
+    assert!((bpm - 50.0).abs() < 1.0, "{}", bpm);
+}
+
+#[test]
+fn a_long_pause_starts_over() {
+    let mut taps = TapTempo::new();
+    let start = Instant::now();
+    let (now, bpm) = tap_intervals(&mut taps, start, &[1.5; 3]);
+    assert!((bpm.unwrap() - 40.0).abs() < 1.0);
+
+    // Less than twice the last interval, but long enough to have walked away
+    let now = now + Duration::from_secs_f64(2.5);
+    assert_eq!(taps.tap_at(now), None);
+    let (_, bpm) = tap_intervals(&mut taps, now + Duration::from_secs_f64(0.75), &[0.75; 2]);
+    let bpm = bpm.unwrap();
+    assert!((bpm - 80.0).abs() < 1.0, "{}", bpm);
+}
+
+/// Whether a tap `ratio` times as long as the steady 0.5 s before it is kept
+fn keeps_interval(ratio: f64) -> bool {
+    let mut taps = TapTempo::new();
+    let (now, _) = tap_intervals(&mut taps, Instant::now(), &[0.5; 3]);
+    taps.tap_at(now + Duration::from_secs_f64(0.5 * ratio))
+        .is_some()
+}
+
+#[test]
+fn intervals_up_to_twice_as_long_or_short_are_kept() {
+    assert!(keeps_interval(1.0));
+    assert!(keeps_interval(2.0));
+    assert!(keeps_interval(0.5));
+    assert!(!keeps_interval(2.01));
+    assert!(!keeps_interval(0.49));
+}
+
+#[test]
+fn the_first_tap_only_starts_measuring() {
+    let mut taps = TapTempo::new();
+    let start = Instant::now();
+    assert_eq!(taps.tap_at(start), None);
+    assert_eq!(taps.latest(), None);
+    let bpm = taps.tap_at(start + Duration::from_millis(500)).unwrap();
+    assert!((bpm - 120.0).abs() < 1e-3, "{}", bpm);
+}
@@ -0,0 +1,64 @@
+//! Checks that loops of a non-integer length keep the tempo over many bars.
+
+use std::sync::Arc;
+
+use metronome::audio_sink::NullSink;
+use metronome::player::{Playback, Player};
+
+#[test]
+fn no_drift_over_1000_bars() {
+    let bars = 1000;
+    for (bpm, sample_rate) in [(120.0, 44100), (97.0, 48000), (133.0, 44100), (61.0, 8000)] {
+        // A 4/4 bar, in exact samples
+        let bar = sample_rate as f64 * 60.0 * 4.0 / bpm;
+        let playback = Playback::new(Arc::new(vec![1.0])).repeat_exact(bar, None);
+        let end = (bar * (bars + 1) as f64) as usize;
+
+        let onsets: Vec<usize> = playback.onsets(0..end).collect();
+        assert!(
+            onsets.len() > bars,
+            "{} bpm: only {} onsets",
+            bpm,
+            onsets.len()
+        );
+        for (i, &onset) in onsets.iter().take(bars + 1).enumerate() {
+            let ideal = i as f64 * bar;
+            assert!(
+                (onset as f64 - ideal).abs() <= 1.0,
+                "{} bpm at {} Hz: bar {} starts at {} instead of {}",
+                bpm,
+                sample_rate,
+                i,
+                onset,
+                ideal
+            );
+        }
+    }
+}
+
+#[test]
+fn switching_at_the_next_bar_after_1000_bars_lands_on_a_downbeat() {
+    let sample_rate = 8000;
+    // 135 BPM in 4/4, 14222.2 samples per bar
+    let bar = sample_rate as f64 * 60.0 * 4.0 / 135.0;
+    let mut player = Player::with_sink(NullSink::new(sample_rate, 1)).unwrap();
+    player.bypass_limiter(true);
+    let click = |level: f32| Playback::new(Arc::new(vec![level])).repeat_exact(bar, None);
+    player.replace_playbacks(vec![click(0.25)], bar, false);
+
+    let rendered = (bar * 1000.5) as usize;
+    player.sink_mut().render(rendered);
+    player.replace_playbacks(vec![click(0.5)], bar, true);
+    let output = player.sink_mut().render(2 * bar as usize);
+
+    let switch = rendered + output.iter().position(|&s| s == 0.5).unwrap();
+    let ideal = 1001.0 * bar;
+    assert!(
+        (switch as f64 - ideal).abs() <= 1.0,
+        "switched at {} instead of {}",
+        switch,
+        ideal
+    );
+    // The old pattern's last downbeat is still the one before, nothing in between
+    assert!(output.iter().all(|&s| s == 0.0 || s == 0.5));
+}
@@ -0,0 +1,181 @@
+//! Pauses, resumes, clears and gates a player rendering without a device.
+
+use std::sync::Arc;
+
+use metronome::audio_sink::NullSink;
+use metronome::pattern::Schedule;
+use metronome::player::{fade_out_gains, BarGate, Playback, Player};
+
+#[test]
+fn pausing_freezes_the_position_and_fades() {
+    let mut player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    player.add_playbacks(vec![
+        Playback::new(Arc::new(vec![0.5; 100])).repeat(100, None)
+    ]);
+    player.sink_mut().render(1000);
+
+    player.set_playing(false);
+    let fade = player.sink_mut().render(1000);
+    assert!(fade[0] > 0.0, "cut off instead of fading");
+    // Each step of the fade is small, so there is no audible click
+    for pair in fade.windows(2) {
+        assert!((pair[0] - pair[1]).abs() < 0.01);
+    }
+    assert_eq!(fade[999], 0.0);
+
+    // The position is the one at the start of the last render
+    let silence = player.sink_mut().render(1000);
+    assert!(silence.iter().all(|&s| s == 0.0));
+    let paused_at = player.position_samples();
+    assert_eq!(paused_at, 2000);
+    player.sink_mut().render(1000);
+    assert_eq!(player.position_samples(), paused_at);
+
+    player.set_playing(true);
+    let resumed = player.sink_mut().render(1000);
+    assert!(resumed[0] < 0.01, "resumed without a fade");
+    assert!(resumed[999] > 0.4);
+    player.sink_mut().render(1000);
+    assert_eq!(player.position_samples(), paused_at + 1000);
+    assert_eq!(player.position_seconds(), 3000.0 / 48000.0);
+}
+
+#[test]
+fn beat_events_report_each_repetition() {
+    let mut player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    player.add_playbacks(vec![
+        Playback::new(Arc::new(vec![0.5; 10])).repeat(300, None),
+        // Sustained tones aren't clicks and don't report anything
+        Playback::new(Arc::new(vec![0.1; 10])).loop_sustain(),
+    ]);
+    player.sink_mut().render(1000);
+
+    let events: Vec<_> = player.beat_events().try_iter().collect();
+    let times: Vec<_> = events.iter().map(|e| e.sample_time).collect();
+    assert_eq!(times, vec![0, 300, 600, 900]);
+    assert!(events.iter().all(|e| e.playback_index == 0));
+}
+
+#[test]
+fn the_fade_out_ramp_falls_to_silence() {
+    let gains: Vec<f32> = fade_out_gains(256, 0, 300).collect();
+    assert_eq!(gains[0], 1.0);
+    assert!(gains.iter().all(|&g| (0.0..=1.0).contains(&g)));
+    assert!(gains.windows(2).all(|pair| pair[1] <= pair[0]));
+    assert!(gains[256..].iter().all(|&g| g == 0.0));
+
+    // Picking up in the middle continues the same ramp
+    let rest: Vec<f32> = fade_out_gains(256, 100, 200).collect();
+    assert_eq!(rest, gains[100..]);
+}
+
+#[test]
+fn clearing_fades_out_what_is_sounding() {
+    let mut player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    player.add_playbacks(vec![
+        Playback::new(Arc::new(vec![0.5; 1000])).repeat(1000, None)
+    ]);
+    player.sink_mut().render(500);
+
+    player.clear_playbacks();
+    let fade = player.sink_mut().render(500);
+    assert!(fade[0] > 0.4, "cut off instead of fading");
+    assert!(fade.windows(2).all(|pair| pair[1] <= pair[0]));
+    assert!(fade[300..].iter().all(|&s| s == 0.0));
+    // The next repetition never starts
+    let after = player.sink_mut().render(1000);
+    assert!(after.iter().all(|&s| s == 0.0));
+}
+
+#[test]
+fn the_bar_gate_alternates_played_and_silent_bars() {
+    let gate = BarGate {
+        play_bars: 2,
+        mute_bars: 1,
+    };
+    let muted: Vec<bool> = (0..7).map(|bar| gate.is_muted(bar)).collect();
+    assert_eq!(muted, [false, false, true, false, false, true, false]);
+    // Without any bars to mute it always plays
+    let always = BarGate {
+        play_bars: 4,
+        mute_bars: 0,
+    };
+    assert!((0..100).all(|bar| !always.is_muted(bar)));
+}
+
+#[test]
+fn gated_bars_are_silent_and_the_clicks_return_on_the_downbeat() {
+    let mut player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    player.bypass_limiter(true);
+    player.set_bar_gate(Some(BarGate {
+        play_bars: 1,
+        mute_bars: 1,
+    }));
+    // Bars of 100.5 samples, so the bar lines land between samples half of the time
+    let constant = Playback::new(Arc::new(vec![0.5; 10])).repeat(10, None);
+    player.replace_playbacks(vec![constant], 100.5, false);
+    let output = player.sink_mut().render(402);
+
+    for (time, &sample) in output.iter().enumerate() {
+        let bar = [0, 101, 201, 302, 402]
+            .iter()
+            .filter(|&&s| s <= time)
+            .count()
+            - 1;
+        let expected = if bar % 2 == 1 { 0.0 } else { 0.5 };
+        assert_eq!(sample, expected, "at {}", time);
+    }
+}
+
+#[test]
+fn the_bar_gate_starts_after_the_count_in() {
+    let mut player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    player.bypass_limiter(true);
+    player.set_bar_gate(Some(BarGate {
+        play_bars: 1,
+        mute_bars: 1,
+    }));
+    // Two bars of 100 samples counting in, then the pattern
+    let constant = Playback::new(Arc::new(vec![0.5; 10])).repeat(10, None);
+    player.replace_playbacks_with_count_in(vec![constant], vec![100.0], false, None, 200);
+    let output = player.sink_mut().render(600);
+
+    for (time, &sample) in output.iter().enumerate() {
+        // The count-in plays in full, the pattern's first bar plays and its second is gated
+        let muted = time >= 200 && (time - 200) / 100 % 2 == 1;
+        let expected = if muted { 0.0 } else { 0.5 };
+        assert_eq!(sample, expected, "at {}", time);
+    }
+}
+
+#[test]
+fn the_bar_gate_counts_the_bars_of_alternating_patterns() {
+    let mut player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    player.bypass_limiter(true);
+    player.set_bar_gate(Some(BarGate {
+        play_bars: 1,
+        mute_bars: 1,
+    }));
+    // A bar of 100 samples alternating with one of 60, so A always plays and B is gated
+    let bar = |len: f64| Schedule {
+        playbacks: vec![Playback::new(Arc::new(vec![0.5; 10])).repeat(10, None)],
+        bar_duration: len,
+        bar_lengths: vec![len],
+    };
+    let schedule = Schedule::alternate(&bar(100.0), &bar(60.0), 1);
+    assert_eq!(schedule.bar_lengths, [100.0, 60.0]);
+    player.replace_playbacks_with_count_in(
+        schedule.playbacks,
+        schedule.bar_lengths,
+        false,
+        None,
+        0,
+    );
+    let output = player.sink_mut().render(480);
+
+    for (time, &sample) in output.iter().enumerate() {
+        let muted = time % 160 >= 100;
+        let expected = if muted { 0.0 } else { 0.5 };
+        assert_eq!(sample, expected, "at {}", time);
+    }
+}
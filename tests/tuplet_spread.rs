@@ -0,0 +1,38 @@
+//! Renders spread tuplets and checks mono output gets them unpanned.
+
+use metronome::audio_sink::NullSink;
+use metronome::click::{ClickShape, Sounds, Timbre};
+use metronome::pattern::Pattern;
+use metronome::player::Player;
+
+const SAMPLE_RATE: usize = 48000;
+
+/// Two bars of quintuplets at 120 BPM, spread `tuplet_spread` apart, on `channels` channels
+fn render(channels: usize, tuplet_spread: f32) -> Vec<f32> {
+    let pattern = Pattern {
+        tuplet: 5,
+        tuplet_spread,
+        ..Pattern::default()
+    };
+    let mut sounds = Sounds::new(SAMPLE_RATE, ClickShape::default(), [Timbre::Tone; 3]);
+    let schedule = pattern.schedule(120.0, SAMPLE_RATE, &mut sounds);
+
+    let mut player = Player::with_sink(NullSink::new(SAMPLE_RATE, channels)).unwrap();
+    // Linear, so the outputs can be compared sample by sample
+    player.bypass_limiter(true);
+    player.replace_playbacks(schedule.playbacks, schedule.bar_duration, false);
+    player.sink_mut().render(2 * schedule.bar_duration as usize)
+}
+
+#[test]
+fn mono_output_ignores_the_spread() {
+    let mono = render(1, 0.0);
+    assert!(mono.iter().any(|&s| s != 0.0));
+    assert_eq!(render(1, 1.0), mono);
+    assert_eq!(render(1, 0.5), mono);
+}
+
+#[test]
+fn stereo_output_spreads_the_tuplets() {
+    assert_ne!(render(2, 1.0), render(2, 0.0));
+}
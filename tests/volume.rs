@@ -0,0 +1,50 @@
+//! Renders a constant signal through the player while changing the volume.
+
+use std::sync::Arc;
+
+use metronome::audio_sink::NullSink;
+use metronome::player::{Playback, Player};
+
+fn constant_player() -> Player<NullSink> {
+    let mut player = Player::with_sink(NullSink::new(48000, 1)).unwrap();
+    // Linear, so the output is exactly the applied gain times the signal
+    player.bypass_limiter(true);
+    player.add_playbacks(vec![Playback::new(Arc::new(vec![0.5])).loop_sustain()]);
+    player.sink_mut().render(100);
+    player
+}
+
+#[test]
+fn volume_changes_glide() {
+    let mut player = constant_player();
+    player.set_volume_db(-20.0);
+    // 10 ms at 48 kHz
+    let output = player.sink_mut().render(1000);
+
+    let target = 0.5 * 10f32.powf(-20.0 / 20.0);
+    assert!(output[0] > 0.49, "jumped to {}", output[0]);
+    for pair in output.windows(2) {
+        assert!(pair[1] <= pair[0], "not monotonic");
+        assert!(
+            pair[0] - pair[1] < 0.001,
+            "stepped by {}",
+            pair[0] - pair[1]
+        );
+    }
+    for sample in &output[480..] {
+        assert!(
+            (sample - target).abs() < 1e-4,
+            "{} instead of {}",
+            sample,
+            target
+        );
+    }
+}
+
+#[test]
+fn unchanged_volume_stays_put() {
+    let mut player = constant_player();
+    player.set_volume_db(0.0);
+    let output = player.sink_mut().render(1000);
+    assert!(output.iter().all(|&s| s == 0.5));
+}
@@ -0,0 +1,139 @@
+//! Generates clicks of every waveform and shape and checks their level, envelope and phase.
+
+use std::time::Duration;
+
+use metronome::click::{
+    generate_click, generate_click_f64, ClickShape, Envelope, Waveform, MIN_CLICK_LENGTH,
+};
+
+const SAMPLE_RATE: usize = 48000;
+
+fn click(waveform: Waveform, gain: f32) -> Vec<f32> {
+    let shape = ClickShape {
+        waveform,
+        ..ClickShape::default()
+    };
+    generate_click(SAMPLE_RATE, 440.0, gain, shape)
+}
+
+fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+}
+
+#[test]
+fn every_waveform_stays_within_the_gain_and_decays() {
+    for waveform in Waveform::ALL {
+        let samples = click(waveform, 0.5);
+        assert_eq!(samples.len(), SAMPLE_RATE / 10);
+        assert!(samples.iter().all(|s| s.is_finite()), "{:?}", waveform);
+
+        let start = peak(&samples[..480]);
+        let end = peak(&samples[samples.len() - 480..]);
+        assert!(
+            start <= 0.5 && start > 0.2,
+            "{:?} starts at {}",
+            waveform,
+            start
+        );
+        assert!(end < start / 10.0, "{:?} ends at {}", waveform, end);
+    }
+}
+
+#[test]
+fn square_and_triangle_follow_the_sine_cycle() {
+    let sine = click(Waveform::Sine, 1.0);
+    // Away from the zero crossings, where rounding could flip the sign
+    let cycle = SAMPLE_RATE as f32 / 440.0;
+    let checked = (0..sine.len()).filter(|&i| {
+        let position = (i as f32 / cycle).fract();
+        (position - 0.25).abs() < 0.2 || (position - 0.75).abs() < 0.2
+    });
+    let square = click(Waveform::Square, 1.0);
+    let triangle = click(Waveform::Triangle, 1.0);
+    for i in checked {
+        assert_eq!(square[i].signum(), sine[i].signum(), "square at {}", i);
+        assert_eq!(triangle[i].signum(), sine[i].signum(), "triangle at {}", i);
+    }
+    // Full level at the top of the first cycle, like the sine
+    let top = (cycle / 4.0).round() as usize;
+    assert!((triangle[top] - sine[top]).abs() < 0.01);
+}
+
+#[test]
+fn the_noise_burst_is_the_same_every_time() {
+    let noise = click(Waveform::Noise, 1.0);
+    assert_eq!(noise, click(Waveform::Noise, 1.0));
+    assert_ne!(noise, click(Waveform::Sine, 1.0));
+}
+
+#[test]
+fn the_length_never_drops_below_the_minimum() {
+    for length in [Duration::ZERO, Duration::from_micros(100)] {
+        let shape = ClickShape {
+            length,
+            ..ClickShape::default()
+        };
+        let samples = generate_click(SAMPLE_RATE, 440.0, 1.0, shape);
+        let min_len = (MIN_CLICK_LENGTH.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+        assert_eq!(samples.len(), min_len);
+    }
+}
+
+#[test]
+fn the_envelope_ends_the_set_decay_below_the_start() {
+    for decay_db in [20.0f32, 60.0] {
+        let shape = ClickShape {
+            length: Duration::from_millis(20),
+            decay_db,
+            // Full level on every sample, so each one shows the envelope
+            waveform: Waveform::Square,
+            ..ClickShape::default()
+        };
+        let samples = generate_click(SAMPLE_RATE, 1000.0, 1.0, shape);
+        let end = samples.last().unwrap().abs();
+        let expected = 10f32.powf(-decay_db / 20.0);
+        assert!(
+            (end / expected - 1.0).abs() < 0.05,
+            "{} dB ends at {}",
+            decay_db,
+            end
+        );
+    }
+}
+
+/// 60 dB below full scale, the f32 phase drifts furthest on the loud end of long clicks
+const MAX_ERROR: f32 = 1e-3;
+
+#[test]
+fn the_fast_path_stays_close_to_the_f64_reference() {
+    // The square's jumps can land a sample apart, the other waveforms are compared
+    let waveforms = [Waveform::Sine, Waveform::Triangle, Waveform::Noise];
+    for (waveform, envelope) in waveforms
+        .into_iter()
+        .flat_map(|w| Envelope::ALL.map(|e| (w, e)))
+    {
+        for freq in [440.0, 1318.5, 4000.0] {
+            let shape = ClickShape {
+                length: Duration::from_millis(200),
+                envelope,
+                waveform,
+                ..ClickShape::default()
+            };
+            let fast = generate_click(SAMPLE_RATE, freq, 1.0, shape);
+            let reference = generate_click_f64(SAMPLE_RATE, freq, 1.0, shape);
+            assert_eq!(fast.len(), reference.len());
+            let error = fast
+                .iter()
+                .zip(&reference)
+                .fold(0.0f32, |max, (a, b)| max.max((a - b).abs()));
+            assert!(
+                error < MAX_ERROR,
+                "{:?} {:?} {} Hz is off by {}",
+                waveform,
+                envelope,
+                freq,
+                error
+            );
+        }
+    }
+}